@@ -0,0 +1,289 @@
+//! Who may listen to a share.
+//!
+//! Every listener is identified by its endpoint id (the public half of its
+//! [`crate::identity`] key), which QUIC authenticates during the connection
+//! handshake. The sharer admits listeners according to an [`AccessPolicy`]
+//! and can remove a listener at any time; a removed listener is refused for
+//! the rest of the session, so it can't simply reconnect.
+
+use crate::protocol::CLOSE_REMOVED;
+use iroh::EndpointId;
+use iroh::endpoint::{Connection, VarInt};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+
+/// How long a listener waits for the sharer to approve it.
+const APPROVAL_TIMEOUT: Duration = Duration::from_secs(60);
+/// Approval requests queued for the UI before new ones are refused.
+const MAX_PENDING_REQUESTS: usize = 16;
+
+/// Which listeners a share admits.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum AccessPolicy {
+    /// Anyone holding the ticket.
+    #[default]
+    Open,
+    /// Only these endpoints.
+    AllowList { endpoints: Vec<EndpointId> },
+    /// Ask the sharer about each new listener; once approved, a listener can
+    /// reconnect without asking again for the rest of the session.
+    ApproveOnConnect,
+}
+
+/// Enforces the access policy of one share session.
+#[derive(Debug)]
+pub struct AccessControl {
+    state: Mutex<AccessState>,
+    /// Endpoints waiting for the sharer's decision, for the UI to ask about.
+    requests: mpsc::Sender<EndpointId>,
+}
+
+#[derive(Debug)]
+struct AccessState {
+    policy: AccessPolicy,
+    approved: HashSet<EndpointId>,
+    removed: HashSet<EndpointId>,
+    pending: HashMap<EndpointId, oneshot::Sender<bool>>,
+    /// Admitted connections, by [`Connection::stable_id`].
+    connections: HashMap<usize, Connection>,
+}
+
+impl AccessControl {
+    /// Access control under `policy`. The receiver yields listeners awaiting
+    /// approval; answer each with [`AccessControl::decide`].
+    pub fn new(policy: AccessPolicy) -> (Self, mpsc::Receiver<EndpointId>) {
+        let (requests, requests_rx) = mpsc::channel(MAX_PENDING_REQUESTS);
+        let control = Self {
+            state: Mutex::new(AccessState {
+                policy,
+                approved: HashSet::new(),
+                removed: HashSet::new(),
+                pending: HashMap::new(),
+                connections: HashMap::new(),
+            }),
+            requests,
+        };
+        (control, requests_rx)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, AccessState> {
+        self.state.lock().expect("access state poisoned")
+    }
+
+    /// Change the policy. Applies to listeners connecting from now on.
+    pub fn set_policy(&self, policy: AccessPolicy) {
+        self.lock().policy = policy;
+    }
+
+    /// Whether the policy decides by who is listening, rather than letting
+    /// in anyone who can reach us.
+    pub fn checks_identity(&self) -> bool {
+        self.lock().policy != AccessPolicy::Open
+    }
+
+    /// Decide whether `id` may listen, waiting for the sharer's approval if
+    /// the policy asks for it. Returns the reason for refusing, if refused.
+    pub async fn admit(&self, id: EndpointId) -> Result<(), String> {
+        let decision = {
+            let mut state = self.lock();
+            if state.removed.contains(&id) {
+                return Err("removed by the sharer".into());
+            }
+            match &state.policy {
+                AccessPolicy::Open => return Ok(()),
+                AccessPolicy::AllowList { endpoints } => {
+                    return if endpoints.contains(&id) {
+                        Ok(())
+                    } else {
+                        Err("not on the sharer's allow-list".into())
+                    };
+                }
+                AccessPolicy::ApproveOnConnect if state.approved.contains(&id) => return Ok(()),
+                AccessPolicy::ApproveOnConnect => {
+                    let (tx, rx) = oneshot::channel();
+                    state.pending.insert(id, tx);
+                    rx
+                }
+            }
+        };
+
+        if self.requests.try_send(id).is_err() {
+            self.lock().pending.remove(&id);
+            return Err("the sharer is not taking approval requests".into());
+        }
+        let result = tokio::time::timeout(APPROVAL_TIMEOUT, decision).await;
+
+        let mut state = self.lock();
+        match result {
+            Ok(Ok(true)) => {
+                state.approved.insert(id);
+                Ok(())
+            }
+            Ok(Ok(false)) => Err("declined by the sharer".into()),
+            // Superseded by a newer connection attempt from the same listener
+            Ok(Err(_)) => Err("declined by the sharer".into()),
+            Err(_) => {
+                // Forget the request unless a newer one has replaced it
+                if state.pending.get(&id).is_some_and(|tx| tx.is_closed()) {
+                    state.pending.remove(&id);
+                }
+                Err("the sharer did not answer in time".into())
+            }
+        }
+    }
+
+    /// Answer a pending approval request. Returns false if `id` wasn't
+    /// waiting (e.g. it gave up).
+    pub fn decide(&self, id: EndpointId, approve: bool) -> bool {
+        match self.lock().pending.remove(&id) {
+            Some(tx) => tx.send(approve).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Track an admitted listener's connection so it can be removed.
+    pub fn register(&self, connection: &Connection) {
+        self.lock()
+            .connections
+            .insert(connection.stable_id(), connection.clone());
+    }
+
+    /// Stop tracking a connection that has ended.
+    pub fn unregister(&self, connection: &Connection) {
+        self.lock().connections.remove(&connection.stable_id());
+    }
+
+    /// Disconnect `id` and refuse it for the rest of the session. Returns
+    /// whether it was connected.
+    pub fn remove(&self, id: EndpointId) -> bool {
+        let mut state = self.lock();
+        state.removed.insert(id);
+        state.approved.remove(&id);
+        if let Some(tx) = state.pending.remove(&id) {
+            let _ = tx.send(false);
+        }
+        let mut found = false;
+        for connection in state.connections.values() {
+            if connection.remote_id() == id {
+                connection.close(VarInt::from_u32(CLOSE_REMOVED), b"removed by the sharer");
+                found = true;
+            }
+        }
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::test_endpoint_id;
+
+    #[tokio::test]
+    async fn open_admits_everyone() {
+        let (access, _requests) = AccessControl::new(AccessPolicy::Open);
+        assert!(access.admit(test_endpoint_id()).await.is_ok());
+    }
+
+    #[test]
+    fn only_open_policy_ignores_identity() {
+        let (access, _requests) = AccessControl::new(AccessPolicy::Open);
+        assert!(!access.checks_identity());
+        access.set_policy(AccessPolicy::ApproveOnConnect);
+        assert!(access.checks_identity());
+    }
+
+    #[tokio::test]
+    async fn allow_list_admits_only_listed() {
+        let friend = test_endpoint_id();
+        let (access, _requests) = AccessControl::new(AccessPolicy::AllowList {
+            endpoints: vec![friend],
+        });
+        assert!(access.admit(friend).await.is_ok());
+        let reason = access.admit(test_endpoint_id()).await.unwrap_err();
+        assert!(reason.contains("allow-list"), "{reason}");
+    }
+
+    #[tokio::test]
+    async fn approval_waits_for_decision_and_is_remembered() {
+        let (access, mut requests) = AccessControl::new(AccessPolicy::ApproveOnConnect);
+        let access = std::sync::Arc::new(access);
+        let id = test_endpoint_id();
+
+        let sharer = {
+            let access = access.clone();
+            tokio::spawn(async move {
+                let pending = requests.recv().await.unwrap();
+                assert!(access.decide(pending, true));
+                requests
+            })
+        };
+        assert!(access.admit(id).await.is_ok());
+        let _requests = sharer.await.unwrap();
+
+        // Reconnecting doesn't ask again.
+        assert!(access.admit(id).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn declined_listener_is_refused() {
+        let (access, mut requests) = AccessControl::new(AccessPolicy::ApproveOnConnect);
+        let access = std::sync::Arc::new(access);
+
+        let sharer = {
+            let access = access.clone();
+            tokio::spawn(async move {
+                let pending = requests.recv().await.unwrap();
+                access.decide(pending, false);
+            })
+        };
+        let reason = access.admit(test_endpoint_id()).await.unwrap_err();
+        assert!(reason.contains("declined"), "{reason}");
+        sharer.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn approval_times_out() {
+        tokio::time::pause();
+        let (access, _requests) = AccessControl::new(AccessPolicy::ApproveOnConnect);
+        let id = test_endpoint_id();
+        let reason = access.admit(id).await.unwrap_err();
+        assert!(reason.contains("in time"), "{reason}");
+        assert!(!access.decide(id, true), "stale request was kept");
+    }
+
+    #[tokio::test]
+    async fn nobody_to_ask_means_refused() {
+        let (access, requests) = AccessControl::new(AccessPolicy::ApproveOnConnect);
+        drop(requests);
+        assert!(access.admit(test_endpoint_id()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn removed_listener_stays_out() {
+        let id = test_endpoint_id();
+        let (access, _requests) = AccessControl::new(AccessPolicy::Open);
+        assert!(!access.remove(id), "wasn't connected");
+        let reason = access.admit(id).await.unwrap_err();
+        assert!(reason.contains("removed"), "{reason}");
+        assert!(access.admit(test_endpoint_id()).await.is_ok());
+    }
+
+    #[test]
+    fn policy_json_shape() {
+        let id = test_endpoint_id();
+        let json = format!(r#"{{"mode":"allow_list","endpoints":["{id}"]}}"#);
+        let policy: AccessPolicy = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            policy,
+            AccessPolicy::AllowList {
+                endpoints: vec![id]
+            }
+        );
+        let open: AccessPolicy = serde_json::from_str(r#"{"mode":"open"}"#).unwrap();
+        assert_eq!(open, AccessPolicy::Open);
+    }
+}
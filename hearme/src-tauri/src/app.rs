@@ -3,49 +3,190 @@
 //! This is the glue that connects the UI to the audio capture, codec,
 //! transport, and playback modules.
 
-use crate::capture::{self, AudioSource};
-use crate::codec;
+use crate::access::AccessPolicy;
+use crate::capture::mix::{self, Captured, SourceMixer};
+use crate::capture::{
+    self, AudioSource, GateMode, MicGate, PcmFrame, SAMPLES_PER_FRAME, SourceKind, SourceLayout,
+};
+use crate::codec::{self, EncoderConfig};
+use crate::discovery::{LanBrowser, LanSharer};
+use crate::identity;
+use crate::jitter::{JitterBuffer, Playout};
+use crate::mixer::{MAX_GAIN, Mixer, MixerInput, SourceMix};
+use crate::network::NetworkConfig;
 use crate::playback::PlaybackStream;
-use crate::transport::{ListenSession, ShareSession, Ticket};
+use crate::protocol::{AudioFrame, MAX_TRACKS, StreamHeader, TrackInfo};
+use crate::roster::{RosterEvent, RosterListener};
+use crate::settings::Settings;
+use crate::stats::ListenStats;
+use crate::ticket::{LISTEN_URI_PREFIX, Ticket, TicketLedger, TicketOptions};
+use crate::transport::{
+    FrameClock, ListenEnd, ListenEvent, ListenSession, ListenTarget, ShareSession, Talkback,
+};
+use iroh::{EndpointId, SecretKey};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::collections::btree_map::Entry;
+use std::path::PathBuf;
 use std::sync::Arc;
-use tauri::{AppHandle, Emitter, State};
-use tokio::sync::Mutex;
-use tracing::{error, info};
+use std::time::{Duration, Instant, SystemTime};
+use tauri::{AppHandle, Emitter, Manager, State};
+#[cfg(desktop)]
+use tauri_plugin_global_shortcut::GlobalShortcutExt;
+use tokio::sync::{Mutex, mpsc, watch};
+use tracing::{error, info, warn};
+
+/// Held to talk while sharing a microphone, even from another app.
+#[cfg(desktop)]
+pub const TALK_SHORTCUT: &str = "Alt+Shift+T";
+/// How often the listener logs latency and jitter buffer state.
+const STATS_LOG_INTERVAL: Duration = Duration::from_secs(5);
+/// How often the listener reports playout statistics to the sharer, and
+/// both sides push connection stats to the UI.
+const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+/// How often the playout loop checks whether the playback ring needs audio.
+const PLAYOUT_TICK: Duration = Duration::from_millis(5);
+/// Decoded frames to keep queued ahead of the audio callback.
+const PLAYOUT_QUEUE_FRAMES: usize = 2;
 
 /// Shared application state managed by Tauri.
 pub struct AppState {
+    /// App data directory; holds the identity and settings.
+    data_dir: PathBuf,
+    /// This device's endpoint identity, used for sharing and listening.
+    identity: Mutex<SecretKey>,
+    settings: Mutex<Settings>,
     /// Active sharing session (if any).
     share: Mutex<Option<ShareContext>>,
-    /// Active listening session (if any).
-    listen: Mutex<Option<ListenContext>>,
+    /// Whether the push-to-talk key is held. Outside the share, so the talk
+    /// shortcut can press and release it without waiting on a lock.
+    talk_tx: watch::Sender<bool>,
+    /// Active listening sessions, by sharer.
+    listens: Mutex<BTreeMap<EndpointId, ListenContext>>,
+    /// Playback of the sharers listened to (while there are any). Lock
+    /// after `listens`.
+    playout: Mutex<Option<PlayoutContext>>,
+    /// Browsing the local network for sharers (if the UI asked to).
+    lan: Mutex<Option<LanContext>>,
+    /// Ticket from the last `hearme://listen/` link opened, until the UI
+    /// takes it.
+    opened_ticket: std::sync::Mutex<Option<String>>,
 }
 
 struct ShareContext {
     session: ShareSession,
-    _capture_handle: capture::CaptureHandle,
+    _capture_handles: Vec<capture::CaptureHandle>,
+    /// Follows what the first application shared plays, for as long as the
+    /// share runs.
+    _now_playing: Option<capture::NowPlayingWatcher>,
+    /// Gain of each source, in the order shared.
+    gains_tx: watch::Sender<Vec<f32>>,
+    /// When each microphone shared is heard, by its place among the
+    /// sources (applications ignore theirs).
+    gates_tx: watch::Sender<Vec<GateMode>>,
     encode_task: tokio::task::JoinHandle<()>,
+    approvals_task: tokio::task::JoinHandle<()>,
+    roster_task: tokio::task::JoinHandle<()>,
+    stats_task: tokio::task::JoinHandle<()>,
+    /// Plays the listeners talking back.
+    talkback_task: tokio::task::JoinHandle<()>,
 }
 
 struct ListenContext {
     session: ListenSession,
+    decode_task: tokio::task::JoinHandle<()>,
+    /// The latest stats, as last pushed to the UI.
+    stats_rx: watch::Receiver<ListenStats>,
+    /// Talking back to the sharer (if we are).
+    talkback: Option<TalkbackContext>,
+}
+
+struct TalkbackContext {
+    _capture_handle: capture::CaptureHandle,
+    encode_task: tokio::task::JoinHandle<()>,
+}
+
+struct PlayoutContext {
     /// Hold the cpal stream alive. Audio plays as long as this exists.
     _playback: PlaybackStream,
-    decode_task: tokio::task::JoinHandle<()>,
+    /// Every sharer's decoded audio, mixed for playback.
+    mixer: Arc<Mixer>,
+    mix_task: tokio::task::JoinHandle<()>,
 }
 
-impl Default for AppState {
-    fn default() -> Self {
-        Self {
+struct LanContext {
+    browser: LanBrowser,
+    notify_task: tokio::task::JoinHandle<()>,
+}
+
+impl AppState {
+    /// State backed by `data_dir`, loading (or creating) the identity and
+    /// loading the settings there.
+    pub fn new(data_dir: PathBuf) -> anyhow::Result<Self> {
+        let identity = identity::load_or_create(&data_dir)?;
+        info!("Endpoint identity: {}", identity.public());
+        let settings = Settings::load(&data_dir)?;
+        Ok(Self {
+            data_dir,
+            identity: Mutex::new(identity),
+            settings: Mutex::new(settings),
             share: Mutex::new(None),
-            listen: Mutex::new(None),
-        }
+            talk_tx: watch::channel(false).0,
+            listens: Mutex::new(BTreeMap::new()),
+            playout: Mutex::new(None),
+            lan: Mutex::new(None),
+            opened_ticket: std::sync::Mutex::new(None),
+        })
     }
 }
 
-impl AppState {
-    pub fn new() -> Self {
-        Self::default()
+/// Handle a link that opened the app. A `hearme://listen/` link is kept for
+/// the UI, which is told with a `ticket-opened` event (and asks on start, in
+/// case the link launched the app).
+pub fn open_link(app: &AppHandle, url: &str) {
+    if !url.starts_with(LISTEN_URI_PREFIX) {
+        warn!("Ignoring unknown link: {url}");
+        return;
+    }
+    if let Err(e) = Ticket::from_string_encoded(url) {
+        warn!("Ignoring link with an invalid ticket: {e}");
+        return;
     }
+    info!("Opened listen link");
+    let state = app.state::<AppState>();
+    *state.opened_ticket.lock().expect("opened ticket poisoned") = Some(url.to_string());
+    let _ = app.emit("ticket-opened", ());
+}
+
+/// The ticket of the last `hearme://listen/` link that opened the app, if the
+/// UI hasn't taken it yet.
+#[tauri::command]
+pub fn take_opened_ticket(state: State<'_, AppState>) -> Option<String> {
+    state
+        .opened_ticket
+        .lock()
+        .expect("opened ticket poisoned")
+        .take()
+}
+
+/// This device's endpoint id: what tickets point listeners at.
+#[tauri::command]
+pub async fn get_identity(state: State<'_, AppState>) -> Result<String, String> {
+    Ok(state.identity.lock().await.public().to_string())
+}
+
+/// Replace this device's identity with a fresh one. Every ticket handed out
+/// so far stops working. Returns the new endpoint id.
+#[tauri::command]
+pub async fn reset_identity(state: State<'_, AppState>) -> Result<String, String> {
+    if state.share.lock().await.is_some() || !state.listens.lock().await.is_empty() {
+        return Err("Stop sharing and listening before resetting your identity".into());
+    }
+    let key = identity::reset(&state.data_dir).map_err(|e| e.to_string())?;
+    let id = key.public().to_string();
+    *state.identity.lock().await = key;
+    info!("Endpoint identity reset: {id}");
+    Ok(id)
 }
 
 /// List audio sources (applications producing audio).
@@ -54,49 +195,437 @@ pub async fn list_audio_sources() -> Result<Vec<AudioSource>, String> {
     capture::list_sources().await.map_err(|e| e.to_string())
 }
 
-/// Start sharing audio from the selected source.
-/// Returns the ticket string for listeners to connect.
+/// List microphones, the default one first. They are shared like any other
+/// source.
+#[tauri::command]
+pub async fn list_microphones() -> Result<Vec<AudioSource>, String> {
+    tokio::task::spawn_blocking(capture::list_microphones)
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+/// A named encoder profile offered by the Share panel.
+#[derive(Debug, Clone, Serialize)]
+pub struct EncoderPreset {
+    pub name: &'static str,
+    pub config: EncoderConfig,
+}
+
+/// List the encoder presets the UI offers as starting points.
+#[tauri::command]
+pub fn list_encoder_presets() -> Vec<EncoderPreset> {
+    vec![
+        EncoderPreset {
+            name: "Balanced",
+            config: EncoderConfig::default(),
+        },
+        EncoderPreset {
+            name: "Music",
+            config: EncoderConfig::music(),
+        },
+        EncoderPreset {
+            name: "Voice",
+            config: EncoderConfig::voice(),
+        },
+    ]
+}
+
+/// A ticket in the forms the Share panel offers it: the string to paste, a
+/// link that opens hearme, and a QR code of that link.
+#[derive(Debug, Clone, Serialize)]
+pub struct TicketInfo {
+    pub ticket: String,
+    pub uri: String,
+    pub qr_svg: String,
+}
+
+impl TryFrom<&Ticket> for TicketInfo {
+    type Error = anyhow::Error;
+
+    fn try_from(ticket: &Ticket) -> anyhow::Result<Self> {
+        Ok(Self {
+            ticket: ticket.to_string_encoded()?,
+            uri: ticket.to_uri()?,
+            qr_svg: ticket.to_qr_svg()?,
+        })
+    }
+}
+
+/// What the sharer announced about its stream, for the Listen panel.
+#[derive(Debug, Clone, Serialize)]
+pub struct StreamInfo {
+    pub encoder: EncoderConfig,
+    /// Bitrates of the tiers the listener can pick from, lowest first.
+    pub tiers: Vec<u32>,
+    /// Names of the tracks the listener can turn on and off.
+    pub tracks: Vec<String>,
+}
+
+impl From<&StreamHeader> for StreamInfo {
+    fn from(header: &StreamHeader) -> Self {
+        Self {
+            encoder: header.encoder.clone(),
+            tiers: header.tiers.clone(),
+            tracks: header.tracks.iter().map(|t| t.name.clone()).collect(),
+        }
+    }
+}
+
+/// A sharer we started listening to, and what it announced about its
+/// stream.
+#[derive(Debug, Clone, Serialize)]
+pub struct ListenInfo {
+    pub sharer: EndpointId,
+    #[serde(flatten)]
+    pub stream: StreamInfo,
+}
+
+/// The payload of a `listen-*` event: news about one of the sharers we
+/// listen to.
+#[derive(Debug, Clone, Serialize)]
+pub struct FromSharer<T> {
+    pub sharer: EndpointId,
+    pub data: T,
+}
+
+impl PlayoutContext {
+    /// Start playback, feeding the device from a fresh mixer.
+    fn start() -> anyhow::Result<Self> {
+        let mut playback = PlaybackStream::start()?;
+        let mut producer = playback.take_producer();
+        let mixer = Arc::new(Mixer::new());
+        let mix_task = {
+            let mixer = mixer.clone();
+            tokio::spawn(async move {
+                let mut playout_tick = tokio::time::interval(PLAYOUT_TICK);
+                playout_tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+                loop {
+                    playout_tick.tick().await;
+                    // The audio callback drains the ring at the device clock;
+                    // top it up one mixed frame at a time so every sharer
+                    // plays out at that clock.
+                    while queued_samples(&producer) < PLAYOUT_QUEUE_FRAMES * SAMPLES_PER_FRAME {
+                        let Some(pcm) = mixer.next_frame() else { break };
+                        for sample in pcm {
+                            let _ = producer.push(sample);
+                        }
+                    }
+                }
+            })
+        };
+        Ok(Self {
+            _playback: playback,
+            mixer,
+            mix_task,
+        })
+    }
+}
+
+impl Drop for PlayoutContext {
+    fn drop(&mut self) {
+        self.mix_task.abort();
+    }
+}
+
+/// An application to capture for a share, and how loud.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ShareSource {
+    #[serde(flatten)]
+    pub source: AudioSource,
+    /// Linear gain, from 0 to [`MAX_GAIN`] (1 when omitted).
+    pub gain: Option<f32>,
+    /// The source's own profile, when it is a track of its own (the
+    /// share's when omitted).
+    pub encoder: Option<EncoderConfig>,
+    /// When a microphone is heard (all the time when omitted).
+    pub gate: Option<GateMode>,
+}
+
+/// Playout of one track of a sharer's stream.
+struct TrackPlayout {
+    jitter: JitterBuffer,
+    decoder: codec::Decoder,
+    input: MixerInput,
+}
+
+impl TrackPlayout {
+    /// Playout of every track of `sharer`'s stream, as `header` lists them,
+    /// each mixed in as `mix`.
+    fn start_all(
+        header: &StreamHeader,
+        sharer: EndpointId,
+        mixer: &Mixer,
+        mix: SourceMix,
+    ) -> anyhow::Result<Vec<Self>> {
+        let decoders = header
+            .tracks
+            .iter()
+            .map(|_| codec::Decoder::new())
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let tracks = decoders
+            .into_iter()
+            .enumerate()
+            .map(|(track, decoder)| Self {
+                jitter: JitterBuffer::new(header),
+                decoder,
+                input: mixer.add(sharer, track as u8, mix),
+            })
+            .collect();
+        Ok(tracks)
+    }
+
+    /// Playout of `listener` talking back, mixed in as its only track.
+    fn talkback(listener: EndpointId, mixer: &Mixer) -> anyhow::Result<Self> {
+        Ok(Self {
+            jitter: JitterBuffer::new(&StreamHeader::current()),
+            decoder: codec::Decoder::new()?,
+            input: mixer.add(listener, 0, SourceMix::default()),
+        })
+    }
+
+    /// The mixer takes a frame whenever the device needs one; keep one or
+    /// two decoded ahead of it.
+    fn fill(&mut self) {
+        while self.input.queued_samples() < PLAYOUT_QUEUE_FRAMES * SAMPLES_PER_FRAME {
+            let Some(playout) = self.jitter.pop() else {
+                break;
+            };
+            let pcm = match playout {
+                Playout::Frame(frame) => match self.decoder.decode(&frame.data) {
+                    Ok(pcm) => pcm,
+                    Err(e) => {
+                        error!("Opus decode error: {e}");
+                        continue;
+                    }
+                },
+                // Recover the frame from the next packet's FEC data if it's
+                // already here, otherwise conceal it.
+                Playout::Lost { seq } => {
+                    let result = match self.jitter.peek(seq + 1) {
+                        Some(next) => self.decoder.decode_fec(&next.data),
+                        None => self.decoder.decode_lost(),
+                    };
+                    result.unwrap_or_else(|e| {
+                        error!("Opus concealment error: {e}");
+                        vec![0.0; SAMPLES_PER_FRAME]
+                    })
+                }
+            };
+            self.input.push(&pcm);
+        }
+    }
+}
+
+/// Start sharing audio from the selected sources, laid out as `layout` says,
+/// to the listeners `access` admits; omitted options take their defaults.
+/// Returns the ticket for listeners to connect.
+///
+/// Emits `listener-pending` under [`AccessPolicy::ApproveOnConnect`] (answer
+/// with [`respond_to_listener`]), `listener-joined` and `listener-left` with
+/// a [`RosterListener`], `share-stats` about once a second, `share-talking`
+/// as the microphones' gates open and close, and `share-ended` once every
+/// source's capture has ended.
 #[tauri::command]
 pub async fn start_sharing(
     state: State<'_, AppState>,
-    source: AudioSource,
+    sources: Vec<ShareSource>,
+    layout: Option<SourceLayout>,
+    encoder: Option<EncoderConfig>,
+    access: Option<AccessPolicy>,
+    ticket: Option<TicketOptions>,
     app: AppHandle,
-) -> Result<String, String> {
+) -> Result<TicketInfo, String> {
     let mut share_guard = state.share.lock().await;
     if share_guard.is_some() {
         return Err("Already sharing".into());
     }
 
+    let encoder_config = encoder.unwrap_or_default();
+    encoder_config.validate().map_err(|e| e.to_string())?;
+    let layout = layout.unwrap_or_default();
+    let gains = check_sources(&sources, layout)?;
+    let tracks = match layout {
+        SourceLayout::Mixed => vec![TrackInfo {
+            name: (sources.iter().map(|s| s.source.name.as_str()))
+                .collect::<Vec<_>>()
+                .join(" + "),
+            encoder: encoder_config.clone(),
+        }],
+        SourceLayout::Tracks => sources
+            .iter()
+            .map(|s| TrackInfo {
+                name: s.source.name.clone(),
+                encoder: s.encoder.clone().unwrap_or_else(|| encoder_config.clone()),
+            })
+            .collect(),
+    };
+
+    // Start capturing audio from the selected apps and microphones, before
+    // the session so a source that won't capture leaves nothing to clean up
+    let mut capture_handles = Vec::new();
+    let mut captures = Vec::new();
+    for ShareSource { source, .. } in &sources {
+        let (handle, pcm_rx) = capture::start_capture(source)
+            .await
+            .map_err(|e| format!("{}: {e}", source.name))?;
+        capture_handles.push(handle);
+        captures.push(pcm_rx);
+    }
+    let mut captured_rx = mix::merge(captures);
+
     // Start the P2P share session
-    let (session, ticket) = ShareSession::start().await.map_err(|e| e.to_string())?;
-    let ticket_str = ticket.to_string_encoded().map_err(|e| e.to_string())?;
+    let secret_key = state.identity.lock().await.clone();
+    let network = state.settings.lock().await.network.clone();
+    // Tickets used up in an earlier share stay used up
+    let tickets =
+        TicketLedger::load(secret_key.public(), &state.data_dir).map_err(|e| e.to_string())?;
+    let (mut session, ticket) = ShareSession::start(
+        &encoder_config,
+        tracks.clone(),
+        access.unwrap_or_default(),
+        &ticket.unwrap_or_default(),
+        &network,
+        secret_key,
+        tickets,
+    )
+    .await
+    .map_err(|e| e.to_string())?;
+    let ticket_info = match TicketInfo::try_from(&ticket) {
+        Ok(info) => info,
+        Err(e) => {
+            if let Err(stop_err) = session.stop().await {
+                warn!("Failed to stop share session: {stop_err}");
+            }
+            return Err(e.to_string());
+        }
+    };
 
-    info!("Share ticket: {ticket_str}");
+    info!("Share ticket: {}", ticket_info.ticket);
 
-    // Start capturing audio from the selected app
-    let (capture_handle, mut pcm_rx) = capture::start_capture(&source)
-        .await
-        .map_err(|e| e.to_string())?;
+    // Tell listeners what the app is playing, where the platform can tell
+    let now_playing = (sources.iter())
+        .find(|s| s.source.kind == SourceKind::App)
+        .map(|s| capture::watch_now_playing(&s.source));
+    if let Some(now_playing) = &now_playing {
+        session.follow_now_playing(now_playing.now_playing());
+    }
 
-    // Spawn task: read PCM -> encode Opus -> broadcast to listeners
-    let opus_tx = session.opus_tx.clone();
+    // Spawn task: read PCM -> gate microphones -> apply gain -> mix (or not) -> stamp -> encode
+    // Opus per track and tier -> broadcast to the listeners on each tier
+    let track_txs = session.track_txs.clone();
+    let mut clocks: Vec<_> = tracks.iter().map(|_| session.frame_clock()).collect();
+    let mut bitrate_rx = session.bitrate_targets();
+    let (gains_tx, gains_rx) = watch::channel(gains);
+    let gates: Vec<_> = sources.iter().map(|s| s.gate.unwrap_or_default()).collect();
+    let (gates_tx, gates_rx) = watch::channel(gates);
+    state.talk_tx.send_replace(false);
+    let talk_rx = state.talk_tx.subscribe();
+    let mut mic_gates: Vec<Option<MicGate>> = (sources.iter())
+        .map(|s| (s.source.kind == SourceKind::Microphone).then(MicGate::default))
+        .collect();
     let app_clone = app.clone();
     let encode_task = tokio::spawn(async move {
-        let mut encoder = match codec::Encoder::new() {
+        let tiers = encoder_config.tiers();
+        let encoders: anyhow::Result<Vec<_>> = tracks
+            .iter()
+            .map(|track| tier_encoders(&tiers, track))
+            .collect();
+        let mut encoders = match encoders {
             Ok(e) => e,
             Err(e) => {
                 error!("Failed to create Opus encoder: {e}");
                 return;
             }
         };
+        let mut mixer = SourceMixer::new(gains_rx.borrow().len());
+        // Whether each track's tiers had listeners at the last frame
+        let mut served: Vec<Vec<bool>> = encoders.iter().map(|t| vec![false; t.len()]).collect();
+        // Whether each microphone's gate is open, and so whether any is
+        let mut gates_open = vec![false; mic_gates.len()];
+        let mut talking = false;
 
-        while let Some(pcm_frame) = pcm_rx.recv().await {
-            match encoder.encode(&pcm_frame) {
-                Ok(packet) => {
-                    let _ = opus_tx.send(Arc::new(packet));
+        while let Some(captured) = captured_rx.recv().await {
+            // Follow the listeners' links, never above the chosen profile
+            if encoder_config.adaptive && bitrate_rx.has_changed().unwrap_or(false) {
+                let targets = bitrate_rx.borrow_and_update().clone();
+                for (track, tiers) in tracks.iter().zip(&mut encoders) {
+                    for (encoder, &target) in tiers.iter_mut().zip(&targets) {
+                        let config = track.for_tier(target);
+                        if let Err(e) = encoder.set_bitrate(config.bitrate, config.max_bandwidth) {
+                            error!("Failed to adapt bitrate: {e}");
+                        }
+                    }
                 }
-                Err(e) => {
-                    error!("Opus encode error: {e}");
+                let _ = app_clone.emit("share-bitrate", targets);
+            }
+
+            // Frames ready to encode, by track
+            let frames: Vec<(usize, PcmFrame)> = match captured {
+                Captured::Frame(source, mut frame) => {
+                    let pcm = &mut frame.pcm;
+                    if let Some(gate) = &mut mic_gates[source] {
+                        gates_open[source] =
+                            gate.apply(pcm, gates_rx.borrow()[source], *talk_rx.borrow());
+                    }
+                    mix::apply_gain(pcm, gains_rx.borrow()[source]);
+                    match layout {
+                        SourceLayout::Mixed => mixer
+                            .push(source, frame)
+                            .into_iter()
+                            .map(|f| (0, f))
+                            .collect(),
+                        SourceLayout::Tracks => vec![(source, frame)],
+                    }
+                }
+                Captured::Ended(source) => {
+                    info!("Capture of source {source} ended");
+                    gates_open[source] = false;
+                    match layout {
+                        SourceLayout::Mixed => {
+                            mixer.end(source).into_iter().map(|f| (0, f)).collect()
+                        }
+                        SourceLayout::Tracks => Vec::new(),
+                    }
+                }
+            };
+            if gates_open.iter().any(|&open| open) != talking {
+                talking = !talking;
+                let _ = app_clone.emit("share-talking", talking);
+            }
+
+            for (track, pcm_frame) in frames {
+                // Every tier shares the stamp, so listeners can switch seamlessly
+                let (seq, timestamp) = clocks[track].stamp(pcm_frame.captured_at);
+                let outputs = encoders[track].iter_mut().zip(&track_txs[track]);
+                for ((encoder, tx), served) in outputs.zip(&mut served[track]) {
+                    // Nobody is on this tier; don't spend CPU on it
+                    if tx.receiver_count() == 0 {
+                        *served = false;
+                        continue;
+                    }
+                    // The tier was idle, so its encoder last heard audio
+                    // long ago; start the newcomers afresh
+                    if !*served {
+                        *served = true;
+                        if let Err(e) = encoder.reset_state() {
+                            error!("Failed to reset Opus encoder: {e}");
+                        }
+                    }
+                    match encoder.encode(&pcm_frame.pcm) {
+                        Ok(data) => {
+                            let frame = AudioFrame {
+                                track: track as u8,
+                                seq,
+                                timestamp,
+                                data,
+                            };
+                            let _ = tx.send(Arc::new(frame));
+                        }
+                        Err(e) => {
+                            error!("Opus encode error: {e}");
+                        }
+                    }
                 }
             }
         }
@@ -105,97 +634,945 @@ pub async fn start_sharing(
         let _ = app_clone.emit("share-ended", ());
     });
 
+    // Spawn task: ask the UI about each listener awaiting approval
+    let mut approval_rx = session
+        .take_approval_requests()
+        .expect("fresh session has its approval requests");
+    let approvals_task = {
+        let app = app.clone();
+        tokio::spawn(async move {
+            while let Some(id) = approval_rx.recv().await {
+                info!("Listener {id} is waiting for approval");
+                let _ = app.emit("listener-pending", id.to_string());
+            }
+        })
+    };
+
+    // Spawn task: tell the UI about listeners joining and leaving
+    let mut roster_rx = session
+        .take_roster_events()
+        .expect("fresh session has its roster events");
+    let roster_task = {
+        let app = app.clone();
+        tokio::spawn(async move {
+            while let Some(event) = roster_rx.recv().await {
+                let _ = match event {
+                    RosterEvent::Joined(listener) => app.emit("listener-joined", listener),
+                    RosterEvent::Left(listener) => app.emit("listener-left", listener),
+                };
+            }
+        })
+    };
+
+    // Spawn task: play the listeners talking back
+    let talkback_rx = session
+        .take_talkback()
+        .expect("fresh session has its talkback");
+    let talkback_task = tokio::spawn(play_talkback(talkback_rx));
+
+    // Hold to talk from whichever app has focus; the button still works
+    // if another app holds the shortcut
+    #[cfg(desktop)]
+    if sources
+        .iter()
+        .any(|s| s.source.kind == SourceKind::Microphone)
+        && let Err(e) = app.global_shortcut().register(TALK_SHORTCUT)
+    {
+        warn!("Failed to register the talk shortcut {TALK_SHORTCUT}: {e}");
+    }
+
+    // Spawn task: push the listeners' stats to the UI
+    let roster = session.roster();
+    let stats_task = tokio::spawn(async move {
+        let mut tick = tokio::time::interval(REPORT_INTERVAL);
+        loop {
+            tick.tick().await;
+            let _ = app.emit("share-stats", roster.listeners());
+        }
+    });
+
     *share_guard = Some(ShareContext {
         session,
-        _capture_handle: capture_handle,
+        _capture_handles: capture_handles,
+        _now_playing: now_playing,
+        gains_tx,
+        gates_tx,
         encode_task,
+        approvals_task,
+        roster_task,
+        stats_task,
+        talkback_task,
     });
 
-    Ok(ticket_str)
+    Ok(ticket_info)
+}
+
+/// Play the listeners talking back on `talkback_rx`, mixed, from the first
+/// voice heard until the last one stops.
+async fn play_talkback(mut talkback_rx: mpsc::Receiver<Talkback>) {
+    let mut playout: Option<PlayoutContext> = None;
+    let mut voices: BTreeMap<EndpointId, TrackPlayout> = BTreeMap::new();
+    let mut playout_tick = tokio::time::interval(PLAYOUT_TICK);
+    playout_tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    loop {
+        tokio::select! {
+            talkback = talkback_rx.recv() => match talkback {
+                Some(Talkback::Frame(listener, frame)) => {
+                    if let Entry::Vacant(entry) = voices.entry(listener) {
+                        if playout.is_none() {
+                            match PlayoutContext::start() {
+                                Ok(ctx) => playout = Some(ctx),
+                                Err(e) => {
+                                    error!("Failed to play listeners talking back: {e}");
+                                    continue;
+                                }
+                            }
+                        }
+                        let mixer = &playout.as_ref().expect("playout just started").mixer;
+                        match TrackPlayout::talkback(listener, mixer) {
+                            Ok(voice) => {
+                                info!("Listener {listener} is talking back");
+                                entry.insert(voice);
+                            }
+                            Err(e) => {
+                                error!("Failed to create Opus decoder: {e}");
+                                continue;
+                            }
+                        }
+                    }
+                    if let Some(voice) = voices.get_mut(&listener) {
+                        voice.jitter.push(frame, Instant::now());
+                    }
+                }
+                Some(Talkback::Stopped(listener)) => {
+                    if voices.remove(&listener).is_some() {
+                        info!("Listener {listener} stopped talking back");
+                    }
+                    if let Some(ctx) = &playout {
+                        ctx.mixer.remove(&listener);
+                    }
+                    if voices.is_empty() {
+                        playout = None;
+                    }
+                }
+                None => break,
+            },
+            _ = playout_tick.tick() => {
+                for voice in voices.values_mut() {
+                    voice.fill();
+                }
+            }
+        }
+    }
+}
+
+/// Change the gain of a source of the current share, by its place among the
+/// sources shared.
+#[tauri::command]
+pub async fn set_source_gain(
+    state: State<'_, AppState>,
+    source: usize,
+    gain: f32,
+) -> Result<(), String> {
+    check_gain(gain)?;
+    let share_guard = state.share.lock().await;
+    let ctx = share_guard.as_ref().ok_or("Not sharing")?;
+    let mut changed = false;
+    ctx.gains_tx
+        .send_if_modified(|gains| match gains.get_mut(source) {
+            Some(g) => {
+                *g = gain;
+                changed = true;
+                true
+            }
+            None => false,
+        });
+    if !changed {
+        return Err("No such source".into());
+    }
+    Ok(())
+}
+
+/// Change when a microphone of the current share is heard, by its place
+/// among the sources shared.
+#[tauri::command]
+pub async fn set_mic_gate(
+    state: State<'_, AppState>,
+    source: usize,
+    gate: GateMode,
+) -> Result<(), String> {
+    gate.validate().map_err(|e| e.to_string())?;
+    let share_guard = state.share.lock().await;
+    let ctx = share_guard.as_ref().ok_or("Not sharing")?;
+    let mut changed = false;
+    ctx.gates_tx
+        .send_if_modified(|gates| match gates.get_mut(source) {
+            Some(g) => {
+                *g = gate;
+                changed = true;
+                true
+            }
+            None => false,
+        });
+    if !changed {
+        return Err("No such source".into());
+    }
+    Ok(())
+}
+
+/// Press (`held`) or release the push-to-talk key of the current share.
+/// Microphones gated by push to talk are heard only while it, or the
+/// [`TALK_SHORTCUT`], is held.
+#[tauri::command]
+pub async fn set_push_to_talk(state: State<'_, AppState>, held: bool) -> Result<(), String> {
+    if state.share.lock().await.is_none() {
+        return Err("Not sharing".into());
+    }
+    state.talk_tx.send_replace(held);
+    Ok(())
+}
+
+/// The [`TALK_SHORTCUT`] was pressed (`held`) or released.
+#[cfg(desktop)]
+pub fn on_talk_shortcut(app: &AppHandle, held: bool) {
+    app.state::<AppState>().talk_tx.send_replace(held);
 }
 
-/// Stop sharing.
+/// Pause or resume the current share. Listeners stay connected and are told;
+/// no audio flows while paused.
 #[tauri::command]
-pub async fn stop_sharing(state: State<'_, AppState>) -> Result<(), String> {
+pub async fn set_share_paused(state: State<'_, AppState>, paused: bool) -> Result<(), String> {
+    let share_guard = state.share.lock().await;
+    let ctx = share_guard.as_ref().ok_or("Not sharing")?;
+    info!("Share {}", if paused { "paused" } else { "resumed" });
+    ctx.session.set_paused(paused);
+    Ok(())
+}
+
+/// Stop sharing, telling listeners the share is over.
+#[tauri::command]
+pub async fn stop_sharing(state: State<'_, AppState>, app: AppHandle) -> Result<(), String> {
     let mut share_guard = state.share.lock().await;
     if let Some(ctx) = share_guard.take() {
+        // Give the talk shortcut back to other apps
+        #[cfg(desktop)]
+        let _ = app.global_shortcut().unregister(TALK_SHORTCUT);
         ctx.encode_task.abort();
+        ctx.approvals_task.abort();
+        ctx.roster_task.abort();
+        ctx.stats_task.abort();
+        ctx.talkback_task.abort();
         ctx.session.stop().await.map_err(|e| e.to_string())?;
         info!("Stopped sharing");
     }
     Ok(())
 }
 
-/// Start listening to a sharer by their ticket.
+/// Issue another ticket for the current share, limited by `options` (e.g. a
+/// one-time ticket for a single person).
 #[tauri::command]
-pub async fn start_listening(
+pub async fn create_ticket(
     state: State<'_, AppState>,
-    ticket_str: String,
-    app: AppHandle,
+    options: TicketOptions,
+) -> Result<TicketInfo, String> {
+    let share_guard = state.share.lock().await;
+    let ctx = share_guard.as_ref().ok_or("Not sharing")?;
+    let ticket = ctx.session.issue_ticket(&options);
+    TicketInfo::try_from(&ticket).map_err(|e| e.to_string())
+}
+
+/// Change who may listen to the current share. Listeners already connected
+/// stay until removed with [`kick_listener`].
+#[tauri::command]
+pub async fn set_access_policy(
+    state: State<'_, AppState>,
+    policy: AccessPolicy,
+) -> Result<(), String> {
+    let share_guard = state.share.lock().await;
+    let ctx = share_guard.as_ref().ok_or("Not sharing")?;
+    info!("Access policy: {policy:?}");
+    ctx.session.access().set_policy(policy);
+    Ok(())
+}
+
+/// Let a listener announced by `listener-pending` in, or turn it away.
+#[tauri::command]
+pub async fn respond_to_listener(
+    state: State<'_, AppState>,
+    endpoint_id: String,
+    approve: bool,
 ) -> Result<(), String> {
-    let mut listen_guard = state.listen.lock().await;
-    if listen_guard.is_some() {
-        return Err("Already listening".into());
+    let id: EndpointId = endpoint_id.parse().map_err(|_| "Invalid endpoint id")?;
+    let share_guard = state.share.lock().await;
+    let ctx = share_guard.as_ref().ok_or("Not sharing")?;
+    if !ctx.session.access().decide(id, approve) {
+        return Err("That listener is no longer waiting".into());
     }
+    Ok(())
+}
 
-    let ticket = Ticket::from_string_encoded(&ticket_str).map_err(|e| e.to_string())?;
+/// Disconnect a listener and keep it out for the rest of this share.
+#[tauri::command]
+pub async fn kick_listener(state: State<'_, AppState>, endpoint_id: String) -> Result<(), String> {
+    let id: EndpointId = endpoint_id.parse().map_err(|_| "Invalid endpoint id")?;
+    let share_guard = state.share.lock().await;
+    let ctx = share_guard.as_ref().ok_or("Not sharing")?;
+    if ctx.session.access().remove(id) {
+        info!("Removed listener {id}");
+    } else {
+        info!("Barred listener {id}, which wasn't connected");
+    }
+    Ok(())
+}
 
-    // Connect to the sharer
-    let (session, mut opus_rx) = ListenSession::connect(&ticket)
-        .await
-        .map_err(|e| e.to_string())?;
+/// Stop hearing `listener` talk back to the current share (`muted`), or
+/// hear it again. The listener is told.
+#[tauri::command]
+pub async fn set_talkback_muted(
+    state: State<'_, AppState>,
+    listener: String,
+    muted: bool,
+) -> Result<(), String> {
+    let id: EndpointId = listener.parse().map_err(|_| "Invalid endpoint id")?;
+    let share_guard = state.share.lock().await;
+    let ctx = share_guard.as_ref().ok_or("Not sharing")?;
+    info!(
+        "Listener {id} talking back {}",
+        if muted { "muted" } else { "unmuted" }
+    );
+    ctx.session.set_talkback_muted(id, muted);
+    Ok(())
+}
 
-    // Start playback — take the producer out for the decode task
-    let mut playback = PlaybackStream::start().map_err(|e| e.to_string())?;
-    let mut producer = playback.take_producer();
+/// The listeners connected to the current share, longest connected first.
+#[tauri::command]
+pub async fn list_listeners(state: State<'_, AppState>) -> Result<Vec<RosterListener>, String> {
+    let share_guard = state.share.lock().await;
+    let ctx = share_guard.as_ref().ok_or("Not sharing")?;
+    Ok(ctx.session.roster().listeners())
+}
 
-    // Spawn task: receive Opus packets -> decode -> push to ring buffer
-    let app_clone = app.clone();
-    let decode_task = tokio::spawn(async move {
-        let mut decoder = match codec::Decoder::new() {
-            Ok(d) => d,
+/// A sharer saved under a name, to listen to without a ticket.
+#[derive(Debug, Clone, Serialize)]
+pub struct Contact {
+    pub alias: String,
+    pub endpoint_id: String,
+}
+
+/// List the saved contacts, by name.
+#[tauri::command]
+pub async fn list_contacts(state: State<'_, AppState>) -> Result<Vec<Contact>, String> {
+    let settings = state.settings.lock().await;
+    Ok(settings
+        .contacts
+        .iter()
+        .map(|(alias, id)| Contact {
+            alias: alias.clone(),
+            endpoint_id: id.to_string(),
+        })
+        .collect())
+}
+
+/// Save a sharer's endpoint id under `alias`, replacing a contact of the same
+/// name.
+#[tauri::command]
+pub async fn save_contact(
+    state: State<'_, AppState>,
+    alias: String,
+    endpoint_id: String,
+) -> Result<(), String> {
+    let id: EndpointId = endpoint_id
+        .trim()
+        .parse()
+        .map_err(|_| "Invalid endpoint id")?;
+    let mut settings = state.settings.lock().await;
+    let mut updated = settings.clone();
+    updated.add_contact(&alias, id).map_err(|e| e.to_string())?;
+    updated.save(&state.data_dir).map_err(|e| e.to_string())?;
+    *settings = updated;
+    Ok(())
+}
+
+/// Forget the contact called `alias`.
+#[tauri::command]
+pub async fn remove_contact(state: State<'_, AppState>, alias: String) -> Result<(), String> {
+    let mut settings = state.settings.lock().await;
+    let mut updated = settings.clone();
+    if !updated.remove_contact(&alias) {
+        return Err(format!("No contact called {alias}"));
+    }
+    updated.save(&state.data_dir).map_err(|e| e.to_string())?;
+    *settings = updated;
+    Ok(())
+}
+
+/// Work out which sharer `sharer` names: a ticket or `hearme://listen/` link,
+/// an endpoint id, or the name of a saved contact.
+fn resolve_sharer(settings: &Settings, sharer: &str) -> anyhow::Result<ListenTarget> {
+    let sharer = sharer.trim();
+    if let Ok(id) = sharer.parse::<EndpointId>() {
+        return Ok(id.into());
+    }
+    if let Some(id) = settings.contact(sharer) {
+        return Ok(id.into());
+    }
+    match Ticket::from_string_encoded(sharer) {
+        Ok(ticket) => Ok((&ticket).into()),
+        Err(e) => Err(e.context("Not a ticket, device ID or saved contact")),
+    }
+}
+
+/// Start listening to a sharer, given by ticket (or `hearme://listen/`
+/// link), device ID or contact name. `max_bitrate` caps the tier the sharer
+/// serves. Other sharers we listen to keep playing, mixed as set with
+/// [`set_listen_mix`]. Returns the sharer's id and what it announced about
+/// its stream.
+///
+/// Every `listen-*` event carries a [`FromSharer`]: `listen-reconnecting`
+/// and `listen-resumed` as the session reconnects on its own, `listen-ended`
+/// with a [`ListenEnd`] (`null` if we stopped it), `listen-now-playing`,
+/// `listen-paused` and `listen-stats`.
+#[tauri::command]
+pub async fn start_listening(
+    state: State<'_, AppState>,
+    sharer: String,
+    max_bitrate: Option<u32>,
+    app: AppHandle,
+) -> Result<ListenInfo, String> {
+    let (target, settings) = {
+        let settings = state.settings.lock().await;
+        let target = resolve_sharer(&settings, &sharer).map_err(|e| format!("{e:#}"))?;
+        (target, settings.clone())
+    };
+    let sharer = target.addr.id;
+    if state.listens.lock().await.contains_key(&sharer) {
+        return Err("Already listening to that sharer".into());
+    }
+
+    // Connect to the sharer, leaving the other listens be meanwhile
+    let secret_key = state.identity.lock().await.clone();
+    let (session, mut events) = ListenSession::connect(
+        &target,
+        max_bitrate,
+        settings.display_name,
+        &settings.network,
+        secret_key,
+    )
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let mut listens = state.listens.lock().await;
+    if listens.contains_key(&sharer) {
+        session.stop().await;
+        return Err("Already listening to that sharer".into());
+    }
+
+    // Start playback with the first sharer, and mix this one in
+    let mut playout = state.playout.lock().await;
+    if playout.is_none() {
+        match PlayoutContext::start() {
+            Ok(ctx) => *playout = Some(ctx),
             Err(e) => {
-                error!("Failed to create Opus decoder: {e}");
-                return;
+                session.stop().await;
+                return Err(e.to_string());
             }
-        };
+        }
+    }
+    let mixer = playout
+        .as_ref()
+        .expect("playout just started")
+        .mixer
+        .clone();
+    let mut header = session.header().clone();
+    let mut tracks = match TrackPlayout::start_all(&header, sharer, &mixer, SourceMix::default()) {
+        Ok(tracks) => tracks,
+        Err(e) => {
+            session.stop().await;
+            return Err(format!("Failed to create Opus decoder: {e}"));
+        }
+    };
+
+    // Spawn task: receive frames -> jitter buffer per track -> decode ->
+    // queue for the mixer
+    let reporter = session.reporter();
+    let (stats_tx, stats_rx) = watch::channel(ListenStats::default());
+    let stream = StreamInfo::from(&header);
+    info!("Sharer {sharer} stream: {stream:?}");
+    let decode_task = tokio::spawn(async move {
+        let mut playout_tick = tokio::time::interval(PLAYOUT_TICK);
+        playout_tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        let mut report_tick = tokio::time::interval(REPORT_INTERVAL);
+        let mut latency = None;
+        let mut last_stats_log = Instant::now();
+        let mut end_reason: Option<ListenEnd> = None;
+        let mut link_stats = None;
 
-        while let Some(packet) = opus_rx.recv().await {
-            match decoder.decode(&packet) {
-                Ok(pcm) => {
-                    for &sample in &pcm {
-                        // Non-blocking push; if ring buffer is full, drop samples
-                        // (better than blocking the async runtime)
-                        let _ = producer.push(sample);
+        loop {
+            tokio::select! {
+                event = events.recv() => match event {
+                    Some(ListenEvent::Frame(frame)) => {
+                        latency = header.latency(frame.timestamp, SystemTime::now());
+                        if let Some(track) = tracks.get_mut(frame.track as usize) {
+                            track.jitter.push(frame, Instant::now());
+                        }
+                    }
+                    Some(ListenEvent::Link(stats)) => link_stats = Some(stats),
+                    Some(ListenEvent::NowPlaying(now_playing)) => {
+                        emit_from(&app, sharer, "listen-now-playing", now_playing);
+                    }
+                    Some(ListenEvent::Paused(paused)) => {
+                        info!("Sharer {sharer} {}", if paused { "paused" } else { "resumed" });
+                        emit_from(&app, sharer, "listen-paused", paused);
+                    }
+                    Some(ListenEvent::TalkbackMuted(muted)) => {
+                        emit_from(&app, sharer, "listen-talkback-muted", muted);
+                    }
+                    Some(ListenEvent::Reconnecting { attempt }) => {
+                        link_stats = None;
+                        info!("Reconnecting to sharer {sharer} (attempt {attempt})");
+                        emit_from(&app, sharer, "listen-reconnecting", attempt);
+                    }
+                    // A restarted sharer numbers its frames afresh, maybe
+                    // with other tracks; start playout over rather than wait
+                    // for old sequence numbers. Tracks turned off stay off,
+                    // by their place, as they do at the sharer.
+                    Some(ListenEvent::Resumed(resumed)) => {
+                        let mix = mixer.mix(&sharer).unwrap_or_default();
+                        let enabled = mixer.tracks_enabled(&sharer);
+                        mixer.remove(&sharer);
+                        match TrackPlayout::start_all(&resumed, sharer, &mixer, mix) {
+                            Ok(restarted) => tracks = restarted,
+                            Err(e) => error!("Failed to reset Opus decoders: {e}"),
+                        }
+                        for (track, enabled) in enabled.into_iter().enumerate() {
+                            mixer.set_track_enabled(&sharer, track as u8, enabled);
+                        }
+                        header = resumed;
+                        latency = None;
+                        emit_from(&app, sharer, "listen-resumed", StreamInfo::from(&header));
+                    }
+                    Some(ListenEvent::Ended(end)) => {
+                        end_reason = Some(end);
+                        break;
+                    }
+                    None => break,
+                },
+                _ = playout_tick.tick() => {
+                    for track in &mut tracks {
+                        track.fill();
                     }
                 }
-                Err(e) => {
-                    error!("Opus decode error: {e}");
+                _ = report_tick.tick() => {
+                    let jitters: Vec<_> = tracks.iter().map(|t| &t.jitter).collect();
+                    let stats = ListenStats::new(link_stats.clone(), latency, &jitters);
+                    let _ = reporter.send(Some(stats.report()));
+                    emit_from(&app, sharer, "listen-stats", stats.clone());
+                    stats_tx.send_replace(stats);
                 }
             }
+
+            if last_stats_log.elapsed() >= STATS_LOG_INTERVAL {
+                last_stats_log = Instant::now();
+                let latency = latency
+                    .map(|l| format!("{}ms", l.as_millis()))
+                    .unwrap_or_else(|| "unknown".into());
+                let stats = stats_tx.borrow();
+                info!(
+                    "Sharer {sharer}: end-to-end latency {latency}, jitter buffer {}ms (target {}ms), {} frames received, {} lost, {} late",
+                    stats.buffer_ms,
+                    stats.target_buffer_ms,
+                    stats.frames_received,
+                    stats.frames_lost,
+                    stats.frames_late
+                );
+            }
         }
 
-        info!("Listen stream ended");
-        let _ = app_clone.emit("listen-ended", ());
+        info!("Listen stream from {sharer} ended: {end_reason:?}");
+        emit_from(&app, sharer, "listen-ended", end_reason);
     });
 
-    *listen_guard = Some(ListenContext {
-        session,
-        _playback: playback,
-        decode_task,
+    listens.insert(
+        sharer,
+        ListenContext {
+            session,
+            decode_task,
+            stats_rx,
+            talkback: None,
+        },
+    );
+
+    Ok(ListenInfo { sharer, stream })
+}
+
+/// Change the highest bitrate tier `sharer` should serve us
+/// (`None`: the best the link sustains).
+#[tauri::command]
+pub async fn set_listen_quality(
+    state: State<'_, AppState>,
+    sharer: String,
+    max_bitrate: Option<u32>,
+) -> Result<(), String> {
+    let sharer: EndpointId = sharer.parse().map_err(|_| "Invalid endpoint id")?;
+    let listens = state.listens.lock().await;
+    let ctx = listens.get(&sharer).ok_or("Not listening to that sharer")?;
+    ctx.session.request_quality(max_bitrate);
+    Ok(())
+}
+
+/// Change how `sharer` sounds in the mix of the sharers we listen to.
+#[tauri::command]
+pub async fn set_listen_mix(
+    state: State<'_, AppState>,
+    sharer: String,
+    mix: SourceMix,
+) -> Result<(), String> {
+    let sharer: EndpointId = sharer.parse().map_err(|_| "Invalid endpoint id")?;
+    mix.validate().map_err(|e| e.to_string())?;
+    let playout = state.playout.lock().await;
+    let mixed = playout
+        .as_ref()
+        .is_some_and(|ctx| ctx.mixer.set_mix(&sharer, mix));
+    if !mixed {
+        return Err("Not listening to that sharer".into());
+    }
+    Ok(())
+}
+
+/// Hear `track` of `sharer`'s stream, by its place among the stream's
+/// tracks, or not. A track turned off isn't sent to us at all.
+#[tauri::command]
+pub async fn set_listen_track(
+    state: State<'_, AppState>,
+    sharer: String,
+    track: u8,
+    enabled: bool,
+) -> Result<(), String> {
+    let sharer: EndpointId = sharer.parse().map_err(|_| "Invalid endpoint id")?;
+    let listens = state.listens.lock().await;
+    let ctx = listens.get(&sharer).ok_or("Not listening to that sharer")?;
+    let playout = state.playout.lock().await;
+    let found = playout
+        .as_ref()
+        .is_some_and(|ctx| ctx.mixer.set_track_enabled(&sharer, track, enabled));
+    if !found {
+        return Err("No such track".into());
+    }
+    ctx.session.set_subscribed(track, enabled);
+    Ok(())
+}
+
+/// Talk back to `sharer` through `microphone`, or stop talking back if
+/// `None`. The sharer may mute us, which `listen-talkback-muted` events say
+/// (reconnecting unmutes until the sharer says otherwise).
+#[tauri::command]
+pub async fn set_talkback(
+    state: State<'_, AppState>,
+    sharer: String,
+    microphone: Option<AudioSource>,
+) -> Result<(), String> {
+    let sharer: EndpointId = sharer.parse().map_err(|_| "Invalid endpoint id")?;
+    let mut listens = state.listens.lock().await;
+    let ctx = listens
+        .get_mut(&sharer)
+        .ok_or("Not listening to that sharer")?;
+    if let Some(talkback) = ctx.talkback.take() {
+        talkback.encode_task.abort();
+        info!("Stopped talking back to {sharer}");
+    }
+    let Some(microphone) = microphone else {
+        return Ok(());
+    };
+    if microphone.kind != SourceKind::Microphone {
+        return Err("Talk back through a microphone".into());
+    }
+
+    let (capture_handle, mut pcm_rx) = capture::start_capture(&microphone)
+        .await
+        .map_err(|e| format!("{}: {e}", microphone.name))?;
+    let mut encoder =
+        codec::Encoder::with_config(&EncoderConfig::voice()).map_err(|e| e.to_string())?;
+
+    // Spawn task: read PCM -> stamp -> encode Opus -> send to the sharer
+    let frame_tx = ctx.session.start_talkback();
+    let encode_task = tokio::spawn(async move {
+        let mut clock = FrameClock::new(Instant::now());
+        while let Some(pcm_frame) = pcm_rx.recv().await {
+            let (seq, timestamp) = clock.stamp(pcm_frame.captured_at);
+            let data = match encoder.encode(&pcm_frame.pcm) {
+                Ok(data) => data,
+                Err(e) => {
+                    error!("Opus encode error: {e}");
+                    continue;
+                }
+            };
+            let frame = AudioFrame {
+                track: 0,
+                seq,
+                timestamp,
+                data,
+            };
+            if frame_tx.send(frame).await.is_err() {
+                break;
+            }
+        }
     });
 
+    info!("Talking back to {sharer} through {}", microphone.name);
+    ctx.talkback = Some(TalkbackContext {
+        _capture_handle: capture_handle,
+        encode_task,
+    });
     Ok(())
 }
 
-/// Stop listening.
+/// Stop listening to `sharer`, or to every sharer if `None`. Playback stops
+/// with the last one.
 #[tauri::command]
-pub async fn stop_listening(state: State<'_, AppState>) -> Result<(), String> {
-    let mut listen_guard = state.listen.lock().await;
-    if let Some(ctx) = listen_guard.take() {
+pub async fn stop_listening(
+    state: State<'_, AppState>,
+    sharer: Option<String>,
+) -> Result<(), String> {
+    let mut listens = state.listens.lock().await;
+    let stopped: Vec<_> = match sharer {
+        Some(sharer) => {
+            let sharer: EndpointId = sharer.parse().map_err(|_| "Invalid endpoint id")?;
+            listens.remove_entry(&sharer).into_iter().collect()
+        }
+        None => std::mem::take(&mut *listens).into_iter().collect(),
+    };
+
+    let mut playout = state.playout.lock().await;
+    for (sharer, ctx) in stopped {
         ctx.decode_task.abort();
+        if let Some(talkback) = ctx.talkback {
+            talkback.encode_task.abort();
+        }
+        if let Some(playout) = playout.as_ref() {
+            playout.mixer.remove(&sharer);
+        }
         ctx.session.stop().await;
-        info!("Stopped listening");
+        info!("Stopped listening to {sharer}");
+    }
+    if listens.is_empty() {
+        *playout = None;
     }
     Ok(())
 }
+
+/// The name sharers list us under while we listen, if set.
+#[tauri::command]
+pub async fn get_display_name(state: State<'_, AppState>) -> Result<Option<String>, String> {
+    Ok(state.settings.lock().await.display_name.clone())
+}
+
+/// Change and save the name sharers list us under (blank for none),
+/// telling the sharer we are listening to, if any.
+#[tauri::command]
+pub async fn set_display_name(state: State<'_, AppState>, name: String) -> Result<(), String> {
+    let display_name = {
+        let mut settings = state.settings.lock().await;
+        let mut updated = settings.clone();
+        updated.set_display_name(&name).map_err(|e| e.to_string())?;
+        updated.save(&state.data_dir).map_err(|e| e.to_string())?;
+        *settings = updated;
+        settings.display_name.clone()
+    };
+    for ctx in state.listens.lock().await.values() {
+        ctx.session.set_display_name(display_name.clone());
+    }
+    Ok(())
+}
+
+/// The network configuration sharing and listening use.
+#[tauri::command]
+pub async fn get_network_config(state: State<'_, AppState>) -> Result<NetworkConfig, String> {
+    Ok(state.settings.lock().await.network.clone())
+}
+
+/// Change and save the network configuration. Takes effect from the next
+/// share or listen.
+#[tauri::command]
+pub async fn set_network_config(
+    state: State<'_, AppState>,
+    config: NetworkConfig,
+) -> Result<(), String> {
+    config.validate().map_err(|e| e.to_string())?;
+    let mut settings = state.settings.lock().await;
+    let mut updated = settings.clone();
+    updated.network = config;
+    updated.save(&state.data_dir).map_err(|e| e.to_string())?;
+    *settings = updated;
+    info!("Network configuration: {:?}", settings.network);
+    Ok(())
+}
+
+/// Start looking for sharers on the local network. Returns those heard so
+/// far; `lan-sharers` events carry the whole list again whenever a sharer
+/// appears, changes source or goes away.
+#[tauri::command]
+pub async fn start_lan_discovery(
+    state: State<'_, AppState>,
+    app: AppHandle,
+) -> Result<Vec<LanSharer>, String> {
+    let mut lan_guard = state.lan.lock().await;
+    if let Some(ctx) = lan_guard.as_ref() {
+        return Ok(ctx.browser.sharers().borrow().clone());
+    }
+    if !state.settings.lock().await.network.mdns {
+        return Err("Local network discovery is off in the network settings".into());
+    }
+
+    let own_id = state.identity.lock().await.public();
+    let browser = LanBrowser::start(own_id).await.map_err(|e| e.to_string())?;
+    let mut sharers_rx = browser.sharers();
+    let sharers = sharers_rx.borrow_and_update().clone();
+    let notify_task = tokio::spawn(async move {
+        while sharers_rx.changed().await.is_ok() {
+            let sharers = sharers_rx.borrow_and_update().clone();
+            let _ = app.emit("lan-sharers", sharers);
+        }
+    });
+    info!("Looking for sharers on the local network");
+
+    *lan_guard = Some(LanContext {
+        browser,
+        notify_task,
+    });
+    Ok(sharers)
+}
+
+/// Stop looking for sharers on the local network.
+#[tauri::command]
+pub async fn stop_lan_discovery(state: State<'_, AppState>) -> Result<(), String> {
+    if let Some(ctx) = state.lan.lock().await.take() {
+        ctx.notify_task.abort();
+    }
+    Ok(())
+}
+
+/// A sharer we listen to: how it sounds in the mix and how listening goes.
+#[derive(Debug, Clone, Serialize)]
+pub struct ListeningTo {
+    pub sharer: EndpointId,
+    pub mix: SourceMix,
+    /// Whether each of the sharer's tracks is heard, by track.
+    pub tracks_enabled: Vec<bool>,
+    /// Whether we talk back to the sharer.
+    pub talking_back: bool,
+    pub stats: ListenStats,
+}
+
+/// The sharers we listen to.
+#[tauri::command]
+pub async fn list_listens(state: State<'_, AppState>) -> Result<Vec<ListeningTo>, String> {
+    Ok(listening_to(&state).await)
+}
+
+async fn listening_to(state: &AppState) -> Vec<ListeningTo> {
+    let listens = state.listens.lock().await;
+    let playout = state.playout.lock().await;
+    listens
+        .iter()
+        .map(|(sharer, ctx)| {
+            let mixer = playout.as_ref().map(|p| &p.mixer);
+            ListeningTo {
+                sharer: *sharer,
+                mix: mixer.and_then(|m| m.mix(sharer)).unwrap_or_default(),
+                tracks_enabled: mixer.map(|m| m.tracks_enabled(sharer)).unwrap_or_default(),
+                talking_back: ctx.talkback.is_some(),
+                stats: ctx.stats_rx.borrow().clone(),
+            }
+        })
+        .collect()
+}
+
+/// Connection stats of the current share and listens, whichever are running.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionStats {
+    pub sharing: Option<Vec<RosterListener>>,
+    pub listening: Vec<ListeningTo>,
+}
+
+/// A snapshot of the stats the `share-stats` and `listen-stats` events push.
+#[tauri::command]
+pub async fn get_connection_stats(state: State<'_, AppState>) -> Result<ConnectionStats, String> {
+    let sharing = state
+        .share
+        .lock()
+        .await
+        .as_ref()
+        .map(|ctx| ctx.session.roster().listeners());
+    let listening = listening_to(&state).await;
+    Ok(ConnectionStats { sharing, listening })
+}
+
+/// Check the sources of a share can be shared together. Returns their gains.
+fn check_sources(sources: &[ShareSource], layout: SourceLayout) -> Result<Vec<f32>, String> {
+    if sources.is_empty() {
+        return Err("Select a source to share".into());
+    }
+    if sources.len() > MAX_TRACKS {
+        return Err(format!("Share at most {MAX_TRACKS} sources at once"));
+    }
+    for (i, ShareSource { source, .. }) in sources.iter().enumerate() {
+        if sources[..i].iter().any(|s| s.source.id == source.id) {
+            return Err(format!("{} is selected twice", source.name));
+        }
+    }
+    for ShareSource {
+        source,
+        encoder,
+        gate,
+        ..
+    } in sources
+    {
+        if let Some(encoder) = encoder {
+            if layout == SourceLayout::Mixed {
+                return Err("Sources mixed together share one profile".into());
+            }
+            encoder
+                .validate()
+                .map_err(|e| format!("{}: {e}", source.name))?;
+        }
+        if let Some(gate) = gate {
+            if source.kind != SourceKind::Microphone {
+                return Err(format!("{} isn't a microphone", source.name));
+            }
+            gate.validate()
+                .map_err(|e| format!("{}: {e}", source.name))?;
+        }
+    }
+    sources
+        .iter()
+        .map(|s| {
+            let gain = s.gain.unwrap_or(1.0);
+            check_gain(gain).map(|()| gain)
+        })
+        .collect()
+}
+
+fn check_gain(gain: f32) -> Result<(), String> {
+    if !(0.0..=MAX_GAIN).contains(&gain) {
+        return Err(format!("Gain must be between 0 and {MAX_GAIN}"));
+    }
+    Ok(())
+}
+
+/// An encoder of `track` per tier of the share, lowest bitrate first.
+fn tier_encoders(tiers: &[u32], track: &TrackInfo) -> anyhow::Result<Vec<codec::Encoder>> {
+    tiers
+        .iter()
+        .map(|&tier| codec::Encoder::with_config(&track.for_tier(tier)))
+        .collect()
+}
+
+/// Emit `event` with news about `sharer`.
+fn emit_from<T: Serialize + Clone>(app: &AppHandle, sharer: EndpointId, event: &str, data: T) {
+    let _ = app.emit(event, FromSharer { sharer, data });
+}
+
+/// Samples waiting in the playback ring for the audio callback.
+fn queued_samples(producer: &rtrb::Producer<f32>) -> usize {
+    producer.buffer().capacity() - producer.slots()
+}
@@ -0,0 +1,452 @@
+//! Congestion-aware bitrate control for the sharer.
+//!
+//! Every listener connection is sampled about once a second: QUIC path stats
+//! (RTT, congestion window, lost packets) from the sharer's side, plus the
+//! latest [`ReceptionReport`] the listener sent back. Each link keeps its own
+//! AIMD estimate — back off multiplicatively on loss, queueing delay or a
+//! congestion window too small for the stream, creep back up additively once
+//! the link has been clean for a while.
+//!
+//! Each link is served the highest encoder tier (simulcast rendition) its
+//! estimate — and the quality the listener asked for — allows. A tier's
+//! encoder follows the slowest link on it, so one constrained listener only
+//! degrades the listeners sharing its tier, and smoothly instead of chopping.
+//! Without simulcast there is a single tier and everyone follows the slowest.
+
+use crate::codec::MIN_BITRATE;
+use crate::protocol::ReceptionReport;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// How often the sharer samples each listener's link.
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Lowest bitrate adaptation will go to, in bits per second.
+pub const MIN_ADAPTIVE_BITRATE: u32 = 12_000;
+/// Multiplier applied to a link's bitrate when it shows congestion.
+const DECREASE_FACTOR: f64 = 0.75;
+/// Added to a link's bitrate per clean sample once it may probe upwards.
+const INCREASE_STEP: u32 = 8_000;
+/// Minimum time between two decreases, so one burst isn't punished twice.
+const DECREASE_HOLDOFF: Duration = Duration::from_secs(2);
+/// How long a link must stay clean after a decrease before probing upwards.
+const INCREASE_HOLDOFF: Duration = Duration::from_secs(5);
+/// Loss fraction above which a link is congested.
+const CONGESTED_LOSS: f64 = 0.05;
+/// Loss fraction below which a link counts as clean.
+const CLEAN_LOSS: f64 = 0.01;
+/// RTT above the link's minimum that indicates a standing queue.
+const MAX_QUEUEING_DELAY: Duration = Duration::from_millis(150);
+/// The congestion window must hold this many RTTs worth of audio.
+const CWND_HEADROOM: f64 = 2.0;
+
+/// One observation of a listener's link.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LinkSample {
+    /// Smoothed RTT of the connection's selected path.
+    pub rtt: Duration,
+    /// Congestion window of the selected path, in bytes.
+    pub cwnd: u64,
+    /// Cumulative UDP datagrams sent on the path.
+    pub sent_packets: u64,
+    /// Cumulative packets QUIC declared lost on the path.
+    pub lost_packets: u64,
+    /// Cumulative frames the sharer skipped because the listener lagged.
+    pub lagged_frames: u64,
+    /// Latest report from the listener, if it has sent one.
+    pub report: Option<ReceptionReport>,
+}
+
+/// What a sample says about a link.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Verdict {
+    Congested,
+    Steady,
+    Clean,
+}
+
+/// Per-link AIMD state.
+#[derive(Debug)]
+struct Link {
+    bitrate: u32,
+    /// Highest bitrate the listener asked for, if it capped its quality.
+    requested: Option<u32>,
+    min_rtt: Option<Duration>,
+    last_sample: Option<LinkSample>,
+    last_decrease: Option<Instant>,
+}
+
+/// Assigns listeners to tiers and picks each tier's encoder bitrate from the
+/// state of the links on it.
+#[derive(Debug)]
+pub struct BitrateController {
+    /// Tier bitrates, lowest first.
+    tiers: Vec<u32>,
+    min: u32,
+    max: u32,
+    links: HashMap<usize, Link>,
+}
+
+impl BitrateController {
+    /// A controller for the given tier bitrates (lowest first, as from
+    /// [`crate::codec::EncoderConfig::tiers`]); no link is ever estimated
+    /// above the highest.
+    pub fn new(tiers: Vec<u32>) -> Self {
+        assert!(!tiers.is_empty(), "at least one tier is required");
+        let min = MIN_ADAPTIVE_BITRATE.clamp(MIN_BITRATE, tiers[0]);
+        let max = tiers[tiers.len() - 1];
+        Self {
+            tiers,
+            min,
+            max,
+            links: HashMap::new(),
+        }
+    }
+
+    /// Start tracking a listener's link at the full bitrate, capped to
+    /// `requested` if the listener asked for lower quality.
+    pub fn add_link(&mut self, id: usize, requested: Option<u32>) {
+        self.links.insert(
+            id,
+            Link {
+                bitrate: self.max,
+                requested,
+                min_rtt: None,
+                last_sample: None,
+                last_decrease: None,
+            },
+        );
+    }
+
+    /// The listener changed the quality it asked for (`None`: the best its
+    /// link sustains).
+    pub fn set_requested(&mut self, id: usize, requested: Option<u32>) {
+        if let Some(link) = self.links.get_mut(&id) {
+            link.requested = requested;
+        }
+    }
+
+    /// Stop tracking a listener's link.
+    pub fn remove_link(&mut self, id: usize) {
+        self.links.remove(&id);
+    }
+
+    /// Feed a new sample for a link and adjust its bitrate.
+    pub fn update(&mut self, id: usize, sample: LinkSample, now: Instant) {
+        let (min, max) = (self.min, self.max);
+        let Some(link) = self.links.get_mut(&id) else {
+            return;
+        };
+
+        let verdict = link.judge(&sample);
+        link.last_sample = Some(sample);
+
+        match verdict {
+            Verdict::Congested => {
+                let can_decrease = link
+                    .last_decrease
+                    .is_none_or(|at| now.duration_since(at) >= DECREASE_HOLDOFF);
+                if can_decrease {
+                    link.bitrate = ((link.bitrate as f64 * DECREASE_FACTOR) as u32).max(min);
+                    link.last_decrease = Some(now);
+                }
+            }
+            Verdict::Clean => {
+                let can_increase = link
+                    .last_decrease
+                    .is_none_or(|at| now.duration_since(at) >= INCREASE_HOLDOFF);
+                if can_increase {
+                    link.bitrate = (link.bitrate + INCREASE_STEP).min(max);
+                }
+            }
+            Verdict::Steady => {}
+        }
+    }
+
+    /// Index of the tier a link should be served: the highest one within
+    /// both its estimate and the quality it requested, else the lowest.
+    pub fn tier(&self, id: usize) -> usize {
+        self.links
+            .get(&id)
+            .map_or(self.tiers.len() - 1, |link| self.tier_of(link))
+    }
+
+    fn tier_of(&self, link: &Link) -> usize {
+        let cap = link.requested.map_or(link.bitrate, |r| r.min(link.bitrate));
+        self.tiers.iter().rposition(|&t| t <= cap).unwrap_or(0)
+    }
+
+    /// Bitrate each tier's encoder should use: its own bitrate, or that of
+    /// the slowest link on it if lower.
+    pub fn targets(&self) -> Vec<u32> {
+        let mut targets = self.tiers.clone();
+        for link in self.links.values() {
+            let tier = self.tier_of(link);
+            targets[tier] = targets[tier].min(link.bitrate);
+        }
+        targets
+    }
+}
+
+impl Link {
+    fn judge(&mut self, sample: &LinkSample) -> Verdict {
+        if !sample.rtt.is_zero() {
+            self.min_rtt = Some(self.min_rtt.map_or(sample.rtt, |m| m.min(sample.rtt)));
+        }
+        let Some(prev) = self.last_sample else {
+            return Verdict::Steady;
+        };
+
+        // Loss as QUIC sees it on the path, and as the listener sees it at
+        // playout (where a frame that arrived too late to play is also lost).
+        let sent = sample.sent_packets.saturating_sub(prev.sent_packets);
+        let lost = sample.lost_packets.saturating_sub(prev.lost_packets);
+        let path_loss = ratio(lost, sent);
+        let playout_loss = match (prev.report, sample.report) {
+            (Some(before), Some(after)) => {
+                let missed = after.lost.saturating_sub(before.lost);
+                let played = after.received.saturating_sub(before.received);
+                ratio(missed, missed + played)
+            }
+            _ => 0.0,
+        };
+        let loss = path_loss.max(playout_loss);
+
+        let lagged = sample.lagged_frames > prev.lagged_frames;
+        let queueing = self
+            .min_rtt
+            .is_some_and(|min| sample.rtt.saturating_sub(min) > MAX_QUEUEING_DELAY);
+        let bytes_per_rtt = self.bitrate as f64 / 8.0 * sample.rtt.as_secs_f64();
+        let cwnd_limited = sample.cwnd > 0 && (sample.cwnd as f64) < bytes_per_rtt * CWND_HEADROOM;
+
+        if lagged || queueing || cwnd_limited || loss > CONGESTED_LOSS {
+            Verdict::Congested
+        } else if loss < CLEAN_LOSS {
+            Verdict::Clean
+        } else {
+            Verdict::Steady
+        }
+    }
+}
+
+fn ratio(part: u64, whole: u64) -> f64 {
+    if whole == 0 {
+        0.0
+    } else {
+        part as f64 / whole as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RTT: Duration = Duration::from_millis(40);
+
+    /// A link that sends 50 packets a second with the given losses so far.
+    fn sample(second: u64, lost: u64) -> LinkSample {
+        LinkSample {
+            rtt: RTT,
+            cwnd: 64 * 1024,
+            sent_packets: second * 50,
+            lost_packets: lost,
+            ..LinkSample::default()
+        }
+    }
+
+    fn controller_with_link(max: u32) -> (BitrateController, Instant) {
+        let mut c = BitrateController::new(vec![max]);
+        c.add_link(1, None);
+        let start = Instant::now();
+        c.update(1, sample(0, 0), start);
+        (c, start)
+    }
+
+    fn at(start: Instant, second: u64) -> Instant {
+        start + Duration::from_secs(second)
+    }
+
+    #[test]
+    fn no_links_means_full_bitrate() {
+        assert_eq!(BitrateController::new(vec![64_000]).targets()[0], 64_000);
+    }
+
+    #[test]
+    fn clean_link_stays_at_max() {
+        let (mut c, start) = controller_with_link(64_000);
+        for s in 1..10 {
+            c.update(1, sample(s, 0), at(start, s));
+        }
+        assert_eq!(c.targets()[0], 64_000);
+    }
+
+    #[test]
+    fn path_loss_backs_off_once_per_holdoff() {
+        let (mut c, start) = controller_with_link(64_000);
+        c.update(1, sample(1, 10), at(start, 1));
+        assert_eq!(c.targets()[0], 48_000);
+        // Still lossy a second later, but within the hold-off.
+        c.update(1, sample(2, 20), at(start, 2));
+        assert_eq!(c.targets()[0], 48_000);
+        c.update(1, sample(3, 30), at(start, 3));
+        assert_eq!(c.targets()[0], 36_000);
+    }
+
+    #[test]
+    fn recovers_additively_after_holdoff() {
+        let (mut c, start) = controller_with_link(64_000);
+        c.update(1, sample(1, 10), at(start, 1));
+        assert_eq!(c.targets()[0], 48_000);
+
+        for s in 2..6 {
+            c.update(1, sample(s, 10), at(start, s));
+        }
+        assert_eq!(c.targets()[0], 48_000, "probed too soon");
+
+        c.update(1, sample(6, 10), at(start, 6));
+        assert_eq!(c.targets()[0], 56_000);
+        c.update(1, sample(7, 10), at(start, 7));
+        c.update(1, sample(8, 10), at(start, 8));
+        assert_eq!(c.targets()[0], 64_000, "capped at the sharer's bitrate");
+    }
+
+    #[test]
+    fn never_drops_below_floor() {
+        let (mut c, start) = controller_with_link(64_000);
+        for s in 1..40 {
+            c.update(1, sample(s, s * 25), at(start, s * 2));
+        }
+        assert_eq!(c.targets()[0], MIN_ADAPTIVE_BITRATE);
+    }
+
+    #[test]
+    fn rising_rtt_is_congestion() {
+        let (mut c, start) = controller_with_link(64_000);
+        let bloated = LinkSample {
+            rtt: RTT + Duration::from_millis(300),
+            ..sample(1, 0)
+        };
+        c.update(1, bloated, at(start, 1));
+        assert!(c.targets()[0] < 64_000);
+    }
+
+    #[test]
+    fn small_congestion_window_is_congestion() {
+        let (mut c, start) = controller_with_link(160_000);
+        let squeezed = LinkSample {
+            rtt: Duration::from_millis(200),
+            cwnd: 4_000,
+            ..sample(1, 0)
+        };
+        c.update(1, squeezed, at(start, 1));
+        assert!(c.targets()[0] < 160_000);
+    }
+
+    #[test]
+    fn lagging_listener_is_congestion() {
+        let (mut c, start) = controller_with_link(64_000);
+        let lagged = LinkSample {
+            lagged_frames: 12,
+            ..sample(1, 0)
+        };
+        c.update(1, lagged, at(start, 1));
+        assert!(c.targets()[0] < 64_000);
+    }
+
+    #[test]
+    fn listener_reported_loss_is_congestion() {
+        let mut c = BitrateController::new(vec![64_000]);
+        c.add_link(1, None);
+        let start = Instant::now();
+        let report = |received, lost| ReceptionReport {
+            received,
+            lost,
+            late: 0,
+            jitter_us: 2_000,
+            latency_us: None,
+        };
+        c.update(
+            1,
+            LinkSample {
+                report: Some(report(100, 0)),
+                ..sample(0, 0)
+            },
+            start,
+        );
+        // The path looks clean, but 10 of the last 50 frames never played.
+        c.update(
+            1,
+            LinkSample {
+                report: Some(report(140, 10)),
+                ..sample(1, 0)
+            },
+            at(start, 1),
+        );
+        assert_eq!(c.targets()[0], 48_000);
+    }
+
+    #[test]
+    fn follows_the_slowest_link() {
+        let (mut c, start) = controller_with_link(64_000);
+        c.add_link(2, None);
+        c.update(2, sample(0, 0), start);
+
+        c.update(1, sample(1, 0), at(start, 1));
+        c.update(2, sample(1, 10), at(start, 1));
+        assert_eq!(c.targets()[0], 48_000);
+
+        // The slow listener leaves; everyone else gets full quality back.
+        c.remove_link(2);
+        assert_eq!(c.targets()[0], 64_000);
+    }
+
+    #[test]
+    fn low_max_bitrate_is_its_own_floor() {
+        let (mut c, start) = controller_with_link(8_000);
+        c.update(1, sample(1, 25), at(start, 1));
+        assert_eq!(c.targets()[0], 8_000);
+    }
+
+    #[test]
+    fn links_get_the_best_tier_they_sustain() {
+        let mut c = BitrateController::new(vec![32_000, 64_000, 128_000]);
+        c.add_link(1, None);
+        c.add_link(2, None);
+        let start = Instant::now();
+        c.update(1, sample(0, 0), start);
+        c.update(2, sample(0, 0), start);
+        assert_eq!((c.tier(1), c.tier(2)), (2, 2));
+
+        // Link 2 backs off to 96kbps and drops to the 64kbps rendition...
+        c.update(2, sample(1, 10), at(start, 1));
+        assert_eq!((c.tier(1), c.tier(2)), (2, 1));
+        // ...without pulling link 1's rendition down.
+        assert_eq!(c.targets(), vec![32_000, 64_000, 128_000]);
+    }
+
+    #[test]
+    fn lowest_tier_adapts_below_its_bitrate() {
+        let mut c = BitrateController::new(vec![32_000, 64_000]);
+        c.add_link(1, None);
+        let start = Instant::now();
+        c.update(1, sample(0, 0), start);
+        for s in 1..5 {
+            c.update(1, sample(s, s * 25), at(start, s * 2));
+        }
+        assert_eq!(c.tier(1), 0);
+        assert!(c.targets()[0] < 32_000, "{:?}", c.targets());
+        assert_eq!(c.targets()[1], 64_000);
+    }
+
+    #[test]
+    fn requested_quality_caps_the_tier() {
+        let mut c = BitrateController::new(vec![32_000, 64_000, 128_000]);
+        c.add_link(1, Some(64_000));
+        assert_eq!(c.tier(1), 1);
+        c.set_requested(1, Some(10_000));
+        assert_eq!(c.tier(1), 0);
+        assert_eq!(c.targets(), vec![32_000, 64_000, 128_000]);
+        c.set_requested(1, None);
+        assert_eq!(c.tier(1), 2);
+    }
+}
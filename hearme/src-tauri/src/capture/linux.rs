@@ -4,9 +4,24 @@
 //! 1. Connect to PipeWire, enumerate nodes with `media.class = "Stream/Output/Audio"`
 //! 2. Match by `application.name` to build the source list
 //! 3. To capture, create a PipeWire stream targeting the app's output node
+//!
+//! What the app plays comes from its MPRIS player on the D-Bus session bus,
+//! found by process id (PipeWire's `application.process.id`) or name.
 
-use super::{AudioSource, CHANNELS, CaptureHandle, SAMPLE_RATE, SAMPLES_PER_FRAME};
-use tokio::sync::mpsc;
+use super::now_playing::{
+    NowPlayingFilter, NowPlayingWatcher, POLL_INTERVAL, PlayerState, mpris_player_name,
+    player_matches,
+};
+use super::{
+    AudioSource, CHANNELS, CaptureHandle, PcmFrame, SAMPLE_RATE, SAMPLES_PER_FRAME, SourceKind,
+};
+use crate::protocol::NowPlaying;
+use std::collections::HashMap;
+use std::time::Instant;
+use tokio::sync::{mpsc, watch};
+use zbus::names::BusName;
+use zbus::proxy::CacheProperties;
+use zbus::zvariant::{OwnedValue, Value};
 
 /// List applications currently outputting audio via PipeWire.
 pub async fn list_sources() -> anyhow::Result<Vec<AudioSource>> {
@@ -46,7 +61,15 @@ fn list_sources_sync() -> anyhow::Result<Vec<AudioSource>> {
                         .unwrap_or("Unknown")
                         .to_string();
                     let id = global.id.to_string();
-                    sources_clone.borrow_mut().push(AudioSource { id, name });
+                    let pid = props
+                        .get("application.process.id")
+                        .and_then(|pid| pid.parse().ok());
+                    sources_clone.borrow_mut().push(AudioSource {
+                        id,
+                        name,
+                        pid,
+                        kind: SourceKind::App,
+                    });
                 }
             }
         })
@@ -74,9 +97,9 @@ fn list_sources_sync() -> anyhow::Result<Vec<AudioSource>> {
 /// Start capturing audio from a specific PipeWire node.
 pub async fn start_capture(
     source: &AudioSource,
-) -> anyhow::Result<(CaptureHandle, mpsc::Receiver<Vec<f32>>)> {
+) -> anyhow::Result<(CaptureHandle, mpsc::Receiver<PcmFrame>)> {
     let node_id: u32 = source.id.parse()?;
-    let (tx, rx) = mpsc::channel::<Vec<f32>>(64);
+    let (tx, rx) = mpsc::channel::<PcmFrame>(64);
     let (stop_tx, _stop_rx) = tokio::sync::oneshot::channel::<()>();
 
     tokio::task::spawn_blocking(move || {
@@ -86,7 +109,7 @@ pub async fn start_capture(
     Ok((CaptureHandle::new(stop_tx), rx))
 }
 
-fn capture_loop(target_node_id: u32, tx: mpsc::Sender<Vec<f32>>) {
+fn capture_loop(target_node_id: u32, tx: mpsc::Sender<PcmFrame>) {
     use pipewire as pw;
     use pw::spa::param::audio::{AudioFormat, AudioInfoRaw};
     use pw::spa::pod::Pod;
@@ -137,6 +160,7 @@ fn capture_loop(target_node_id: u32, tx: mpsc::Sender<Vec<f32>>) {
     let _listener = stream
         .add_local_listener::<()>()
         .process(move |stream, _| {
+            let now = Instant::now();
             if let Some(mut buffer) = stream.dequeue_buffer()
                 && let Some(slice) = buffer.datas_mut().first_mut().and_then(|data| data.data())
             {
@@ -146,8 +170,7 @@ fn capture_loop(target_node_id: u32, tx: mpsc::Sender<Vec<f32>>) {
                 acc.extend_from_slice(samples);
 
                 // Emit complete frames (20ms = SAMPLES_PER_FRAME)
-                while acc.len() >= SAMPLES_PER_FRAME {
-                    let frame: Vec<f32> = acc.drain(..SAMPLES_PER_FRAME).collect();
+                for frame in super::drain_frames(&mut acc, now) {
                     let _ = tx_clone.try_send(frame);
                 }
             }
@@ -176,3 +199,254 @@ fn bytemuck_cast_slice(bytes: &[u8]) -> &[f32] {
     let len = bytes.len() / 4;
     unsafe { std::slice::from_raw_parts(bytes.as_ptr() as *const f32, len) }
 }
+
+#[zbus::proxy(
+    interface = "org.mpris.MediaPlayer2",
+    default_path = "/org/mpris/MediaPlayer2"
+)]
+trait MediaPlayer2 {
+    #[zbus(property)]
+    fn identity(&self) -> zbus::Result<String>;
+}
+
+#[zbus::proxy(
+    interface = "org.mpris.MediaPlayer2.Player",
+    default_path = "/org/mpris/MediaPlayer2"
+)]
+trait Player {
+    #[zbus(property)]
+    fn metadata(&self) -> zbus::Result<HashMap<String, OwnedValue>>;
+    #[zbus(property)]
+    fn playback_status(&self) -> zbus::Result<String>;
+    #[zbus(property)]
+    fn position(&self) -> zbus::Result<i64>;
+}
+
+/// Follow what `source`'s MPRIS player plays.
+pub fn watch_now_playing(source: &AudioSource) -> NowPlayingWatcher {
+    let (tx, rx) = watch::channel(None);
+    let (name, pid) = (source.name.clone(), source.pid);
+    let task = tokio::spawn(async move {
+        if let Err(e) = follow_player(&name, pid, &tx).await {
+            tracing::warn!("Not following what {name} plays: {e}");
+        }
+    });
+    NowPlayingWatcher::new(rx, Some(task))
+}
+
+async fn follow_player(
+    app_name: &str,
+    app_pid: Option<u32>,
+    tx: &watch::Sender<Option<NowPlaying>>,
+) -> anyhow::Result<()> {
+    let connection = zbus::Connection::session().await?;
+    let dbus = zbus::fdo::DBusProxy::new(&connection).await?;
+    let mut filter = NowPlayingFilter::default();
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        // Players come and go (some only appear once they play), so look
+        // for the app's every time
+        let reading = match find_player(&connection, &dbus, app_name, app_pid).await {
+            Ok(Some(player)) => read_player(&player)
+                .await
+                .ok()
+                .and_then(PlayerState::now_playing),
+            Ok(None) => None,
+            // The bus may only be busy; keep what we had and ask again
+            Err(e) => {
+                tracing::warn!("Failed to look for {app_name}'s player: {e}");
+                continue;
+            }
+        };
+        if filter.is_news(&reading, Instant::now()) {
+            tx.send_replace(reading);
+        }
+    }
+}
+
+/// The MPRIS player of the app `app_name` running as `app_pid`, if it has
+/// one on the bus.
+async fn find_player<'a>(
+    connection: &'a zbus::Connection,
+    dbus: &zbus::fdo::DBusProxy<'_>,
+    app_name: &str,
+    app_pid: Option<u32>,
+) -> anyhow::Result<Option<PlayerProxy<'a>>> {
+    for name in dbus.list_names().await? {
+        let Some(player_name) = mpris_player_name(name.as_str()) else {
+            continue;
+        };
+        let Ok(bus_name) = BusName::try_from(name.to_string()) else {
+            continue;
+        };
+        let pid = dbus
+            .get_connection_unix_process_id(bus_name.clone())
+            .await
+            .ok();
+        let mut matches = player_matches(&[player_name], pid, app_name, app_pid);
+        // A player we can't ask just isn't the one we look for
+        if !matches && let Ok(identity) = player_identity(connection, bus_name.clone()).await {
+            matches = player_matches(&[&identity], None, app_name, None);
+        }
+        if matches {
+            let player = PlayerProxy::builder(connection)
+                .destination(bus_name)?
+                .cache_properties(CacheProperties::No)
+                .build()
+                .await?;
+            return Ok(Some(player));
+        }
+    }
+    Ok(None)
+}
+
+/// The name the player at `bus_name` goes by.
+async fn player_identity(
+    connection: &zbus::Connection,
+    bus_name: BusName<'_>,
+) -> zbus::Result<String> {
+    MediaPlayer2Proxy::builder(connection)
+        .destination(bus_name)?
+        .cache_properties(CacheProperties::No)
+        .build()
+        .await?
+        .identity()
+        .await
+}
+
+async fn read_player(player: &PlayerProxy<'_>) -> zbus::Result<PlayerState> {
+    let metadata = player.metadata().await?;
+    let field = |key: &str| metadata.get(key).and_then(|v| text(v));
+    Ok(PlayerState {
+        title: field("xesam:title"),
+        artists: metadata
+            .get("xesam:artist")
+            .map(|v| texts(v))
+            .unwrap_or_default(),
+        album: field("xesam:album"),
+        art_url: field("mpris:artUrl"),
+        length_us: metadata.get("mpris:length").and_then(|v| integer(v)),
+        // Not every player knows where it is
+        position_us: player.position().await.ok(),
+        status: player.playback_status().await?,
+    })
+}
+
+/// `value` with any variant wrapping it taken off.
+fn unwrapped<'a>(value: &'a Value<'a>) -> &'a Value<'a> {
+    match value {
+        Value::Value(inner) => unwrapped(inner),
+        value => value,
+    }
+}
+
+fn text(value: &Value<'_>) -> Option<String> {
+    match unwrapped(value) {
+        Value::Str(s) if !s.as_str().is_empty() => Some(s.as_str().to_owned()),
+        Value::ObjectPath(path) => Some(path.as_str().to_owned()),
+        _ => None,
+    }
+}
+
+/// A list of strings, which some players send as just the one.
+fn texts(value: &Value<'_>) -> Vec<String> {
+    match unwrapped(value) {
+        Value::Array(array) => array.iter().filter_map(text).collect(),
+        value => text(value).into_iter().collect(),
+    }
+}
+
+/// An integer, whichever width and signedness the player sends.
+fn integer(value: &Value<'_>) -> Option<i64> {
+    match unwrapped(value) {
+        Value::I64(n) => Some(*n),
+        Value::U64(n) => i64::try_from(*n).ok(),
+        Value::I32(n) => Some(i64::from(*n)),
+        Value::U32(n) => Some(i64::from(*n)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// A media player, as far as hearme asks.
+    struct MockPlayer;
+
+    #[zbus::interface(name = "org.mpris.MediaPlayer2.Player")]
+    impl MockPlayer {
+        #[zbus(property)]
+        fn metadata(&self) -> HashMap<String, OwnedValue> {
+            let value = |v: Value<'_>| OwnedValue::try_from(v).unwrap();
+            HashMap::from([
+                ("xesam:title".into(), value("Blue in Green".into())),
+                ("xesam:artist".into(), value(vec!["Miles Davis"].into())),
+                ("xesam:album".into(), value("Kind of Blue".into())),
+                (
+                    "mpris:artUrl".into(),
+                    value("https://example.com/kind-of-blue.jpg".into()),
+                ),
+                ("mpris:length".into(), value(337_000_000i64.into())),
+            ])
+        }
+
+        #[zbus(property)]
+        fn playback_status(&self) -> String {
+            "Paused".into()
+        }
+
+        #[zbus(property)]
+        fn position(&self) -> i64 {
+            12_000_000
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a D-Bus session bus"]
+    async fn reads_what_a_player_on_the_bus_plays() {
+        let _player = zbus::connection::Builder::session()
+            .unwrap()
+            .name("org.mpris.MediaPlayer2.hearmetest")
+            .unwrap()
+            .serve_at("/org/mpris/MediaPlayer2", MockPlayer)
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+
+        // The player runs in this very process
+        let source = AudioSource {
+            id: "0".into(),
+            name: "Someone else".into(),
+            pid: Some(std::process::id()),
+            kind: SourceKind::App,
+        };
+        let watcher = watch_now_playing(&source);
+        let mut now_playing = watcher.now_playing();
+        let now_playing = tokio::time::timeout(
+            Duration::from_secs(5),
+            now_playing.wait_for(Option::is_some),
+        )
+        .await
+        .expect("player found")
+        .unwrap()
+        .clone()
+        .unwrap();
+
+        assert_eq!(
+            now_playing,
+            NowPlaying {
+                title: Some("Blue in Green".into()),
+                artist: Some("Miles Davis".into()),
+                album: Some("Kind of Blue".into()),
+                art_url: Some("https://example.com/kind-of-blue.jpg".into()),
+                length_ms: Some(337_000),
+                position_ms: Some(12_000),
+                playing: false,
+            }
+        );
+    }
+}
@@ -4,7 +4,10 @@
 //! ScreenCaptureKit can capture audio from a specific application without
 //! any virtual audio device.
 
-use super::{AudioSource, CHANNELS, CaptureHandle, SAMPLE_RATE, SAMPLES_PER_FRAME};
+use super::{
+    AudioSource, CHANNELS, CaptureHandle, PcmFrame, SAMPLE_RATE, SAMPLES_PER_FRAME, SourceKind,
+};
+use std::time::Instant;
 use tokio::sync::mpsc;
 
 pub async fn list_sources() -> anyhow::Result<Vec<AudioSource>> {
@@ -23,6 +26,8 @@ pub async fn list_sources() -> anyhow::Result<Vec<AudioSource>> {
                 .application_name
                 .clone()
                 .unwrap_or_else(|| app.bundle_identifier.clone()),
+            pid: None,
+            kind: SourceKind::App,
         })
         .collect();
 
@@ -31,7 +36,7 @@ pub async fn list_sources() -> anyhow::Result<Vec<AudioSource>> {
 
 pub async fn start_capture(
     source: &AudioSource,
-) -> anyhow::Result<(CaptureHandle, mpsc::Receiver<Vec<f32>>)> {
+) -> anyhow::Result<(CaptureHandle, mpsc::Receiver<PcmFrame>)> {
     use screencapturekit::{
         content_filter::{InitParams, SCContentFilter},
         output::SCStreamOutputType,
@@ -75,7 +80,7 @@ pub async fn start_capture(
         ..Default::default()
     };
 
-    let (tx, rx) = mpsc::channel::<Vec<f32>>(64);
+    let (tx, rx) = mpsc::channel::<PcmFrame>(64);
     let (stop_tx, _stop_rx) = tokio::sync::oneshot::channel::<()>();
 
     // Create and start the stream
@@ -89,6 +94,7 @@ pub async fn start_capture(
     let tx_clone = tx.clone();
 
     stream.add_output_handler(SCStreamOutputType::Audio, move |sample_buffer| {
+        let now = Instant::now();
         // Extract PCM f32 data from CMSampleBuffer
         if let Some(audio_buffer) = sample_buffer.audio_buffer_list() {
             for buffer in audio_buffer.buffers() {
@@ -102,8 +108,7 @@ pub async fn start_capture(
                 let mut acc = acc_clone.lock().unwrap();
                 acc.extend_from_slice(samples);
 
-                while acc.len() >= SAMPLES_PER_FRAME {
-                    let frame: Vec<f32> = acc.drain(..SAMPLES_PER_FRAME).collect();
+                for frame in super::drain_frames(&mut acc, now) {
                     let _ = tx_clone.try_send(frame);
                 }
             }
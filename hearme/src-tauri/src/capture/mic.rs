@@ -0,0 +1,291 @@
+//! Microphone capture, for talking over the shared audio.
+//!
+//! Unlike applications, which each platform captures its own way,
+//! microphones are recorded from cpal's input devices everywhere. Whether
+//! the sharer is heard is up to a [`MicGate`]: always, while a push-to-talk
+//! key is held, or while they are speaking.
+
+use super::{
+    AudioSource, CHANNELS, CaptureHandle, PcmFrame, SAMPLE_RATE, SAMPLES_PER_FRAME, SourceKind,
+};
+use anyhow::{Context, Result};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{FromSample, Sample, SampleFormat, SizedSample};
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+use tokio::sync::{mpsc, oneshot};
+
+/// Frames a voice-activated gate stays open after the voice drops below the
+/// threshold (300ms), so the quiet ends of words aren't cut off.
+const VOICE_HANGOVER_FRAMES: u32 = 15;
+/// Quietest threshold a voice-activated gate can be set to, in dBFS.
+pub const MIN_THRESHOLD_DB: f32 = -80.0;
+
+/// When a microphone is heard.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum GateMode {
+    /// All the time.
+    #[default]
+    Open,
+    /// While the talk key is held.
+    PushToTalk,
+    /// While it picks up more than `threshold_db` (dBFS).
+    VoiceActivity { threshold_db: f32 },
+}
+
+impl GateMode {
+    /// Check the threshold is in range.
+    pub fn validate(&self) -> Result<()> {
+        if let Self::VoiceActivity { threshold_db } = self {
+            anyhow::ensure!(
+                (MIN_THRESHOLD_DB..=0.0).contains(threshold_db),
+                "Voice threshold must be between {MIN_THRESHOLD_DB} and 0 dB"
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Silences a microphone's frames while it shouldn't be heard.
+#[derive(Debug, Default)]
+pub struct MicGate {
+    open: bool,
+    /// Frames left before a voice-activated gate closes.
+    hangover: u32,
+}
+
+impl MicGate {
+    /// Silence `pcm` unless `mode` lets it through, `talk_held` saying
+    /// whether the push-to-talk key is held. Returns whether the gate is
+    /// open. Opening and closing fade over the frame, so they don't click.
+    pub fn apply(&mut self, pcm: &mut [f32], mode: GateMode, talk_held: bool) -> bool {
+        let was_open = self.open;
+        self.open = match mode {
+            GateMode::Open => true,
+            GateMode::PushToTalk => talk_held,
+            GateMode::VoiceActivity { threshold_db } => {
+                if level_db(pcm) >= threshold_db {
+                    self.hangover = VOICE_HANGOVER_FRAMES;
+                    true
+                } else if self.hangover > 0 {
+                    self.hangover -= 1;
+                    true
+                } else {
+                    false
+                }
+            }
+        };
+        match (was_open, self.open) {
+            (true, true) => {}
+            (false, false) => pcm.fill(0.0),
+            (_, opening) => {
+                let frames = pcm.len() / CHANNELS as usize;
+                for (i, frame) in pcm.chunks_mut(CHANNELS as usize).enumerate() {
+                    let ramp = i as f32 / frames as f32;
+                    let gain = if opening { ramp } else { 1.0 - ramp };
+                    frame.iter_mut().for_each(|s| *s *= gain);
+                }
+            }
+        }
+        self.open
+    }
+}
+
+/// The RMS level of `pcm`, in dBFS.
+pub fn level_db(pcm: &[f32]) -> f32 {
+    let power = pcm.iter().map(|s| s * s).sum::<f32>() / pcm.len().max(1) as f32;
+    10.0 * power.log10()
+}
+
+/// List the microphones (input devices), the default one first.
+pub fn list_microphones() -> Result<Vec<AudioSource>> {
+    let host = cpal::default_host();
+    let default_id = host.default_input_device().and_then(|d| d.id().ok());
+    let mut mics = Vec::new();
+    for device in host.input_devices()? {
+        let (Ok(id), Ok(description)) = (device.id(), device.description()) else {
+            continue;
+        };
+        let mic = AudioSource {
+            id: id.to_string(),
+            name: description.name().to_string(),
+            pid: None,
+            kind: SourceKind::Microphone,
+        };
+        match default_id.as_ref() == Some(&id) {
+            true => mics.insert(0, mic),
+            false => mics.push(mic),
+        }
+    }
+    Ok(mics)
+}
+
+/// Start recording from a microphone listed by [`list_microphones`].
+pub async fn start_capture(
+    source: &AudioSource,
+) -> Result<(CaptureHandle, mpsc::Receiver<PcmFrame>)> {
+    let id: cpal::DeviceId = source.id.parse().context("Invalid microphone id")?;
+    let (tx, rx) = mpsc::channel::<PcmFrame>(64);
+    let (stop_tx, stop_rx) = oneshot::channel::<()>();
+    let (ready_tx, ready_rx) = oneshot::channel();
+
+    // Not every platform lets a cpal stream move between threads, so it
+    // lives on one of its own until the handle is dropped.
+    std::thread::spawn(move || {
+        let stream = match open_stream(&id, tx) {
+            Ok(stream) => stream,
+            Err(e) => {
+                let _ = ready_tx.send(Err(e));
+                return;
+            }
+        };
+        let _ = ready_tx.send(Ok(()));
+        let _ = stop_rx.blocking_recv();
+        drop(stream);
+    });
+
+    ready_rx.await.context("Microphone capture thread died")??;
+    Ok((CaptureHandle::new(stop_tx), rx))
+}
+
+/// Open and start a 48kHz stream of the microphone `id`, sending its audio
+/// on `tx` in stereo frames.
+fn open_stream(id: &cpal::DeviceId, tx: mpsc::Sender<PcmFrame>) -> Result<cpal::Stream> {
+    let device = cpal::default_host()
+        .device_by_id(id)
+        .context("Microphone not found")?;
+    let supported = device
+        .supported_input_configs()?
+        .filter(|c| matches!(c.sample_format(), SampleFormat::F32 | SampleFormat::I16))
+        .filter_map(|c| c.try_with_sample_rate(SAMPLE_RATE))
+        // Float if it can, stereo if it can
+        .max_by_key(|c| {
+            (
+                c.sample_format() == SampleFormat::F32,
+                c.channels() == CHANNELS,
+            )
+        })
+        .context("Microphone can't record at 48 kHz")?;
+    let channels = supported.channels() as usize;
+    let config = supported.config();
+    let stream = match supported.sample_format() {
+        SampleFormat::I16 => build_stream::<i16>(&device, &config, channels, tx)?,
+        _ => build_stream::<f32>(&device, &config, channels, tx)?,
+    };
+    stream.play()?;
+    Ok(stream)
+}
+
+fn build_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    channels: usize,
+    tx: mpsc::Sender<PcmFrame>,
+) -> Result<cpal::Stream>
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+{
+    let mut acc = Vec::with_capacity(SAMPLES_PER_FRAME * 2);
+    let stream = device.build_input_stream(
+        config,
+        move |data: &[T], _: &cpal::InputCallbackInfo| {
+            let now = Instant::now();
+            push_stereo(
+                data.iter().map(|&s| f32::from_sample(s)),
+                channels,
+                &mut acc,
+            );
+
+            // Emit complete frames (20ms = SAMPLES_PER_FRAME)
+            for frame in super::drain_frames(&mut acc, now) {
+                let _ = tx.try_send(frame);
+            }
+        },
+        |err| {
+            tracing::error!("Microphone error: {err}");
+        },
+        None,
+    )?;
+    Ok(stream)
+}
+
+/// Append interleaved samples of `channels` channels to `out` in stereo: a
+/// mono microphone is heard on both sides, and channels past the second are
+/// left out.
+fn push_stereo(samples: impl Iterator<Item = f32>, channels: usize, out: &mut Vec<f32>) {
+    for (i, sample) in samples.enumerate() {
+        match (channels, i % channels) {
+            (1, _) => out.extend([sample, sample]),
+            (_, 0 | 1) => out.push(sample),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(value: f32) -> Vec<f32> {
+        vec![value; SAMPLES_PER_FRAME]
+    }
+
+    #[test]
+    fn microphones_are_recorded_in_stereo() {
+        let mut out = Vec::new();
+        push_stereo([0.1, 0.2].into_iter(), 1, &mut out);
+        assert_eq!(out, [0.1, 0.1, 0.2, 0.2]);
+
+        out.clear();
+        push_stereo([0.1, 0.2, 0.3, 0.4, 0.5, 0.6].into_iter(), 3, &mut out);
+        assert_eq!(out, [0.1, 0.2, 0.4, 0.5]);
+    }
+
+    #[test]
+    fn push_to_talk_fades_in_and_out() {
+        let mut gate = MicGate::default();
+        let mut pcm = frame(0.5);
+        assert!(!gate.apply(&mut pcm, GateMode::PushToTalk, false));
+        assert_eq!(pcm, frame(0.0));
+
+        let mut pcm = frame(0.5);
+        assert!(gate.apply(&mut pcm, GateMode::PushToTalk, true));
+        assert_eq!(pcm[0], 0.0);
+        assert!(pcm[SAMPLES_PER_FRAME - 1] > 0.49);
+
+        let mut pcm = frame(0.5);
+        assert!(gate.apply(&mut pcm, GateMode::PushToTalk, true));
+        assert_eq!(pcm, frame(0.5));
+
+        let mut pcm = frame(0.5);
+        assert!(!gate.apply(&mut pcm, GateMode::PushToTalk, false));
+        assert_eq!(pcm[0], 0.5);
+        assert!(pcm[SAMPLES_PER_FRAME - 1] < 0.01);
+    }
+
+    #[test]
+    fn voice_gate_holds_open_past_the_last_word() {
+        let mode = GateMode::VoiceActivity {
+            threshold_db: -30.0,
+        };
+        assert!((level_db(&frame(0.1)) + 20.0).abs() < 0.01);
+
+        let mut gate = MicGate::default();
+        assert!(!gate.apply(&mut frame(0.01), mode, false));
+        assert!(gate.apply(&mut frame(0.1), mode, false));
+        for _ in 0..VOICE_HANGOVER_FRAMES {
+            assert!(gate.apply(&mut frame(0.01), mode, false));
+        }
+        assert!(!gate.apply(&mut frame(0.01), mode, false));
+
+        // The talk key means nothing here
+        assert!(!gate.apply(&mut frame(0.0), mode, true));
+        assert!(mode.validate().is_ok());
+        let too_quiet = GateMode::VoiceActivity {
+            threshold_db: -120.0,
+        };
+        assert!(too_quiet.validate().is_err());
+    }
+}
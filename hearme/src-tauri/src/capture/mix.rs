@@ -0,0 +1,227 @@
+//! Sharing several captured sources at once.
+//!
+//! A share can capture more than one application, say a game and a voice
+//! chat. How listeners get them is the share's [`SourceLayout`]: summed into
+//! one stream by a [`SourceMixer`], or each as a track of its own that
+//! listeners turn on and off as they like. Either way every source is
+//! scaled by its own gain first.
+
+use super::{PcmFrame, SAMPLES_PER_FRAME};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use tokio::sync::mpsc;
+
+/// Frames one source may get ahead of the others before the mix stops
+/// waiting for them (they went quiet and their capture stopped producing).
+const MAX_QUEUED_FRAMES: usize = 3;
+
+/// How the sources of a share reach listeners.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SourceLayout {
+    /// Mixed into one stream.
+    #[default]
+    Mixed,
+    /// One track per source, in the order given.
+    Tracks,
+}
+
+/// What one of several captures produced, by its index.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Captured {
+    Frame(usize, PcmFrame),
+    /// The capture ended; nothing follows from it.
+    Ended(usize),
+}
+
+/// Receive the frames of several captures on one channel, as they come.
+/// The channel closes once every capture has ended.
+pub fn merge(captures: Vec<mpsc::Receiver<PcmFrame>>) -> mpsc::Receiver<Captured> {
+    let (tx, rx) = mpsc::channel(64);
+    for (source, mut capture) in captures.into_iter().enumerate() {
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            while let Some(frame) = capture.recv().await {
+                if tx.send(Captured::Frame(source, frame)).await.is_err() {
+                    return;
+                }
+            }
+            let _ = tx.send(Captured::Ended(source)).await;
+        });
+    }
+    rx
+}
+
+/// Scale a captured frame by `gain`.
+pub fn apply_gain(pcm: &mut [f32], gain: f32) {
+    if gain != 1.0 {
+        pcm.iter_mut().for_each(|sample| *sample *= gain);
+    }
+}
+
+/// Sum a frame from each of `sources` into one. Loud sources together may
+/// go past full scale, so the sum is clamped to it.
+pub fn sum_and_clamp<S: IntoIterator<Item = f32>>(
+    sources: impl IntoIterator<Item = S>,
+) -> Vec<f32> {
+    let mut frame = vec![0.0; SAMPLES_PER_FRAME];
+    for pcm in sources {
+        for (sum, sample) in frame.iter_mut().zip(pcm) {
+            *sum += sample;
+        }
+    }
+    frame.iter_mut().for_each(|s| *s = s.clamp(-1.0, 1.0));
+    frame
+}
+
+/// Sums frames captured from several sources into one stream.
+///
+/// Each source's capture runs at its own pace, so frames are queued per
+/// source and mixed once every source has one. A source that falls silent
+/// isn't waited for: once another has [`MAX_QUEUED_FRAMES`] queued, the mix
+/// goes on without it until it captures again. An ended source is never
+/// waited for again.
+#[derive(Debug)]
+pub struct SourceMixer {
+    queues: Vec<VecDeque<PcmFrame>>,
+    /// Whether the mix waits for each source.
+    waited_for: Vec<bool>,
+    ended: Vec<bool>,
+}
+
+impl SourceMixer {
+    pub fn new(sources: usize) -> Self {
+        Self {
+            queues: vec![VecDeque::new(); sources],
+            waited_for: vec![true; sources],
+            ended: vec![false; sources],
+        }
+    }
+
+    /// Take a frame captured from `source`. Returns the frames of the mix
+    /// this completes, oldest first.
+    pub fn push(&mut self, source: usize, frame: PcmFrame) -> Vec<PcmFrame> {
+        self.queues[source].push_back(frame);
+        self.waited_for[source] = !self.ended[source];
+        self.ready()
+    }
+
+    /// `source` will capture nothing more. Returns the frames of the mix
+    /// that were only waiting for it.
+    pub fn end(&mut self, source: usize) -> Vec<PcmFrame> {
+        self.ended[source] = true;
+        self.waited_for[source] = false;
+        self.ready()
+    }
+
+    fn ready(&mut self) -> Vec<PcmFrame> {
+        if self.queues.iter().any(|q| q.len() >= MAX_QUEUED_FRAMES) {
+            for (queue, waited_for) in self.queues.iter().zip(&mut self.waited_for) {
+                *waited_for &= !queue.is_empty();
+            }
+        }
+        let mut mixed = Vec::new();
+        loop {
+            let all_here = self
+                .queues
+                .iter()
+                .zip(&self.waited_for)
+                .all(|(queue, &waited_for)| !waited_for || !queue.is_empty());
+            if !all_here || self.queues.iter().all(VecDeque::is_empty) {
+                return mixed;
+            }
+            let frames: Vec<_> = (self.queues.iter_mut())
+                .filter_map(VecDeque::pop_front)
+                .collect();
+            // The mix is as old as the oldest frame in it
+            let captured_at =
+                (frames.iter().map(|f| f.captured_at).min()).expect("a source had a frame");
+            mixed.push(PcmFrame {
+                pcm: sum_and_clamp(frames.into_iter().map(|f| f.pcm)),
+                captured_at,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::LazyLock;
+    use std::time::{Duration, Instant};
+
+    static CAPTURED_AT: LazyLock<Instant> = LazyLock::new(Instant::now);
+
+    fn frame(value: f32) -> PcmFrame {
+        PcmFrame {
+            pcm: vec![value; SAMPLES_PER_FRAME],
+            captured_at: *CAPTURED_AT,
+        }
+    }
+
+    #[test]
+    fn sources_are_mixed_once_each_has_a_frame() {
+        let mut mixer = SourceMixer::new(2);
+        assert!(mixer.push(0, frame(0.25)).is_empty());
+        assert!(mixer.push(0, frame(0.5)).is_empty());
+        assert_eq!(mixer.push(1, frame(0.25)), vec![frame(0.5)]);
+        assert_eq!(mixer.push(1, frame(0.75)), vec![frame(1.0)]);
+
+        let mut loud = frame(0.75);
+        apply_gain(&mut loud.pcm, 2.0);
+        assert_eq!(loud, frame(1.5));
+        assert!(mixer.push(0, loud).is_empty());
+        // Clipped at full scale
+        assert_eq!(mixer.push(1, frame(0.25)), vec![frame(1.0)]);
+    }
+
+    #[test]
+    fn quiet_or_ended_sources_are_not_waited_for() {
+        let mut mixer = SourceMixer::new(2);
+        assert!(mixer.push(0, frame(0.1)).is_empty());
+        assert!(mixer.push(0, frame(0.2)).is_empty());
+        // Source 1 has gone quiet; the mix carries on without it
+        assert_eq!(
+            mixer.push(0, frame(0.3)),
+            vec![frame(0.1), frame(0.2), frame(0.3)]
+        );
+
+        assert_eq!(mixer.push(0, frame(0.4)), vec![frame(0.4)]);
+
+        // Until it captures again
+        assert!(mixer.push(1, frame(0.1)).is_empty());
+        assert_eq!(mixer.push(0, frame(0.2)), vec![frame(0.3)]);
+
+        assert!(mixer.push(1, frame(0.1)).is_empty());
+        assert_eq!(mixer.end(0), vec![frame(0.1)]);
+        assert_eq!(mixer.push(1, frame(0.2)), vec![frame(0.2)]);
+    }
+
+    #[test]
+    fn mix_is_as_old_as_its_oldest_frame() {
+        let mut mixer = SourceMixer::new(2);
+        let late = PcmFrame {
+            captured_at: *CAPTURED_AT + Duration::from_millis(5),
+            ..frame(0.25)
+        };
+        assert!(mixer.push(0, late).is_empty());
+        assert_eq!(mixer.push(1, frame(0.25)), vec![frame(0.5)]);
+    }
+
+    #[tokio::test]
+    async fn merged_captures_end_when_all_do() {
+        let (game_tx, game_rx) = mpsc::channel(4);
+        let (chat_tx, chat_rx) = mpsc::channel(4);
+        let mut merged = merge(vec![game_rx, chat_rx]);
+
+        game_tx.send(frame(0.1)).await.unwrap();
+        assert_eq!(merged.recv().await, Some(Captured::Frame(0, frame(0.1))));
+        drop(game_tx);
+        assert_eq!(merged.recv().await, Some(Captured::Ended(0)));
+        chat_tx.send(frame(0.2)).await.unwrap();
+        drop(chat_tx);
+        assert_eq!(merged.recv().await, Some(Captured::Frame(1, frame(0.2))));
+        assert_eq!(merged.recv().await, Some(Captured::Ended(1)));
+        assert_eq!(merged.recv().await, None);
+    }
+}
@@ -4,10 +4,29 @@
 //! - Linux: PipeWire (attach to an app's audio output node)
 //! - macOS: ScreenCaptureKit (per-app audio, macOS 13+)
 //! - Windows: WASAPI process loopback (per-PID capture)
+//!
+//! Microphones, for talking over the shared audio, are recorded through cpal
+//! on every platform ([`mic`]).
+//!
+//! Alongside the audio, [`watch_now_playing`] follows what the app plays:
+//! - Linux: the app's MPRIS media player, over the D-Bus session bus
+//! - Windows: the app's system media transport controls session
+//! - macOS: no public API tells, so nothing is known
+//!
+//! A share may capture several sources at once; [`mix`] decides how they
+//! reach listeners.
 
 use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
 
+pub mod mic;
+pub mod mix;
+pub mod now_playing;
+pub use mic::{GateMode, MicGate};
+pub use mix::SourceLayout;
+pub use now_playing::NowPlayingWatcher;
+
 #[cfg(target_os = "linux")]
 mod linux;
 #[cfg(target_os = "macos")]
@@ -15,13 +34,31 @@ mod macos;
 #[cfg(target_os = "windows")]
 mod windows;
 
-/// An audio source that can be captured (an application producing audio).
+/// What kind of thing an [`AudioSource`] is.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SourceKind {
+    /// An application producing audio.
+    #[default]
+    App,
+    /// A microphone.
+    Microphone,
+}
+
+/// An audio source that can be captured (an application producing audio, or
+/// a microphone).
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AudioSource {
-    /// Platform-specific identifier (PipeWire node ID, PID, SCK app ID).
+    /// Platform-specific identifier (PipeWire node ID, PID, SCK app ID), or
+    /// cpal's device id for a microphone.
     pub id: String,
     /// Human-readable name (e.g. "Firefox", "Spotify").
     pub name: String,
+    /// Process producing the audio, where the platform tells.
+    #[serde(default)]
+    pub pid: Option<u32>,
+    #[serde(default)]
+    pub kind: SourceKind,
 }
 
 /// Audio format we normalize everything to before Opus encoding.
@@ -32,6 +69,32 @@ pub const FRAME_SIZE: usize = 960;
 /// Interleaved samples per frame: 960 * 2 channels = 1920 f32s.
 pub const SAMPLES_PER_FRAME: usize = FRAME_SIZE * CHANNELS as usize;
 
+/// A frame of captured audio: `SAMPLES_PER_FRAME` interleaved samples.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PcmFrame {
+    pub pcm: Vec<f32>,
+    /// When the frame's last sample was captured.
+    pub captured_at: Instant,
+}
+
+/// Take the complete frames off the front of `acc`, whose last sample was
+/// captured at `now`, each stamped with when its own last sample was.
+pub(crate) fn drain_frames(acc: &mut Vec<f32>, now: Instant) -> Vec<PcmFrame> {
+    let mut frames = Vec::new();
+    while acc.len() >= SAMPLES_PER_FRAME {
+        let pcm: Vec<f32> = acc.drain(..SAMPLES_PER_FRAME).collect();
+        // What is left in `acc` was captured after this frame
+        let later = (acc.len() / CHANNELS as usize) as u64;
+        let captured_at = now
+            .checked_sub(Duration::from_micros(
+                later * 1_000_000 / SAMPLE_RATE as u64,
+            ))
+            .unwrap_or(now);
+        frames.push(PcmFrame { pcm, captured_at });
+    }
+    frames
+}
+
 /// List applications currently producing audio.
 pub async fn list_sources() -> anyhow::Result<Vec<AudioSource>> {
     #[cfg(target_os = "linux")]
@@ -47,12 +110,21 @@ pub async fn list_sources() -> anyhow::Result<Vec<AudioSource>> {
     anyhow::bail!("Unsupported platform for audio capture")
 }
 
+/// List microphones that can be recorded, the default one first.
+pub fn list_microphones() -> anyhow::Result<Vec<AudioSource>> {
+    mic::list_microphones()
+}
+
 /// Start capturing audio from the given source. Returns a receiver of PCM f32
 /// frames (each frame is `SAMPLES_PER_FRAME` interleaved f32 samples = 20ms).
 /// The returned `CaptureHandle` must be kept alive; dropping it stops capture.
 pub async fn start_capture(
     source: &AudioSource,
-) -> anyhow::Result<(CaptureHandle, mpsc::Receiver<Vec<f32>>)> {
+) -> anyhow::Result<(CaptureHandle, mpsc::Receiver<PcmFrame>)> {
+    if source.kind == SourceKind::Microphone {
+        return mic::start_capture(source).await;
+    }
+
     #[cfg(target_os = "linux")]
     return linux::start_capture(source).await;
 
@@ -66,6 +138,22 @@ pub async fn start_capture(
     anyhow::bail!("Unsupported platform for audio capture")
 }
 
+/// Start following what the given source is playing. Where the platform
+/// can't tell, the watcher never knows anything.
+pub fn watch_now_playing(source: &AudioSource) -> NowPlayingWatcher {
+    #[cfg(target_os = "linux")]
+    return linux::watch_now_playing(source);
+
+    #[cfg(target_os = "windows")]
+    return windows::watch_now_playing(source);
+
+    #[cfg(not(any(target_os = "linux", target_os = "windows")))]
+    {
+        let _ = source;
+        NowPlayingWatcher::new(tokio::sync::watch::channel(None).1, None)
+    }
+}
+
 /// Handle to an active capture session. Drop to stop capture.
 pub struct CaptureHandle {
     _stop: tokio::sync::oneshot::Sender<()>,
@@ -97,11 +185,29 @@ mod tests {
         assert_eq!(SAMPLES_PER_FRAME, 1920);
     }
 
+    #[test]
+    fn drained_frames_are_stamped_by_their_last_sample() {
+        let now = Instant::now();
+        // A frame and a half, the half captured after the frame
+        let mut acc = vec![0.0; SAMPLES_PER_FRAME + SAMPLES_PER_FRAME / 2];
+        let frames = drain_frames(&mut acc, now);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].captured_at, now - Duration::from_millis(10));
+        assert_eq!(acc.len(), SAMPLES_PER_FRAME / 2);
+
+        acc.extend([0.0; SAMPLES_PER_FRAME / 2]);
+        let frames = drain_frames(&mut acc, now);
+        assert_eq!(frames[0].captured_at, now);
+        assert!(acc.is_empty());
+    }
+
     #[test]
     fn audio_source_serialization() {
         let source = AudioSource {
             id: "42".to_string(),
             name: "Firefox".to_string(),
+            pid: Some(4242),
+            kind: SourceKind::App,
         };
 
         let json = serde_json::to_string(&source).expect("serialize");
@@ -109,5 +215,11 @@ mod tests {
 
         assert_eq!(deserialized.id, "42");
         assert_eq!(deserialized.name, "Firefox");
+        assert_eq!(deserialized.pid, Some(4242));
+
+        // Sources from before pids were known
+        let old: AudioSource = serde_json::from_str(r#"{"id":"42","name":"Firefox"}"#).unwrap();
+        assert_eq!(old.pid, None);
+        assert_eq!(old.kind, SourceKind::App);
     }
 }
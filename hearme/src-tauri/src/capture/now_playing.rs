@@ -0,0 +1,267 @@
+//! What the captured application is playing.
+//!
+//! Where the platform exposes it (MPRIS over D-Bus on Linux, media sessions
+//! on Windows), a [`NowPlayingWatcher`] polls the captured application's
+//! media player and publishes what it plays whenever that changes: another
+//! track, play or pause, or a seek. Positions are only sent along with such
+//! news; listeners extrapolate them in between.
+
+use crate::protocol::NowPlaying;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+/// How often the player is asked what it is playing.
+pub const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How far the position may stray from where it should be before it counts
+/// as a seek.
+const SEEK_TOLERANCE: Duration = Duration::from_secs(2);
+
+/// Watches what a captured application is playing. Drop to stop.
+pub struct NowPlayingWatcher {
+    now_playing_rx: watch::Receiver<Option<NowPlaying>>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl NowPlayingWatcher {
+    /// A watcher fed by `task` through the sender of `now_playing_rx`, or
+    /// one that never knows anything if there is no task.
+    pub(super) fn new(
+        now_playing_rx: watch::Receiver<Option<NowPlaying>>,
+        task: Option<JoinHandle<()>>,
+    ) -> Self {
+        Self {
+            now_playing_rx,
+            task,
+        }
+    }
+
+    /// What is playing, `None` while nothing is known; changes only with
+    /// news.
+    pub fn now_playing(&self) -> watch::Receiver<Option<NowPlaying>> {
+        self.now_playing_rx.clone()
+    }
+}
+
+impl Drop for NowPlayingWatcher {
+    fn drop(&mut self) {
+        if let Some(task) = &self.task {
+            task.abort();
+        }
+    }
+}
+
+/// What a media player reports, in MPRIS terms (Windows' media sessions
+/// are read into the same shape).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PlayerState {
+    /// `xesam:title`
+    pub title: Option<String>,
+    /// `xesam:artist`
+    pub artists: Vec<String>,
+    /// `xesam:album`
+    pub album: Option<String>,
+    /// `mpris:artUrl`
+    pub art_url: Option<String>,
+    /// `mpris:length`, in µs.
+    pub length_us: Option<i64>,
+    /// `Position`, in µs.
+    pub position_us: Option<i64>,
+    /// `PlaybackStatus`: "Playing", "Paused" or "Stopped".
+    pub status: String,
+}
+
+impl PlayerState {
+    /// What the player is playing, or `None` if it is stopped or has no
+    /// track. Art only a local file holds is left out: listeners can't see
+    /// it.
+    pub fn now_playing(self) -> Option<NowPlaying> {
+        if self.status == "Stopped" {
+            return None;
+        }
+        let artist = (!self.artists.is_empty()).then(|| self.artists.join(", "));
+        if self.title.is_none() && artist.is_none() {
+            return None;
+        }
+        let ms = |us: Option<i64>| us.and_then(|us| u64::try_from(us / 1000).ok());
+        Some(NowPlaying {
+            title: self.title,
+            artist,
+            album: self.album,
+            art_url: self
+                .art_url
+                .filter(|url| url.starts_with("https://") || url.starts_with("http://")),
+            length_ms: ms(self.length_us),
+            position_ms: ms(self.position_us),
+            playing: self.status == "Playing",
+        })
+    }
+}
+
+/// The name an MPRIS player goes by on the bus:
+/// `org.mpris.MediaPlayer2.firefox.instance_1_42` -> `firefox`.
+pub fn mpris_player_name(bus_name: &str) -> Option<&str> {
+    bus_name
+        .strip_prefix("org.mpris.MediaPlayer2.")?
+        .split('.')
+        .next()
+}
+
+/// Whether a media player (running as `player_pid`, and going by
+/// `player_names`) belongs to the application `app_name` running as
+/// `app_pid`. Sandboxed players may hide their process id, so names count
+/// when the ids can't be compared.
+pub fn player_matches(
+    player_names: &[&str],
+    player_pid: Option<u32>,
+    app_name: &str,
+    app_pid: Option<u32>,
+) -> bool {
+    if let (Some(player), Some(app)) = (player_pid, app_pid)
+        && player == app
+    {
+        return true;
+    }
+    let normalize = |name: &str| -> String {
+        name.chars()
+            .filter(|c| c.is_alphanumeric())
+            .flat_map(char::to_lowercase)
+            .collect()
+    };
+    let app_name = app_name.strip_suffix(".exe").unwrap_or(app_name);
+    let app = normalize(app_name);
+    player_names.iter().any(|name| {
+        let player = normalize(name);
+        // Too short a name could be anything's prefix
+        app.len() >= 3
+            && player.len() >= 3
+            && (app.starts_with(&player) || player.starts_with(&app))
+    })
+}
+
+/// Decides which readings of a player are news worth announcing.
+#[derive(Debug, Default)]
+pub struct NowPlayingFilter {
+    last: Option<(Option<NowPlaying>, Instant)>,
+}
+
+impl NowPlayingFilter {
+    /// Whether `reading`, taken at `now`, differs from the last news in
+    /// more than the position moving on as it should.
+    pub fn is_news(&mut self, reading: &Option<NowPlaying>, now: Instant) -> bool {
+        let news = match &self.last {
+            None => true,
+            Some((last, then)) => match (last, reading) {
+                (Some(last), Some(reading)) => {
+                    let moved = NowPlaying {
+                        position_ms: reading.position_ms,
+                        ..last.clone()
+                    };
+                    moved != *reading
+                        || strayed(last, reading, now.saturating_duration_since(*then))
+                }
+                (last, reading) => last != reading,
+            },
+        };
+        if news {
+            self.last = Some((reading.clone(), now));
+        }
+        news
+    }
+}
+
+/// Whether `reading`'s position is off from where `last`'s should be after
+/// `elapsed`.
+fn strayed(last: &NowPlaying, reading: &NowPlaying, elapsed: Duration) -> bool {
+    let (Some(then), Some(position)) = (last.position_ms, reading.position_ms) else {
+        return last.position_ms.is_some() != reading.position_ms.is_some();
+    };
+    let expected = match last.playing {
+        true => then + elapsed.as_millis() as u64,
+        false => then,
+    };
+    position.abs_diff(expected) > SEEK_TOLERANCE.as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spotify(status: &str, position_s: i64) -> PlayerState {
+        PlayerState {
+            title: Some("Blue in Green".into()),
+            artists: vec!["Miles Davis".into(), "Bill Evans".into()],
+            album: Some("Kind of Blue".into()),
+            art_url: Some("https://i.scdn.co/image/ab67616d".into()),
+            length_us: Some(337_000_000),
+            position_us: Some(position_s * 1_000_000),
+            status: status.into(),
+        }
+    }
+
+    #[test]
+    fn player_state_becomes_now_playing() {
+        let now_playing = spotify("Playing", 12).now_playing().unwrap();
+        assert_eq!(now_playing.title.as_deref(), Some("Blue in Green"));
+        assert_eq!(
+            now_playing.artist.as_deref(),
+            Some("Miles Davis, Bill Evans")
+        );
+        assert_eq!(now_playing.length_ms, Some(337_000));
+        assert_eq!(now_playing.position_ms, Some(12_000));
+        assert!(now_playing.playing);
+
+        let local_art = PlayerState {
+            art_url: Some("file:///home/me/.cache/art.png".into()),
+            ..spotify("Paused", 0)
+        };
+        let now_playing = local_art.now_playing().unwrap();
+        assert_eq!(now_playing.art_url, None);
+        assert!(!now_playing.playing);
+
+        assert_eq!(spotify("Stopped", 0).now_playing(), None);
+        assert_eq!(PlayerState::default().now_playing(), None);
+    }
+
+    #[test]
+    fn players_match_by_pid_or_name() {
+        let firefox = mpris_player_name("org.mpris.MediaPlayer2.firefox.instance_1_42").unwrap();
+        assert_eq!(firefox, "firefox");
+        assert!(player_matches(&[firefox], Some(42), "Firefox", Some(42)));
+        assert!(player_matches(&[firefox], Some(7), "Firefox", None));
+        assert!(!player_matches(&[firefox], Some(7), "Spotify", Some(42)));
+        assert_eq!(mpris_player_name("org.freedesktop.Notifications"), None);
+
+        // A player's identity may be all that matches
+        assert!(player_matches(
+            &["vlc", "VLC media player"],
+            None,
+            "VLC media player",
+            None
+        ));
+        // Windows: an app model id against an executable name
+        let store_spotify = "SpotifyAB.SpotifyMusic_zpdnekdrzrea0!Spotify";
+        assert!(player_matches(&[store_spotify], None, "Spotify.exe", None));
+        // Too short to tell anything by
+        assert!(!player_matches(&["a"], None, "Audacity", None));
+    }
+
+    #[test]
+    fn only_track_changes_pauses_and_seeks_are_news() {
+        let mut filter = NowPlayingFilter::default();
+        let start = Instant::now();
+        let at = |s: u64| start + Duration::from_secs(s);
+        let reading = |status, position| spotify(status, position).now_playing();
+
+        assert!(filter.is_news(&reading("Playing", 10), at(0)));
+        // Playing on as expected
+        assert!(!filter.is_news(&reading("Playing", 11), at(1)));
+        assert!(!filter.is_news(&reading("Playing", 15), at(5)));
+        // Skipped ahead
+        assert!(filter.is_news(&reading("Playing", 60), at(6)));
+        assert!(filter.is_news(&reading("Paused", 61), at(7)));
+        assert!(!filter.is_news(&reading("Paused", 61), at(30)));
+        assert!(filter.is_news(&None, at(31)));
+        assert!(!filter.is_news(&None, at(32)));
+    }
+}
@@ -3,9 +3,23 @@
 //! Requires Windows 10 build 20348+ (Windows 11 / Server 2022).
 //! Uses WASAPI's AudioClient application loopback mode to capture
 //! audio from a specific process by PID.
+//!
+//! What the app plays comes from its system media transport controls
+//! session, found by matching the session's app id to the process name.
 
-use super::{AudioSource, CHANNELS, CaptureHandle, SAMPLE_RATE, SAMPLES_PER_FRAME};
-use tokio::sync::mpsc;
+use super::now_playing::{
+    NowPlayingFilter, NowPlayingWatcher, POLL_INTERVAL, PlayerState, player_matches,
+};
+use super::{
+    AudioSource, CHANNELS, CaptureHandle, PcmFrame, SAMPLE_RATE, SAMPLES_PER_FRAME, SourceKind,
+};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{mpsc, watch};
+use windows::Media::Control::{
+    GlobalSystemMediaTransportControlsSession as MediaSession,
+    GlobalSystemMediaTransportControlsSessionManager as MediaSessionManager,
+    GlobalSystemMediaTransportControlsSessionPlaybackStatus as PlaybackStatus,
+};
 
 /// List applications with active audio sessions via WASAPI session enumeration.
 /// Runs on a blocking thread because COM/WASAPI objects are `!Send`.
@@ -85,6 +99,8 @@ fn list_sources_sync() -> anyhow::Result<Vec<AudioSource>> {
             Some(AudioSource {
                 id: pid.to_string(),
                 name,
+                pid: Some(pid),
+                kind: SourceKind::App,
             })
         })
         .collect();
@@ -96,9 +112,9 @@ fn list_sources_sync() -> anyhow::Result<Vec<AudioSource>> {
 
 pub async fn start_capture(
     source: &AudioSource,
-) -> anyhow::Result<(CaptureHandle, mpsc::Receiver<Vec<f32>>)> {
+) -> anyhow::Result<(CaptureHandle, mpsc::Receiver<PcmFrame>)> {
     let pid: u32 = source.id.parse()?;
-    let (tx, rx) = mpsc::channel::<Vec<f32>>(64);
+    let (tx, rx) = mpsc::channel::<PcmFrame>(64);
     let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
 
     std::thread::spawn(move || {
@@ -112,7 +128,7 @@ pub async fn start_capture(
 
 fn capture_loop(
     pid: u32,
-    tx: mpsc::Sender<Vec<f32>>,
+    tx: mpsc::Sender<PcmFrame>,
     mut stop_rx: tokio::sync::oneshot::Receiver<()>,
 ) -> anyhow::Result<()> {
     use wasapi::*;
@@ -175,6 +191,7 @@ fn capture_loop(
         }
 
         // Read available frames (interleaved f32 bytes)
+        let now = Instant::now();
         match capture_client.read_from_device(&mut read_buf) {
             Ok((frames_read, _info)) => {
                 if frames_read == 0 {
@@ -185,8 +202,7 @@ fn capture_loop(
 
                 accumulator.extend_from_slice(samples);
 
-                while accumulator.len() >= SAMPLES_PER_FRAME {
-                    let frame: Vec<f32> = accumulator.drain(..SAMPLES_PER_FRAME).collect();
+                for frame in super::drain_frames(&mut accumulator, now) {
                     if tx.blocking_send(frame).is_err() {
                         return Ok(());
                     }
@@ -202,6 +218,97 @@ fn capture_loop(
     Ok(())
 }
 
+/// Follow what `source`'s media session plays.
+pub fn watch_now_playing(source: &AudioSource) -> NowPlayingWatcher {
+    let (tx, rx) = watch::channel(None);
+    let name = source.name.clone();
+    let task = tokio::spawn(async move {
+        let mut filter = NowPlayingFilter::default();
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            // WinRT objects are `!Send`, so each reading is taken on a
+            // blocking thread
+            let app_name = name.clone();
+            let reading = match tokio::task::spawn_blocking(move || read_app(&app_name)).await {
+                Ok(Ok(state)) => state.and_then(PlayerState::now_playing),
+                Ok(Err(e)) => {
+                    tracing::warn!("Not following what {name} plays: {e}");
+                    return;
+                }
+                Err(_) => None,
+            };
+            if filter.is_news(&reading, Instant::now()) {
+                tx.send_replace(reading);
+            }
+        }
+    });
+    NowPlayingWatcher::new(rx, Some(task))
+}
+
+/// What the media session of the app `app_name` plays, if it has one.
+/// Sessions come and go with the app's players, so this looks every time.
+fn read_app(app_name: &str) -> anyhow::Result<Option<PlayerState>> {
+    // Initialize COM for this thread
+    wasapi::initialize_mta()
+        .ok()
+        .map_err(|e| anyhow::anyhow!("COM init failed: {e}"))?;
+    let manager = MediaSessionManager::RequestAsync()?.get()?;
+    Ok(find_session(&manager, app_name).and_then(|session| read_session(&session).ok()))
+}
+
+/// The media session of the app `app_name`, if it has one.
+fn find_session(manager: &MediaSessionManager, app_name: &str) -> Option<MediaSession> {
+    let sessions = manager.GetSessions().ok()?;
+    (0..sessions.Size().ok()?)
+        .filter_map(|i| sessions.GetAt(i).ok())
+        .find(|session| {
+            session.SourceAppUserModelId().is_ok_and(|app_id| {
+                player_matches(&[app_id.to_string_lossy().as_str()], None, app_name, None)
+            })
+        })
+}
+
+fn read_session(session: &MediaSession) -> windows::core::Result<PlayerState> {
+    let properties = session.TryGetMediaPropertiesAsync()?.get()?;
+    let status = match session.GetPlaybackInfo()?.PlaybackStatus()? {
+        PlaybackStatus::Playing => "Playing",
+        PlaybackStatus::Closed | PlaybackStatus::Stopped => "Stopped",
+        _ => "Paused",
+    };
+    let text = |s: windows::core::HSTRING| (!s.is_empty()).then(|| s.to_string_lossy());
+
+    // Timelines are in 100ns units, and positions as of their last update
+    let timeline = session.GetTimelineProperties()?;
+    let length = timeline.EndTime()?.Duration - timeline.StartTime()?.Duration;
+    let mut position = timeline.Position()?.Duration;
+    if status == "Playing" {
+        position += (filetime_now() - timeline.LastUpdatedTime()?.UniversalTime).max(0);
+    }
+    // Players without a timeline leave it all at zero
+    let has_timeline = length > 0;
+
+    Ok(PlayerState {
+        title: text(properties.Title()?),
+        artists: text(properties.Artist()?).into_iter().collect(),
+        album: text(properties.AlbumTitle()?),
+        // The thumbnail is a stream only this machine can open
+        art_url: None,
+        length_us: has_timeline.then_some(length / 10),
+        position_us: has_timeline.then_some(position.min(length) / 10),
+        status: status.into(),
+    })
+}
+
+/// Now, in 100ns units since 1601 (a WinRT `DateTime`).
+fn filetime_now() -> i64 {
+    const UNIX_EPOCH_AS_FILETIME: i64 = 116_444_736_000_000_000;
+    let since_unix = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    UNIX_EPOCH_AS_FILETIME + (since_unix.as_nanos() / 100) as i64
+}
+
 fn bytemuck_cast_slice(bytes: &[u8]) -> &[f32] {
     let len = bytes.len() / 4;
     unsafe { std::slice::from_raw_parts(bytes.as_ptr() as *const f32, len) }
@@ -1,27 +1,271 @@
 //! Opus encoding and decoding.
 //!
-//! Wraps the `opus` crate for 48kHz stereo at 64kbps.
+//! Wraps the `opus` crate for 48kHz stereo. The encoder is tuned by an
+//! [`EncoderConfig`] (64kbps VBR by default).
 //! Frame size: 20ms = 960 samples/channel = 1920 interleaved f32s.
 
-use crate::capture::{CHANNELS, SAMPLES_PER_FRAME};
+use crate::capture::{CHANNELS, SAMPLE_RATE, SAMPLES_PER_FRAME};
+use serde::{Deserialize, Serialize};
 
 /// Maximum Opus packet size (20ms stereo at high bitrate won't exceed this).
 const MAX_PACKET_SIZE: usize = 4000;
 
+/// Bitrate range Opus accepts, in bits per second.
+pub const MIN_BITRATE: u32 = 6_000;
+pub const MAX_BITRATE: u32 = 510_000;
+
+/// Bitrates of the lower renditions a simulcast sharer offers alongside its
+/// chosen bitrate.
+pub const SIMULCAST_LADDER: [u32; 3] = [32_000, 64_000, 128_000];
+
+/// How the encoder spends its bitrate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BitrateMode {
+    /// Every packet is the same size.
+    Cbr,
+    /// Packet size follows signal complexity.
+    Vbr,
+    /// VBR, but never exceeding the target over a short window.
+    ConstrainedVbr,
+}
+
+/// Opus application mode; fixed for the lifetime of an encoder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Application {
+    /// Music and general audio.
+    Audio,
+    /// Speech; favours intelligibility.
+    Voip,
+    /// Lowest algorithmic delay; disables the speech-optimised modes.
+    LowDelay,
+}
+
+/// Hint about what is being encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Signal {
+    Auto,
+    Music,
+    Voice,
+}
+
+/// Audio bandwidth cap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Bandwidth {
+    /// 4kHz
+    Narrowband,
+    /// 6kHz
+    Mediumband,
+    /// 8kHz
+    Wideband,
+    /// 12kHz
+    Superwideband,
+    /// 20kHz
+    Fullband,
+}
+
+impl Bandwidth {
+    /// Widest bandwidth worth spending `bitrate` on for a stereo stream;
+    /// narrower bands leave more bits for what's left.
+    pub fn for_bitrate(bitrate: u32) -> Self {
+        match bitrate {
+            ..16_000 => Bandwidth::Narrowband,
+            16_000..24_000 => Bandwidth::Wideband,
+            24_000..40_000 => Bandwidth::Superwideband,
+            _ => Bandwidth::Fullband,
+        }
+    }
+}
+
+/// Encoder profile chosen by the sharer and announced to every listener.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EncoderConfig {
+    /// Target bitrate in bits per second.
+    pub bitrate: u32,
+    pub bitrate_mode: BitrateMode,
+    /// 0 (fastest) to 10 (best quality).
+    pub complexity: u8,
+    pub application: Application,
+    pub signal: Signal,
+    pub max_bandwidth: Bandwidth,
+    /// Discontinuous transmission: send almost nothing during silence.
+    pub dtx: bool,
+    /// Expected packet loss in-band FEC is tuned for; 0 turns FEC off.
+    pub fec_loss_percent: u8,
+    /// Let the sharer lower the bitrate and bandwidth (never above these
+    /// settings) when listeners' links are congested.
+    pub adaptive: bool,
+    /// Also encode lower-bitrate renditions from [`SIMULCAST_LADDER`], so each
+    /// listener can get the best one its link sustains.
+    pub simulcast: bool,
+}
+
+impl Default for EncoderConfig {
+    fn default() -> Self {
+        Self {
+            bitrate: 64_000,
+            bitrate_mode: BitrateMode::Vbr,
+            complexity: 10,
+            application: Application::Audio,
+            signal: Signal::Auto,
+            max_bandwidth: Bandwidth::Fullband,
+            dtx: false,
+            fec_loss_percent: 10,
+            adaptive: true,
+            simulcast: false,
+        }
+    }
+}
+
+impl EncoderConfig {
+    /// Music players: high bitrate, full band.
+    pub fn music() -> Self {
+        Self {
+            bitrate: 160_000,
+            signal: Signal::Music,
+            ..Self::default()
+        }
+    }
+
+    /// Voice calls: low bitrate speech mode with DTX.
+    pub fn voice() -> Self {
+        Self {
+            bitrate: 24_000,
+            application: Application::Voip,
+            signal: Signal::Voice,
+            max_bandwidth: Bandwidth::Wideband,
+            dtx: true,
+            ..Self::default()
+        }
+    }
+
+    /// Bitrates of the renditions this profile encodes, lowest first. The
+    /// last is always `bitrate` itself.
+    pub fn tiers(&self) -> Vec<u32> {
+        let mut tiers = Vec::new();
+        if self.simulcast {
+            tiers.extend(SIMULCAST_LADDER.iter().filter(|&&t| t < self.bitrate));
+        }
+        tiers.push(self.bitrate);
+        tiers
+    }
+
+    /// The profile of the rendition at `bitrate`: the same tuning, with the
+    /// bandwidth narrowed to what that bitrate can carry.
+    pub fn for_tier(&self, bitrate: u32) -> Self {
+        Self {
+            bitrate,
+            max_bandwidth: Bandwidth::for_bitrate(bitrate).min(self.max_bandwidth),
+            ..self.clone()
+        }
+    }
+
+    /// Check that every field is within the range Opus accepts.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            (MIN_BITRATE..=MAX_BITRATE).contains(&self.bitrate),
+            "Bitrate must be between {MIN_BITRATE} and {MAX_BITRATE} bps, got {}",
+            self.bitrate
+        );
+        anyhow::ensure!(
+            self.complexity <= 10,
+            "Complexity must be between 0 and 10, got {}",
+            self.complexity
+        );
+        anyhow::ensure!(
+            self.fec_loss_percent <= 100,
+            "Expected packet loss must be a percentage, got {}",
+            self.fec_loss_percent
+        );
+        Ok(())
+    }
+}
+
 pub struct Encoder {
     inner: opus::Encoder,
 }
 
 impl Encoder {
     pub fn new() -> anyhow::Result<Self> {
+        Self::with_config(&EncoderConfig::default())
+    }
+
+    /// Create an encoder tuned by `config`.
+    pub fn with_config(config: &EncoderConfig) -> anyhow::Result<Self> {
+        config.validate()?;
         let channels = if CHANNELS == 2 {
             opus::Channels::Stereo
         } else {
             opus::Channels::Mono
         };
-        let mut enc = opus::Encoder::new(48_000, channels, opus::Application::Audio)?;
-        enc.set_bitrate(opus::Bitrate::Bits(64_000))?;
-        Ok(Self { inner: enc })
+        let application = match config.application {
+            Application::Audio => opus::Application::Audio,
+            Application::Voip => opus::Application::Voip,
+            Application::LowDelay => opus::Application::LowDelay,
+        };
+        let enc = opus::Encoder::new(SAMPLE_RATE, channels, application)?;
+        let mut encoder = Self { inner: enc };
+
+        encoder.set_bitrate(config.bitrate, config.max_bandwidth)?;
+        encoder
+            .inner
+            .set_vbr(config.bitrate_mode != BitrateMode::Cbr)?;
+        encoder
+            .inner
+            .set_vbr_constraint(config.bitrate_mode == BitrateMode::ConstrainedVbr)?;
+        encoder.inner.set_complexity(config.complexity as i32)?;
+        encoder.inner.set_signal(match config.signal {
+            Signal::Auto => opus::Signal::Auto,
+            Signal::Music => opus::Signal::Music,
+            Signal::Voice => opus::Signal::Voice,
+        })?;
+        encoder.inner.set_dtx(config.dtx)?;
+        encoder.set_inband_fec(config.fec_loss_percent)?;
+        Ok(encoder)
+    }
+
+    /// Change the target bitrate and bandwidth cap mid-stream, e.g. to follow
+    /// the listeners' links. Takes effect from the next frame.
+    pub fn set_bitrate(&mut self, bitrate: u32, max_bandwidth: Bandwidth) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            (MIN_BITRATE..=MAX_BITRATE).contains(&bitrate),
+            "Bitrate must be between {MIN_BITRATE} and {MAX_BITRATE} bps, got {bitrate}"
+        );
+        self.inner
+            .set_bitrate(opus::Bitrate::Bits(bitrate as i32))?;
+        self.inner.set_max_bandwidth(match max_bandwidth {
+            Bandwidth::Narrowband => opus::Bandwidth::Narrowband,
+            Bandwidth::Mediumband => opus::Bandwidth::Mediumband,
+            Bandwidth::Wideband => opus::Bandwidth::Wideband,
+            Bandwidth::Superwideband => opus::Bandwidth::Superwideband,
+            Bandwidth::Fullband => opus::Bandwidth::Fullband,
+        })?;
+        Ok(())
+    }
+
+    /// Turn on in-band forward error correction, tuned for the given expected
+    /// packet loss. Each packet then also carries a low-bitrate copy of the
+    /// previous frame that the decoder can use via [`Decoder::decode_fec`].
+    /// Pass 0 to turn FEC off.
+    ///
+    /// Opus only embeds FEC data in its SILK and hybrid modes, so at high
+    /// bitrates (pure CELT) this mostly biases the encoder toward those modes.
+    pub fn set_inband_fec(&mut self, expected_loss_percent: u8) -> anyhow::Result<()> {
+        let percent = expected_loss_percent.min(100);
+        self.inner.set_inband_fec(percent > 0)?;
+        self.inner.set_packet_loss_perc(percent as i32)?;
+        Ok(())
+    }
+
+    /// Forget the audio encoded so far, keeping the settings, e.g. before
+    /// encoding again after frames were skipped.
+    pub fn reset_state(&mut self) -> anyhow::Result<()> {
+        self.inner.reset_state()?;
+        Ok(())
     }
 
     /// Encode a 20ms PCM f32 frame into an Opus packet.
@@ -66,6 +310,23 @@ impl Decoder {
         output.truncate(total);
         Ok(output)
     }
+
+    /// Synthesize a frame for a packet that never arrived, using Opus packet
+    /// loss concealment. Returns `SAMPLES_PER_FRAME` interleaved f32 samples.
+    pub fn decode_lost(&mut self) -> anyhow::Result<Vec<f32>> {
+        let mut output = vec![0f32; SAMPLES_PER_FRAME];
+        self.inner.decode_float(&[], &mut output, false)?;
+        Ok(output)
+    }
+
+    /// Recover a lost frame from the in-band FEC data carried by the packet
+    /// that follows it. Falls back to concealment if `next_packet` carries no
+    /// FEC data. `next_packet` must still be decoded normally afterwards.
+    pub fn decode_fec(&mut self, next_packet: &[u8]) -> anyhow::Result<Vec<f32>> {
+        let mut output = vec![0f32; SAMPLES_PER_FRAME];
+        self.inner.decode_float(next_packet, &mut output, true)?;
+        Ok(output)
+    }
 }
 
 #[cfg(test)]
@@ -151,4 +412,232 @@ mod tests {
             assert_eq!(output.len(), SAMPLES_PER_FRAME);
         }
     }
+
+    fn sine_frame(frame_idx: usize) -> Vec<f32> {
+        let mut input = vec![0.0f32; SAMPLES_PER_FRAME];
+        for i in 0..FRAME_SIZE {
+            let t = (frame_idx * FRAME_SIZE + i) as f32 / 48_000.0;
+            let sample = (2.0 * std::f32::consts::PI * 440.0 * t).sin() * 0.5;
+            input[i * CHANNELS as usize] = sample;
+            input[i * CHANNELS as usize + 1] = sample;
+        }
+        input
+    }
+
+    #[test]
+    fn presets_are_valid() {
+        for config in [
+            EncoderConfig::default(),
+            EncoderConfig::music(),
+            EncoderConfig::voice(),
+        ] {
+            config.validate().expect("valid preset");
+            let mut encoder = Encoder::with_config(&config).expect("encoder creation");
+            let packet = encoder.encode(&sine_frame(0)).expect("encode");
+            assert!(!packet.is_empty());
+        }
+    }
+
+    #[test]
+    fn reset_encoder_decodes_from_the_next_packet() {
+        let mut encoder = Encoder::new().expect("encoder creation");
+        let mut decoder = Decoder::new().expect("decoder creation");
+        for i in 0..5 {
+            decoder
+                .decode(&encoder.encode(&sine_frame(i)).unwrap())
+                .unwrap();
+        }
+        // Frames skipped while nobody listened, then a fresh start
+        encoder.reset_state().expect("reset");
+        let packet = encoder.encode(&sine_frame(50)).expect("encode");
+        assert_eq!(decoder.decode(&packet).unwrap().len(), SAMPLES_PER_FRAME);
+    }
+
+    #[test]
+    fn out_of_range_config_is_rejected() {
+        let config = EncoderConfig {
+            bitrate: 1_000,
+            ..EncoderConfig::default()
+        };
+        assert!(config.validate().is_err());
+        assert!(Encoder::with_config(&config).is_err());
+
+        let config = EncoderConfig {
+            complexity: 11,
+            ..EncoderConfig::default()
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn cbr_packets_have_constant_size() {
+        let config = EncoderConfig {
+            bitrate_mode: BitrateMode::Cbr,
+            ..EncoderConfig::default()
+        };
+        let mut encoder = Encoder::with_config(&config).expect("encoder creation");
+        let sizes: Vec<usize> = (0..5)
+            .map(|i| encoder.encode(&sine_frame(i)).expect("encode").len())
+            .collect();
+        assert!(sizes.windows(2).all(|w| w[0] == w[1]), "{sizes:?}");
+        // 64kbps * 20ms = 160 bytes
+        assert_eq!(sizes[0], 160);
+    }
+
+    #[test]
+    fn simulcast_tiers_stay_below_chosen_bitrate() {
+        assert_eq!(EncoderConfig::default().tiers(), vec![64_000]);
+
+        let music = EncoderConfig {
+            simulcast: true,
+            ..EncoderConfig::music()
+        };
+        assert_eq!(music.tiers(), vec![32_000, 64_000, 128_000, 160_000]);
+
+        let balanced = EncoderConfig {
+            simulcast: true,
+            ..EncoderConfig::default()
+        };
+        assert_eq!(balanced.tiers(), vec![32_000, 64_000]);
+    }
+
+    #[test]
+    fn tier_profile_narrows_bandwidth() {
+        let config = EncoderConfig::music();
+        let low = config.for_tier(20_000);
+        assert_eq!(low.bitrate, 20_000);
+        assert_eq!(low.max_bandwidth, Bandwidth::Wideband);
+        assert_eq!(low.signal, Signal::Music);
+
+        // Never wider than the sharer's own cap.
+        let voice = EncoderConfig::voice().for_tier(128_000);
+        assert_eq!(voice.max_bandwidth, Bandwidth::Wideband);
+    }
+
+    #[test]
+    fn bandwidth_narrows_with_bitrate() {
+        assert_eq!(Bandwidth::for_bitrate(12_000), Bandwidth::Narrowband);
+        assert_eq!(Bandwidth::for_bitrate(20_000), Bandwidth::Wideband);
+        assert_eq!(Bandwidth::for_bitrate(32_000), Bandwidth::Superwideband);
+        assert_eq!(Bandwidth::for_bitrate(64_000), Bandwidth::Fullband);
+    }
+
+    #[test]
+    fn bitrate_can_change_mid_stream() {
+        let config = EncoderConfig {
+            bitrate_mode: BitrateMode::Cbr,
+            ..EncoderConfig::default()
+        };
+        let mut encoder = Encoder::with_config(&config).expect("encoder creation");
+        encoder.encode(&sine_frame(0)).expect("encode");
+
+        encoder
+            .set_bitrate(32_000, Bandwidth::Superwideband)
+            .expect("set bitrate");
+        // 32kbps * 20ms = 80 bytes
+        assert_eq!(encoder.encode(&sine_frame(1)).expect("encode").len(), 80);
+        assert!(encoder.set_bitrate(1_000, Bandwidth::Narrowband).is_err());
+    }
+
+    #[test]
+    fn lower_bitrate_makes_smaller_packets() {
+        let mut music = Encoder::with_config(&EncoderConfig::music()).expect("encoder");
+        let mut voice = Encoder::with_config(&EncoderConfig::voice()).expect("encoder");
+        let music_bytes: usize = (0..10)
+            .map(|i| music.encode(&sine_frame(i)).expect("encode").len())
+            .sum();
+        let voice_bytes: usize = (0..10)
+            .map(|i| voice.encode(&sine_frame(i)).expect("encode").len())
+            .sum();
+        assert!(voice_bytes < music_bytes, "{voice_bytes} >= {music_bytes}");
+    }
+
+    #[test]
+    fn config_deserializes_partial_json() {
+        let config: EncoderConfig =
+            serde_json::from_str(r#"{"bitrate": 128000, "bitrate_mode": "constrained_vbr"}"#)
+                .expect("deserialize");
+        assert_eq!(config.bitrate, 128_000);
+        assert_eq!(config.bitrate_mode, BitrateMode::ConstrainedVbr);
+        assert_eq!(config.application, Application::Audio);
+    }
+
+    #[test]
+    fn lost_frame_is_concealed() {
+        let mut encoder = Encoder::new().expect("encoder creation");
+        let mut decoder = Decoder::new().expect("decoder creation");
+
+        for frame_idx in 0..5 {
+            let packet = encoder.encode(&sine_frame(frame_idx)).expect("encode");
+            decoder.decode(&packet).expect("decode");
+        }
+
+        // PLC continues the signal instead of dropping to silence
+        let concealed = decoder.decode_lost().expect("decode_lost");
+        assert_eq!(concealed.len(), SAMPLES_PER_FRAME);
+        let energy: f32 = concealed.iter().map(|s| s * s).sum();
+        assert!(
+            energy > 0.1,
+            "Concealed frame should not be silent, got {energy}"
+        );
+    }
+
+    /// Squared error against a clean decode when frame 10 of a sine is
+    /// lost, as `(concealed, recovered from the next packet's FEC)`.
+    fn loss_recovery_error(config: &EncoderConfig) -> (f32, f32) {
+        let mut encoder = Encoder::with_config(config).expect("encoder creation");
+        let packets: Vec<Vec<u8>> = (0..12)
+            .map(|i| encoder.encode(&sine_frame(i)).expect("encode"))
+            .collect();
+
+        let mut clean = Decoder::new().expect("decoder creation");
+        let mut plc = Decoder::new().expect("decoder creation");
+        let mut fec = Decoder::new().expect("decoder creation");
+        for packet in &packets[..10] {
+            clean.decode(packet).expect("decode");
+            plc.decode(packet).expect("decode");
+            fec.decode(packet).expect("decode");
+        }
+        let expected = clean.decode(&packets[10]).expect("decode");
+        let concealed = plc.decode_lost().expect("decode_lost");
+        let recovered = fec.decode_fec(&packets[11]).expect("decode_fec");
+        assert_eq!(recovered.len(), SAMPLES_PER_FRAME);
+
+        let error = |frame: &[f32]| -> f32 {
+            frame
+                .iter()
+                .zip(&expected)
+                .map(|(a, b)| (a - b) * (a - b))
+                .sum()
+        };
+        (error(&concealed), error(&recovered))
+    }
+
+    #[test]
+    fn lost_frame_is_recovered_from_fec() {
+        // FEC only rides in SILK and hybrid packets, so use the voice profile
+        let config = EncoderConfig {
+            fec_loss_percent: 20,
+            ..EncoderConfig::voice()
+        };
+        let (concealed, recovered) = loss_recovery_error(&config);
+        assert!(
+            recovered * 2.0 < concealed,
+            "FEC error {recovered} should be well below PLC error {concealed}"
+        );
+    }
+
+    #[test]
+    fn inband_fec_can_be_turned_off() {
+        let config = EncoderConfig {
+            fec_loss_percent: 0,
+            ..EncoderConfig::voice()
+        };
+        // Without FEC data the next packet can only conceal the loss
+        let (concealed, recovered) = loss_recovery_error(&config);
+        assert!(
+            recovered >= concealed,
+            "FEC error {recovered} should be no better than PLC error {concealed}"
+        );
+    }
 }
@@ -0,0 +1,175 @@
+//! Finding sharers on the local network.
+//!
+//! Every sharer advertises its endpoint id over mDNS under
+//! [`MDNS_SERVICE_NAME`] (unless mDNS is off in its
+//! [`crate::network::NetworkConfig`]); one that doesn't publish to DNS also
+//! advertises the name of the source it shares. A [`LanBrowser`] keeps a
+//! live list of the sharers it hears, for the Listen panel to offer.
+
+use anyhow::Result;
+use iroh::EndpointId;
+use iroh::address_lookup::{DiscoveryEvent, MdnsAddressLookup, UserData};
+use n0_future::StreamExt;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::info;
+
+/// Service name hearme sharers advertise on the local network over mDNS.
+pub const MDNS_SERVICE_NAME: &str = "hearme";
+
+/// A sharer heard on the local network.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LanSharer {
+    pub endpoint_id: EndpointId,
+    /// What it shares, if it says.
+    pub source: Option<String>,
+}
+
+/// The mDNS user data announcing `source_name`, cut to fit.
+pub fn advertisement(source_name: &str) -> UserData {
+    let mut len = source_name.len().min(UserData::MAX_LENGTH);
+    while !source_name.is_char_boundary(len) {
+        len -= 1;
+    }
+    source_name[..len]
+        .parse()
+        .expect("cut to the user data limit")
+}
+
+/// Watches the local network for sharers. Drop to stop.
+pub struct LanBrowser {
+    sharers_rx: watch::Receiver<Vec<LanSharer>>,
+    task: JoinHandle<()>,
+}
+
+impl LanBrowser {
+    /// Start listening for sharers' advertisements, as `own_id` (which is
+    /// never listed). Doesn't advertise anything itself.
+    pub async fn start(own_id: EndpointId) -> Result<Self> {
+        let mdns = MdnsAddressLookup::builder()
+            .advertise(false)
+            .service_name(MDNS_SERVICE_NAME)
+            .build(own_id)?;
+        let mut events = mdns.subscribe().await;
+        let (sharers_tx, sharers_rx) = watch::channel(Vec::new());
+
+        let task = tokio::spawn(async move {
+            // Holding the lookup keeps the mDNS discoverer running
+            let _mdns = mdns;
+            let mut sharers = Sharers::default();
+            while let Some(event) = events.next().await {
+                if sharers.apply(event) {
+                    sharers_tx.send_replace(sharers.list());
+                }
+            }
+            info!("LAN discovery ended");
+        });
+
+        Ok(Self { sharers_rx, task })
+    }
+
+    /// The sharers currently heard, by source name; updates as they come and
+    /// go.
+    pub fn sharers(&self) -> watch::Receiver<Vec<LanSharer>> {
+        self.sharers_rx.clone()
+    }
+}
+
+impl Drop for LanBrowser {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// The sharers heard so far: endpoint id -> advertised source name.
+#[derive(Debug, Default)]
+struct Sharers(BTreeMap<EndpointId, Option<String>>);
+
+impl Sharers {
+    /// Take in a discovery event. Returns whether the list changed.
+    fn apply(&mut self, event: DiscoveryEvent) -> bool {
+        match event {
+            DiscoveryEvent::Discovered { endpoint_info, .. } => {
+                let source = endpoint_info.user_data().map(|data| data.to_string());
+                let previous = self.0.insert(endpoint_info.endpoint_id, source.clone());
+                previous != Some(source)
+            }
+            DiscoveryEvent::Expired { endpoint_id } => self.0.remove(&endpoint_id).is_some(),
+        }
+    }
+
+    fn list(&self) -> Vec<LanSharer> {
+        let mut list: Vec<_> = self
+            .0
+            .iter()
+            .map(|(&endpoint_id, source)| LanSharer {
+                endpoint_id,
+                source: source.clone(),
+            })
+            .collect();
+        // Sorting is stable: sharers of the same name stay in id order
+        list.sort_by(|a, b| a.source.cmp(&b.source));
+        list
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::test_endpoint_id;
+    use iroh::address_lookup::EndpointInfo;
+
+    fn discovered(id: EndpointId, source: Option<&str>) -> DiscoveryEvent {
+        DiscoveryEvent::Discovered {
+            endpoint_info: EndpointInfo::new(id).with_user_data(source.map(|s| s.parse().unwrap())),
+            last_updated: None,
+        }
+    }
+
+    #[test]
+    fn sharers_come_and_go() {
+        let (spotify, unnamed) = (test_endpoint_id(), test_endpoint_id());
+        let mut sharers = Sharers::default();
+
+        assert!(sharers.apply(discovered(spotify, Some("Spotify"))));
+        assert!(sharers.apply(discovered(unnamed, None)));
+        // Re-announcing the same thing changes nothing
+        assert!(!sharers.apply(discovered(spotify, Some("Spotify"))));
+        assert_eq!(
+            sharers.list(),
+            vec![
+                LanSharer {
+                    endpoint_id: unnamed,
+                    source: None
+                },
+                LanSharer {
+                    endpoint_id: spotify,
+                    source: Some("Spotify".into())
+                },
+            ]
+        );
+
+        // Switching source is an update
+        assert!(sharers.apply(discovered(spotify, Some("Firefox"))));
+        assert_eq!(sharers.list()[1].source.as_deref(), Some("Firefox"));
+
+        assert!(sharers.apply(DiscoveryEvent::Expired {
+            endpoint_id: unnamed
+        }));
+        assert!(!sharers.apply(DiscoveryEvent::Expired {
+            endpoint_id: unnamed
+        }));
+        assert_eq!(sharers.list().len(), 1);
+    }
+
+    #[test]
+    fn long_source_names_are_cut_on_a_char_boundary() {
+        assert_eq!(advertisement("Spotify").to_string(), "Spotify");
+        let long = "é".repeat(UserData::MAX_LENGTH);
+        let cut = advertisement(&long).to_string();
+        assert!(cut.len() <= UserData::MAX_LENGTH);
+        assert!(long.starts_with(&cut));
+    }
+}
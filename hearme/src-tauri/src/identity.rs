@@ -0,0 +1,105 @@
+//! Persistent endpoint identity.
+//!
+//! An iroh endpoint is identified by its Ed25519 key: the `EndpointId` in
+//! every ticket is its public half. hearme keeps one secret key in the app data
+//! directory and uses it for both sharing and listening, so tickets (and bare
+//! endpoint ids) handed out stay valid across restarts until the key is reset.
+
+use crate::storage;
+use anyhow::{Context, Result};
+use iroh::SecretKey;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// File holding the hex-encoded secret key, inside the app data directory.
+const KEY_FILE: &str = "identity.key";
+
+/// Load the identity stored in `dir`, creating one on first run.
+pub fn load_or_create(dir: &Path) -> Result<SecretKey> {
+    let path = key_path(dir);
+    match fs::read_to_string(&path) {
+        Ok(hex) => parse(&hex).with_context(|| format!("Corrupt identity in {}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => reset(dir),
+        Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+/// Replace the identity stored in `dir` with a fresh one. Every ticket issued
+/// under the old identity stops working.
+pub fn reset(dir: &Path) -> Result<SecretKey> {
+    let key = SecretKey::generate(&mut rand::rng());
+    save(dir, &key)?;
+    Ok(key)
+}
+
+fn key_path(dir: &Path) -> PathBuf {
+    dir.join(KEY_FILE)
+}
+
+fn parse(hex: &str) -> Result<SecretKey> {
+    let bytes = data_encoding::HEXLOWER_PERMISSIVE.decode(hex.trim().as_bytes())?;
+    let bytes: [u8; 32] = bytes
+        .try_into()
+        .map_err(|b: Vec<u8>| anyhow::anyhow!("expected 32 bytes, got {}", b.len()))?;
+    Ok(SecretKey::from_bytes(&bytes))
+}
+
+fn save(dir: &Path, key: &SecretKey) -> Result<()> {
+    let hex = data_encoding::HEXLOWER.encode(&key.to_bytes());
+    storage::write_private(&key_path(dir), hex.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identity_survives_reload() {
+        let dir = storage::temp_dir("identity");
+        let first = load_or_create(&dir).unwrap();
+        let second = load_or_create(&dir).unwrap();
+        assert_eq!(first.public(), second.public());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn reset_changes_identity() {
+        let dir = storage::temp_dir("identity");
+        let first = load_or_create(&dir).unwrap();
+        let reset_key = reset(&dir).unwrap();
+        assert_ne!(first.public(), reset_key.public());
+        assert_eq!(load_or_create(&dir).unwrap().public(), reset_key.public());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn creates_missing_directory() {
+        let dir = storage::temp_dir("identity").join("nested");
+        assert!(load_or_create(&dir).is_ok());
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn corrupt_identity_is_an_error_not_a_new_key() {
+        let dir = storage::temp_dir("identity");
+        fs::write(dir.join(KEY_FILE), "not hex").unwrap();
+        assert!(load_or_create(&dir).is_err());
+        fs::write(dir.join(KEY_FILE), "abcd").unwrap();
+        assert!(load_or_create(&dir).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn key_file_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = storage::temp_dir("identity");
+        load_or_create(&dir).unwrap();
+        let mode = fs::metadata(dir.join(KEY_FILE))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
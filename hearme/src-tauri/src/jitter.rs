@@ -0,0 +1,384 @@
+//! Adaptive jitter buffer between the network receive loop and the decoder.
+//!
+//! Frames arrive in bursts and occasionally out of order, especially over a
+//! relayed path. The buffer holds them keyed by sequence number and releases
+//! one frame per playout tick once it holds `target` frames. The target grows
+//! as soon as measured jitter (RFC 3550 interarrival jitter) calls for it, and
+//! shrinks again slowly once the path calms down.
+//!
+//! ```text
+//!  receive loop ──push(frame)──▶ [ seq → frame ] ──pop()──▶ decoder
+//!                                 ◀── depth ──▶
+//! ```
+
+use crate::protocol::{AudioFrame, StreamHeader};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+/// Never aim for less than this many frames of buffering (40ms).
+const MIN_TARGET: usize = 2;
+/// Never aim for more than this many frames of buffering (500ms).
+const MAX_TARGET: usize = 25;
+/// Buffer this many multiples of the measured jitter.
+const JITTER_MULTIPLIER: f64 = 3.0;
+/// Consecutive pops with a lower desired target before the target shrinks
+/// by one frame (~2s at 20ms frames).
+const SHRINK_AFTER_POPS: u32 = 100;
+/// Frames held beyond the target before the oldest are discarded to pull
+/// latency back down.
+const MAX_EXCESS: usize = 4;
+
+/// What the decoder should play next.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Playout {
+    /// The next frame in sequence.
+    Frame(AudioFrame),
+    /// The frame with this sequence number never arrived in time.
+    Lost { seq: u64 },
+}
+
+/// Counters describing what the buffer has seen so far.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct JitterStats {
+    /// Frames accepted into the buffer.
+    pub received: u64,
+    /// Frames that arrived after their playout slot had passed.
+    pub late: u64,
+    /// Frames received more than once.
+    pub duplicate: u64,
+    /// Playout slots with no frame (gaps in the sequence).
+    pub lost: u64,
+    /// Frames dropped to bring latency back down.
+    pub discarded: u64,
+    /// Times the buffer ran dry and had to re-buffer.
+    pub underruns: u64,
+}
+
+pub struct JitterBuffer {
+    frames: BTreeMap<u64, AudioFrame>,
+    /// Sequence number of the next frame to play.
+    next_seq: Option<u64>,
+    /// False while (re)buffering up to the target.
+    playing: bool,
+    sample_rate: f64,
+    frame_duration: Duration,
+    /// Arrival time of the first frame; arrivals are measured from here.
+    origin: Option<Instant>,
+    /// Relative transit time of the previous frame, in seconds.
+    last_transit: Option<f64>,
+    /// Smoothed interarrival jitter, in seconds.
+    jitter: f64,
+    /// Frames to hold before playout starts.
+    target: usize,
+    shrink_votes: u32,
+    stats: JitterStats,
+}
+
+impl JitterBuffer {
+    /// Create a buffer for the stream described by `header`.
+    pub fn new(header: &StreamHeader) -> Self {
+        Self {
+            frames: BTreeMap::new(),
+            next_seq: None,
+            playing: false,
+            sample_rate: header.sample_rate as f64,
+            frame_duration: header.frame_duration(),
+            origin: None,
+            last_transit: None,
+            jitter: 0.0,
+            target: MIN_TARGET,
+            shrink_votes: 0,
+            stats: JitterStats::default(),
+        }
+    }
+
+    /// Insert a frame that arrived at `arrival`.
+    pub fn push(&mut self, frame: AudioFrame, arrival: Instant) {
+        if self.next_seq.is_some_and(|next| frame.seq < next) {
+            self.stats.late += 1;
+            return;
+        }
+        if self.frames.contains_key(&frame.seq) {
+            self.stats.duplicate += 1;
+            return;
+        }
+
+        self.update_jitter(&frame, arrival);
+        self.stats.received += 1;
+        self.frames.insert(frame.seq, frame);
+    }
+
+    /// Take the next thing to play, or `None` while buffering.
+    ///
+    /// Call once per frame duration, paced by the playback device.
+    pub fn pop(&mut self) -> Option<Playout> {
+        if !self.playing {
+            if self.frames.len() < self.target {
+                return None;
+            }
+            // Start (or restart after an underrun) from the oldest frame held,
+            // rather than concealing the whole outage.
+            self.playing = true;
+            self.next_seq = self.frames.keys().next().copied();
+        }
+
+        let Some(&oldest) = self.frames.keys().next() else {
+            self.playing = false;
+            self.stats.underruns += 1;
+            return None;
+        };
+        let mut next = self.next_seq.unwrap_or(oldest);
+
+        // Holding far more than needed: skip ahead to cut latency.
+        while self.frames.len() > self.target + MAX_EXCESS {
+            if let Some((seq, _)) = self.frames.pop_first() {
+                self.stats.discarded += 1;
+                next = next.max(seq + 1);
+            }
+        }
+
+        self.update_target();
+        self.next_seq = Some(next + 1);
+
+        match self.frames.remove(&next) {
+            Some(frame) => Some(Playout::Frame(frame)),
+            None => {
+                self.stats.lost += 1;
+                Some(Playout::Lost { seq: next })
+            }
+        }
+    }
+
+    /// Look at a buffered frame without taking it, e.g. the frame after a
+    /// lost one to recover the loss from its FEC data.
+    pub fn peek(&self, seq: u64) -> Option<&AudioFrame> {
+        self.frames.get(&seq)
+    }
+
+    /// Number of frames currently held.
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Audio currently held, as a duration.
+    pub fn depth_duration(&self) -> Duration {
+        self.frame_duration * self.frames.len() as u32
+    }
+
+    /// Buffering the buffer is currently aiming for.
+    pub fn target_delay(&self) -> Duration {
+        self.frame_duration * self.target as u32
+    }
+
+    /// Smoothed interarrival jitter.
+    pub fn jitter(&self) -> Duration {
+        Duration::from_secs_f64(self.jitter)
+    }
+
+    pub fn stats(&self) -> JitterStats {
+        self.stats
+    }
+
+    /// RFC 3550 §6.4.1: J += (|D| - J) / 16, where D is the difference in
+    /// relative transit time between consecutive arrivals.
+    fn update_jitter(&mut self, frame: &AudioFrame, arrival: Instant) {
+        let origin = *self.origin.get_or_insert(arrival);
+        let arrival_secs = arrival.saturating_duration_since(origin).as_secs_f64();
+        let transit = arrival_secs - frame.timestamp as f64 / self.sample_rate;
+
+        if let Some(last) = self.last_transit {
+            let d = (transit - last).abs();
+            self.jitter += (d - self.jitter) / 16.0;
+        }
+        self.last_transit = Some(transit);
+
+        // Grow immediately when the path gets worse.
+        let desired = self.desired_target();
+        if desired > self.target {
+            self.target = desired;
+            self.shrink_votes = 0;
+        }
+    }
+
+    /// Shrink one frame at a time once the desired target has stayed lower
+    /// for a while.
+    fn update_target(&mut self) {
+        if self.desired_target() < self.target {
+            self.shrink_votes += 1;
+            if self.shrink_votes >= SHRINK_AFTER_POPS {
+                self.target -= 1;
+                self.shrink_votes = 0;
+            }
+        } else {
+            self.shrink_votes = 0;
+        }
+    }
+
+    fn desired_target(&self) -> usize {
+        let frame = self.frame_duration.as_secs_f64();
+        let frames = (JITTER_MULTIPLIER * self.jitter / frame).ceil() as usize + 1;
+        frames.clamp(MIN_TARGET, MAX_TARGET)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::FRAME_SIZE;
+
+    const FRAME: Duration = Duration::from_millis(20);
+
+    fn frame(seq: u64) -> AudioFrame {
+        AudioFrame {
+            track: 0,
+            seq,
+            timestamp: seq * FRAME_SIZE as u64,
+            data: vec![seq as u8],
+        }
+    }
+
+    fn played_seq(playout: Option<Playout>) -> Option<u64> {
+        match playout {
+            Some(Playout::Frame(f)) => Some(f.seq),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn buffers_until_target_then_plays_in_order() {
+        let mut jb = JitterBuffer::new(&StreamHeader::current());
+        let t0 = Instant::now();
+
+        jb.push(frame(0), t0);
+        assert_eq!(jb.pop(), None, "still buffering");
+
+        jb.push(frame(1), t0 + FRAME);
+        assert_eq!(played_seq(jb.pop()), Some(0));
+        assert_eq!(played_seq(jb.pop()), Some(1));
+    }
+
+    #[test]
+    fn reorders_by_sequence_number() {
+        let mut jb = JitterBuffer::new(&StreamHeader::current());
+        let t0 = Instant::now();
+
+        jb.push(frame(1), t0);
+        jb.push(frame(0), t0);
+        jb.push(frame(2), t0 + FRAME);
+
+        assert_eq!(played_seq(jb.pop()), Some(0));
+        assert_eq!(played_seq(jb.pop()), Some(1));
+        assert_eq!(played_seq(jb.pop()), Some(2));
+    }
+
+    #[test]
+    fn reports_gap_as_lost() {
+        let mut jb = JitterBuffer::new(&StreamHeader::current());
+        let t0 = Instant::now();
+
+        jb.push(frame(0), t0);
+        jb.push(frame(2), t0 + FRAME * 2);
+
+        assert_eq!(played_seq(jb.pop()), Some(0));
+        assert_eq!(jb.pop(), Some(Playout::Lost { seq: 1 }));
+        assert_eq!(played_seq(jb.pop()), Some(2));
+        assert_eq!(jb.stats().lost, 1);
+    }
+
+    #[test]
+    fn frame_after_loss_can_be_peeked() {
+        let mut jb = JitterBuffer::new(&StreamHeader::current());
+        let t0 = Instant::now();
+
+        jb.push(frame(0), t0);
+        jb.push(frame(2), t0 + FRAME * 2);
+        jb.pop();
+
+        assert_eq!(jb.pop(), Some(Playout::Lost { seq: 1 }));
+        assert_eq!(jb.peek(2).map(|f| f.seq), Some(2));
+        assert_eq!(played_seq(jb.pop()), Some(2));
+    }
+
+    #[test]
+    fn drops_late_and_duplicate_frames() {
+        let mut jb = JitterBuffer::new(&StreamHeader::current());
+        let t0 = Instant::now();
+
+        jb.push(frame(0), t0);
+        jb.push(frame(1), t0 + FRAME);
+        jb.push(frame(1), t0 + FRAME);
+        assert_eq!(played_seq(jb.pop()), Some(0));
+
+        jb.push(frame(0), t0 + FRAME * 2);
+        let stats = jb.stats();
+        assert_eq!(stats.duplicate, 1);
+        assert_eq!(stats.late, 1);
+        assert_eq!(jb.depth(), 1);
+    }
+
+    #[test]
+    fn underrun_rebuffers() {
+        let mut jb = JitterBuffer::new(&StreamHeader::current());
+        let t0 = Instant::now();
+
+        jb.push(frame(0), t0);
+        jb.push(frame(1), t0 + FRAME);
+        jb.pop();
+        jb.pop();
+        assert_eq!(jb.pop(), None);
+        assert_eq!(jb.stats().underruns, 1);
+
+        // After an outage, playout resumes from the oldest frame held.
+        jb.push(frame(10), t0 + FRAME * 10);
+        assert_eq!(jb.pop(), None, "re-buffering");
+        jb.push(frame(11), t0 + FRAME * 11);
+        assert_eq!(played_seq(jb.pop()), Some(10));
+    }
+
+    #[test]
+    fn steady_arrivals_keep_minimum_target() {
+        let mut jb = JitterBuffer::new(&StreamHeader::current());
+        let t0 = Instant::now();
+
+        for seq in 0..50 {
+            jb.push(frame(seq), t0 + FRAME * seq as u32);
+            jb.pop();
+        }
+        assert_eq!(jb.target_delay(), FRAME * MIN_TARGET as u32);
+        assert!(jb.jitter() < Duration::from_millis(1));
+    }
+
+    #[test]
+    fn bursty_arrivals_grow_target_then_shrink() {
+        let mut jb = JitterBuffer::new(&StreamHeader::current());
+        let t0 = Instant::now();
+
+        // Frames arrive in bursts of five every 100ms.
+        for seq in 0..100u64 {
+            let burst = (seq / 5) as u32;
+            jb.push(frame(seq), t0 + FRAME * 5 * burst);
+        }
+        let grown = jb.target_delay();
+        assert!(grown > FRAME * MIN_TARGET as u32, "target {grown:?}");
+
+        // Then the path calms down.
+        for seq in 100..1_000u64 {
+            jb.push(frame(seq), t0 + FRAME * seq as u32);
+            jb.pop();
+        }
+        assert!(jb.target_delay() < grown);
+    }
+
+    #[test]
+    fn excess_frames_are_discarded() {
+        let mut jb = JitterBuffer::new(&StreamHeader::current());
+        let t0 = Instant::now();
+
+        for seq in 0..20 {
+            jb.push(frame(seq), t0);
+        }
+        jb.pop();
+        assert!(jb.depth() <= jb.target + MAX_EXCESS);
+        assert!(jb.stats().discarded > 0);
+    }
+}
@@ -14,13 +14,30 @@
 //! └─────────────────────────────────────────────────────┘
 //! ```
 
+pub mod access;
 pub mod app;
+pub mod bitrate;
 pub mod capture;
 pub mod codec;
+pub mod discovery;
+pub mod identity;
+pub mod jitter;
+pub mod mixer;
+pub mod network;
 pub mod playback;
+pub mod protocol;
+pub mod roster;
+pub mod settings;
+pub mod stats;
+pub mod storage;
+pub mod ticket;
 pub mod transport;
 
 use app::AppState;
+use tauri::Manager;
+use tauri_plugin_deep_link::DeepLinkExt;
+#[cfg(desktop)]
+use tauri_plugin_global_shortcut::ShortcutState;
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
@@ -31,15 +48,87 @@ pub fn run() {
         )
         .init();
 
-    tauri::Builder::default()
+    let builder = tauri::Builder::default();
+
+    // A hearme:// link opened while running reaches this instance rather
+    // than starting a second one. Must be the first plugin.
+    #[cfg(desktop)]
+    let builder = builder.plugin(tauri_plugin_single_instance::init(|app, _argv, _cwd| {
+        if let Some(window) = app.get_webview_window("main") {
+            let _ = window.unminimize();
+            let _ = window.set_focus();
+        }
+    }));
+
+    // Push to talk from whichever app has focus, while sharing a microphone
+    #[cfg(desktop)]
+    let builder = builder.plugin(
+        tauri_plugin_global_shortcut::Builder::new()
+            .with_handler(|app, _shortcut, event| {
+                app::on_talk_shortcut(app, event.state() == ShortcutState::Pressed);
+            })
+            .build(),
+    );
+
+    builder
+        .plugin(tauri_plugin_deep_link::init())
         .plugin(tauri_plugin_shell::init())
-        .manage(AppState::new())
+        .setup(|app| {
+            let data_dir = app.path().app_data_dir()?;
+            app.manage(AppState::new(data_dir)?);
+
+            // Installers register the scheme; for Linux and Windows dev
+            // builds, register it at runtime.
+            #[cfg(any(target_os = "linux", all(debug_assertions, windows)))]
+            app.deep_link().register_all()?;
+
+            let handle = app.handle().clone();
+            for url in app.deep_link().get_current()?.unwrap_or_default() {
+                app::open_link(&handle, url.as_str());
+            }
+            app.deep_link().on_open_url(move |event| {
+                for url in event.urls() {
+                    app::open_link(&handle, url.as_str());
+                }
+            });
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
+            app::get_identity,
+            app::take_opened_ticket,
+            app::reset_identity,
             app::list_audio_sources,
+            app::list_microphones,
+            app::list_encoder_presets,
             app::start_sharing,
+            app::set_source_gain,
+            app::set_mic_gate,
+            app::set_push_to_talk,
+            app::set_share_paused,
             app::stop_sharing,
+            app::create_ticket,
+            app::set_access_policy,
+            app::respond_to_listener,
+            app::kick_listener,
+            app::set_talkback_muted,
+            app::list_listeners,
+            app::list_contacts,
+            app::save_contact,
+            app::remove_contact,
             app::start_listening,
+            app::set_listen_quality,
+            app::set_listen_mix,
+            app::set_listen_track,
+            app::set_talkback,
+            app::list_listens,
             app::stop_listening,
+            app::get_display_name,
+            app::set_display_name,
+            app::get_network_config,
+            app::set_network_config,
+            app::get_connection_stats,
+            app::start_lan_discovery,
+            app::stop_lan_discovery,
         ])
         .run(tauri::generate_context!())
         .expect("error while running hearme");
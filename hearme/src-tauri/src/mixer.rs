@@ -0,0 +1,317 @@
+//! Mixing several sharers into one playback stream.
+//!
+//! Every track of every sharer being listened to plays out through its own
+//! [`MixerInput`]: the sharer's decode task keeps a couple of decoded frames
+//! queued there, paced by the track's jitter buffer. The [`Mixer`] takes a
+//! frame from every input at the pace of the playback device, applies the
+//! sharer's [`SourceMix`] (gain, mute, pan) and sums them. Tracks the
+//! listener turned off are drained unheard.
+
+use crate::capture::mix::sum_and_clamp;
+use crate::capture::{CHANNELS, SAMPLES_PER_FRAME};
+use anyhow::{Result, bail};
+use iroh::EndpointId;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+/// Loudest a source can be turned up to (+6 dB).
+pub const MAX_GAIN: f32 = 2.0;
+/// Decoded frames an input holds at most.
+const INPUT_CAPACITY_FRAMES: usize = 4;
+
+/// How one source sounds in the mix.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SourceMix {
+    /// Linear gain, from 0 to [`MAX_GAIN`].
+    pub gain: f32,
+    pub muted: bool,
+    /// Balance, from -1 (left only) through 0 (as the sharer sends it) to 1
+    /// (right only).
+    pub pan: f32,
+}
+
+impl Default for SourceMix {
+    fn default() -> Self {
+        Self {
+            gain: 1.0,
+            muted: false,
+            pan: 0.0,
+        }
+    }
+}
+
+impl SourceMix {
+    /// Check the gain and pan are in range.
+    pub fn validate(&self) -> Result<()> {
+        if !(0.0..=MAX_GAIN).contains(&self.gain) {
+            bail!("Gain must be between 0 and {MAX_GAIN}");
+        }
+        if !(-1.0..=1.0).contains(&self.pan) {
+            bail!("Pan must be between -1 and 1");
+        }
+        Ok(())
+    }
+
+    /// The gain of the left and right channel.
+    fn channel_gains(&self) -> [f32; 2] {
+        if self.muted {
+            return [0.0; 2];
+        }
+        [
+            self.gain * (1.0 - self.pan).min(1.0),
+            self.gain * (1.0 + self.pan).min(1.0),
+        ]
+    }
+}
+
+/// Where one source's decode task queues its audio for the [`Mixer`].
+pub struct MixerInput {
+    producer: rtrb::Producer<f32>,
+}
+
+impl MixerInput {
+    /// Decoded samples waiting to be mixed.
+    pub fn queued_samples(&self) -> usize {
+        self.producer.buffer().capacity() - self.producer.slots()
+    }
+
+    /// Queue a decoded frame. Samples that don't fit are dropped.
+    pub fn push(&mut self, pcm: &[f32]) {
+        for &sample in pcm {
+            let _ = self.producer.push(sample);
+        }
+    }
+}
+
+struct Input {
+    consumer: rtrb::Consumer<f32>,
+    mix: SourceMix,
+    enabled: bool,
+}
+
+/// Inputs by sharer and track.
+type Inputs = BTreeMap<(EndpointId, u8), Input>;
+
+/// The sharers being listened to, mixed into one stream.
+#[derive(Default)]
+pub struct Mixer {
+    inputs: Mutex<Inputs>,
+}
+
+impl Mixer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inputs> {
+        self.inputs.lock().expect("mixer poisoned")
+    }
+
+    /// The inputs of `sharer`'s tracks.
+    fn tracks<'a>(
+        inputs: &'a mut Inputs,
+        sharer: &EndpointId,
+    ) -> impl Iterator<Item = (u8, &'a mut Input)> {
+        inputs
+            .range_mut((*sharer, 0)..=(*sharer, u8::MAX))
+            .map(|((_, track), input)| (*track, input))
+    }
+
+    /// Mix in `track` of `sharer`, sounding as `mix`, from the returned input
+    /// on. Replaces any input the track already had.
+    pub fn add(&self, sharer: EndpointId, track: u8, mix: SourceMix) -> MixerInput {
+        let (producer, consumer) = rtrb::RingBuffer::new(INPUT_CAPACITY_FRAMES * SAMPLES_PER_FRAME);
+        let input = Input {
+            consumer,
+            mix,
+            enabled: true,
+        };
+        self.lock().insert((sharer, track), input);
+        MixerInput { producer }
+    }
+
+    /// Stop mixing in any track of `sharer`.
+    pub fn remove(&self, sharer: &EndpointId) {
+        self.lock().retain(|(id, _), _| id != sharer);
+    }
+
+    /// How `sharer` sounds, if it is mixed in.
+    pub fn mix(&self, sharer: &EndpointId) -> Option<SourceMix> {
+        Self::tracks(&mut self.lock(), sharer)
+            .next()
+            .map(|(_, input)| input.mix)
+    }
+
+    /// Whether each of `sharer`'s tracks is heard, by track.
+    pub fn tracks_enabled(&self, sharer: &EndpointId) -> Vec<bool> {
+        Self::tracks(&mut self.lock(), sharer)
+            .map(|(_, input)| input.enabled)
+            .collect()
+    }
+
+    /// Make `sharer` sound as `mix` from the next frame on. Returns whether
+    /// it is mixed in at all.
+    pub fn set_mix(&self, sharer: &EndpointId, mix: SourceMix) -> bool {
+        let mut inputs = self.lock();
+        let mut any = false;
+        for (_, input) in Self::tracks(&mut inputs, sharer) {
+            input.mix = mix;
+            any = true;
+        }
+        any
+    }
+
+    /// Hear `track` of `sharer` or not, from the next frame on. Returns
+    /// whether the track is mixed in at all.
+    pub fn set_track_enabled(&self, sharer: &EndpointId, track: u8, enabled: bool) -> bool {
+        match self.lock().get_mut(&(*sharer, track)) {
+            Some(input) => {
+                input.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The next frame of the mix: a frame of every input that has one
+    /// queued, summed. `None` if no input has a whole frame yet.
+    pub fn next_frame(&self) -> Option<Vec<f32>> {
+        let mut inputs = self.lock();
+        let mut any = false;
+        let sources = inputs.values_mut().filter_map(|input| {
+            let chunk = input.consumer.read_chunk(SAMPLES_PER_FRAME).ok()?;
+            if !input.enabled {
+                chunk.commit_all();
+                return None;
+            }
+            any = true;
+            let gains = input.mix.channel_gains();
+            // Takes the frame off the input as the sum reads it
+            Some(
+                chunk
+                    .into_iter()
+                    .enumerate()
+                    .map(move |(i, sample)| sample * gains[i % CHANNELS as usize]),
+            )
+        });
+        let mixed = sum_and_clamp(sources);
+        any.then_some(mixed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::test_endpoint_id;
+
+    /// A frame of `left` in every left sample and `right` in every right.
+    fn frame(left: f32, right: f32) -> Vec<f32> {
+        (0..SAMPLES_PER_FRAME)
+            .map(|i| if i % 2 == 0 { left } else { right })
+            .collect()
+    }
+
+    #[test]
+    fn sources_are_summed_with_their_gain_and_pan() {
+        let mixer = Mixer::new();
+        let (alice, bob) = (test_endpoint_id(), test_endpoint_id());
+        let mut alice_in = mixer.add(alice, 0, SourceMix::default());
+        let mut bob_in = mixer.add(
+            bob,
+            0,
+            SourceMix {
+                gain: 0.5,
+                pan: -1.0,
+                ..SourceMix::default()
+            },
+        );
+        assert_eq!(mixer.next_frame(), None);
+
+        alice_in.push(&frame(0.25, 0.25));
+        bob_in.push(&frame(0.5, 0.5));
+        assert_eq!(alice_in.queued_samples(), SAMPLES_PER_FRAME);
+        // Bob is halved and only heard on the left
+        assert_eq!(mixer.next_frame(), Some(frame(0.5, 0.25)));
+        assert_eq!(alice_in.queued_samples(), 0);
+
+        // A source with nothing queued just isn't heard
+        alice_in.push(&frame(0.25, 0.25));
+        assert_eq!(mixer.next_frame(), Some(frame(0.25, 0.25)));
+
+        // Muted, loud and clipped
+        assert!(mixer.set_mix(
+            &alice,
+            SourceMix {
+                muted: true,
+                ..SourceMix::default()
+            }
+        ));
+        let loud = SourceMix {
+            gain: MAX_GAIN,
+            ..SourceMix::default()
+        };
+        assert!(mixer.set_mix(&bob, loud));
+        assert_eq!(mixer.mix(&bob), Some(loud));
+        alice_in.push(&frame(0.25, 0.25));
+        bob_in.push(&frame(0.75, -0.25));
+        assert_eq!(mixer.next_frame(), Some(frame(1.0, -0.5)));
+
+        mixer.remove(&bob);
+        assert!(!mixer.set_mix(&bob, SourceMix::default()));
+        assert_eq!(mixer.mix(&bob), None);
+    }
+
+    #[test]
+    fn tracks_share_the_mix_and_are_turned_off_alone() {
+        let mixer = Mixer::new();
+        let (alice, bob) = (test_endpoint_id(), test_endpoint_id());
+        let mut game = mixer.add(alice, 0, SourceMix::default());
+        let mut chat = mixer.add(alice, 1, SourceMix::default());
+        let _bob_in = mixer.add(bob, 0, SourceMix::default());
+
+        let half = SourceMix {
+            gain: 0.5,
+            ..SourceMix::default()
+        };
+        assert!(mixer.set_mix(&alice, half));
+        assert_eq!(mixer.mix(&bob), Some(SourceMix::default()));
+        game.push(&frame(0.5, 0.5));
+        chat.push(&frame(0.25, 0.25));
+        assert_eq!(mixer.next_frame(), Some(frame(0.375, 0.375)));
+
+        // Turned off, the chat is drained but not heard
+        assert!(mixer.set_track_enabled(&alice, 1, false));
+        assert_eq!(mixer.tracks_enabled(&alice), vec![true, false]);
+        game.push(&frame(0.5, 0.5));
+        chat.push(&frame(0.25, 0.25));
+        assert_eq!(mixer.next_frame(), Some(frame(0.25, 0.25)));
+        assert_eq!(chat.queued_samples(), 0);
+        assert!(!mixer.set_track_enabled(&alice, 2, false));
+
+        mixer.remove(&alice);
+        assert_eq!(mixer.tracks_enabled(&alice), Vec::<bool>::new());
+        assert_eq!(mixer.mix(&bob), Some(SourceMix::default()));
+    }
+
+    #[test]
+    fn out_of_range_mixes_are_rejected() {
+        assert!(SourceMix::default().validate().is_ok());
+        let too_loud = SourceMix {
+            gain: MAX_GAIN + 0.1,
+            ..SourceMix::default()
+        };
+        assert!(too_loud.validate().is_err());
+        let too_far = SourceMix {
+            pan: -1.5,
+            ..SourceMix::default()
+        };
+        assert!(too_far.validate().is_err());
+        let nan = SourceMix {
+            gain: f32::NAN,
+            ..SourceMix::default()
+        };
+        assert!(nan.validate().is_err());
+    }
+}
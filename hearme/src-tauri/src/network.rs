@@ -0,0 +1,303 @@
+//! How hearme's endpoints reach the network.
+//!
+//! A [`NetworkConfig`] picks the relays (n0's, self-hosted ones, or none),
+//! the local sockets to bind and how peers' addresses are found: via n0's
+//! DNS (pkarr) and/or over mDNS on the local network. It applies to sharing
+//! and listening alike and is kept in [`crate::settings`].
+//!
+//! With relays and DNS off, hearme works on a local network with no internet
+//! access at all ([`NetworkConfig::lan`]).
+
+use crate::discovery::{self, MDNS_SERVICE_NAME};
+use anyhow::{Result, bail};
+use iroh::address_lookup::{DnsAddressLookup, MdnsAddressLookup, PkarrPublisher};
+use iroh::endpoint::{BindOpts, Builder};
+use iroh::{Endpoint, RelayMode, RelayUrl, SecretKey};
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+/// How long an endpoint may take to reach its relay before we give up.
+const RELAY_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Which relays help peers connect when no direct path exists.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum Relays {
+    /// n0's public relays.
+    #[default]
+    Default,
+    /// Our own relays, e.g. where only a self-hosted one is reachable.
+    Custom { urls: Vec<RelayUrl> },
+    /// No relays: peers must reach each other directly.
+    Disabled,
+}
+
+/// Which IP versions to use.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IpFamily {
+    /// IPv4, and IPv6 where the host has it.
+    #[default]
+    Both,
+    V4Only,
+    V6Only,
+}
+
+/// How the share and listen endpoints reach the network.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkConfig {
+    pub relays: Relays,
+    /// Local address to bind, restricting hearme to one interface (and its
+    /// IP version); all interfaces when `None`.
+    pub bind_addr: Option<IpAddr>,
+    /// UDP port to bind, e.g. one opened in a firewall; any free port when
+    /// `None`.
+    pub bind_port: Option<u16>,
+    pub ip_family: IpFamily,
+    /// Publish our address to, and look peers up in, n0's DNS by endpoint
+    /// id.
+    pub dns_lookup: bool,
+    /// Advertise shares, and find sharers, on the local network over mDNS.
+    pub mdns: bool,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            relays: Relays::Default,
+            bind_addr: None,
+            bind_port: None,
+            ip_family: IpFamily::Both,
+            dns_lookup: true,
+            mdns: true,
+        }
+    }
+}
+
+impl NetworkConfig {
+    /// The local network only: no relays or DNS, peers found over mDNS.
+    pub fn lan() -> Self {
+        Self {
+            relays: Relays::Disabled,
+            dns_lookup: false,
+            ..Self::default()
+        }
+    }
+
+    /// Check that the configuration can be used to bind an endpoint.
+    pub fn validate(&self) -> Result<()> {
+        if matches!(&self.relays, Relays::Custom { urls } if urls.is_empty()) {
+            bail!("Give at least one relay URL, or turn relays off");
+        }
+        if self.bind_addrs().is_empty() {
+            bail!("The bind address doesn't match the IP version chosen");
+        }
+        Ok(())
+    }
+
+    /// Whether endpoints connect to a relay, which sessions wait for before
+    /// handing out addresses.
+    pub fn uses_relays(&self) -> bool {
+        self.relays != Relays::Disabled
+    }
+
+    /// Wait until `endpoint` has reached a relay, if it uses any. Fails,
+    /// naming the relays, if none answers within [`RELAY_TIMEOUT`].
+    pub async fn wait_online(&self, endpoint: &Endpoint) -> Result<()> {
+        let relays = match &self.relays {
+            Relays::Default => "n0's relays".to_string(),
+            Relays::Custom { urls } => {
+                let urls: Vec<_> = urls.iter().map(RelayUrl::to_string).collect();
+                urls.join(", ")
+            }
+            Relays::Disabled => return Ok(()),
+        };
+        if tokio::time::timeout(RELAY_TIMEOUT, endpoint.online())
+            .await
+            .is_err()
+        {
+            bail!(
+                "Couldn't reach {relays} within {}s; check the relay URLs or turn relays off",
+                RELAY_TIMEOUT.as_secs()
+            );
+        }
+        Ok(())
+    }
+
+    /// A builder for a sharer's endpoint as `secret_key`, publishing its
+    /// address as configured. `source_name` is advertised over mDNS, but
+    /// only while DNS is off, so it never lands in a public record.
+    pub fn sharer_builder(&self, secret_key: SecretKey, source_name: &str) -> Result<Builder> {
+        let mut builder = self.builder(secret_key)?;
+        if self.dns_lookup {
+            builder = builder
+                .address_lookup(PkarrPublisher::n0_dns())
+                .address_lookup(DnsAddressLookup::n0_dns());
+        }
+        if self.mdns {
+            builder = builder
+                .address_lookup(MdnsAddressLookup::builder().service_name(MDNS_SERVICE_NAME));
+            if !self.dns_lookup {
+                builder =
+                    builder.user_data_for_address_lookup(discovery::advertisement(source_name));
+            }
+        }
+        Ok(builder)
+    }
+
+    /// A builder for a listener's endpoint as `secret_key`. It looks sharers
+    /// up but never publishes itself: a share running alongside has the
+    /// same identity and owns that record.
+    pub fn listener_builder(&self, secret_key: SecretKey) -> Result<Builder> {
+        let mut builder = self.builder(secret_key)?;
+        if self.dns_lookup {
+            builder = builder.address_lookup(DnsAddressLookup::n0_dns());
+        }
+        if self.mdns {
+            builder = builder.address_lookup(
+                MdnsAddressLookup::builder()
+                    .advertise(false)
+                    .service_name(MDNS_SERVICE_NAME),
+            );
+        }
+        Ok(builder)
+    }
+
+    fn builder(&self, secret_key: SecretKey) -> Result<Builder> {
+        self.validate()?;
+        let relay_mode = match &self.relays {
+            Relays::Default => RelayMode::Default,
+            Relays::Custom { urls } => RelayMode::Custom(urls.iter().cloned().collect()),
+            Relays::Disabled => RelayMode::Disabled,
+        };
+        let mut builder = Endpoint::empty_builder(relay_mode).secret_key(secret_key);
+
+        // The builder binds all interfaces on random ports by default
+        if self.bind_addr.is_some() || self.bind_port.is_some() || self.ip_family != IpFamily::Both
+        {
+            builder = builder.clear_ip_transports();
+            for addr in self.bind_addrs() {
+                // Like the default, IPv6 is optional unless asked for
+                let required = addr.is_ipv4()
+                    || self.bind_addr.is_some()
+                    || self.ip_family == IpFamily::V6Only;
+                let opts = BindOpts::default().set_is_required(required);
+                builder = builder.bind_addr_with_opts(addr, opts)?;
+            }
+        }
+        Ok(builder)
+    }
+
+    /// The sockets to bind: one per IP version in use.
+    fn bind_addrs(&self) -> Vec<SocketAddr> {
+        let port = self.bind_port.unwrap_or(0);
+        let (v4, v6) = match self.bind_addr {
+            None => (
+                Some(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+                Some(IpAddr::V6(Ipv6Addr::UNSPECIFIED)),
+            ),
+            Some(ip @ IpAddr::V4(_)) => (Some(ip), None),
+            Some(ip @ IpAddr::V6(_)) => (None, Some(ip)),
+        };
+        let (v4, v6) = match self.ip_family {
+            IpFamily::Both => (v4, v6),
+            IpFamily::V4Only => (v4, None),
+            IpFamily::V6Only => (None, v6),
+        };
+        [v4, v6]
+            .into_iter()
+            .flatten()
+            .map(|ip| SocketAddr::new(ip, port))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_fields_are_defaults() {
+        let config: NetworkConfig = serde_json::from_str("{}").unwrap();
+        assert_eq!(config, NetworkConfig::default());
+        assert!(config.uses_relays());
+        assert!(config.dns_lookup && config.mdns);
+    }
+
+    #[test]
+    fn json_shape() {
+        let json = r#"{
+            "relays": {"mode": "custom", "urls": ["https://relay.example.com./"]},
+            "bind_port": 4433,
+            "ip_family": "v4_only",
+            "dns_lookup": false
+        }"#;
+        let config: NetworkConfig = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            config.relays,
+            Relays::Custom {
+                urls: vec!["https://relay.example.com./".parse().unwrap()]
+            }
+        );
+        assert_eq!(config.bind_port, Some(4433));
+        assert_eq!(config.ip_family, IpFamily::V4Only);
+        assert!(!config.dns_lookup);
+        assert!(config.mdns);
+    }
+
+    #[test]
+    fn lan_turns_off_relays_and_dns() {
+        let lan = NetworkConfig::lan();
+        assert!(!lan.uses_relays());
+        assert!(!lan.dns_lookup);
+        assert!(lan.mdns);
+        assert!(lan.validate().is_ok());
+    }
+
+    #[test]
+    fn custom_relays_need_a_url() {
+        let config = NetworkConfig {
+            relays: Relays::Custom { urls: Vec::new() },
+            ..NetworkConfig::default()
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn bind_addrs_follow_address_port_and_family() {
+        let default = NetworkConfig::default();
+        assert_eq!(
+            default.bind_addrs(),
+            vec![
+                "0.0.0.0:0".parse::<SocketAddr>().unwrap(),
+                "[::]:0".parse().unwrap()
+            ]
+        );
+
+        let v4_port = NetworkConfig {
+            bind_port: Some(4433),
+            ip_family: IpFamily::V4Only,
+            ..NetworkConfig::default()
+        };
+        assert_eq!(v4_port.bind_addrs(), vec!["0.0.0.0:4433".parse().unwrap()]);
+
+        // A specific address binds only its own IP version
+        let interface = NetworkConfig {
+            bind_addr: Some("192.168.1.20".parse().unwrap()),
+            ..NetworkConfig::default()
+        };
+        assert_eq!(
+            interface.bind_addrs(),
+            vec!["192.168.1.20:0".parse().unwrap()]
+        );
+
+        let mismatch = NetworkConfig {
+            ip_family: IpFamily::V6Only,
+            ..interface
+        };
+        assert!(mismatch.bind_addrs().is_empty());
+        assert!(mismatch.validate().is_err());
+    }
+}
@@ -0,0 +1,616 @@
+//! Wire protocol spoken on the `/hearme/audio/1` ALPN.
+//!
+//! Every message is length-prefixed: `[u16 LE length][postcard payload]`.
+//! The hello and the welcome are preceded by the sender's `[u16 LE
+//! version]`.
+//!
+//! ```text
+//! listener                                  sharer
+//!    │ ── open bi-stream ─────────────────────▶ │
+//!    │ ── version, Hello { capabilities } ────▶ │
+//!    │ ◀─ version, Welcome::Accept(StreamHeader) │   (or Welcome::Reject)
+//!    │ ◀═══ uni-stream: TrackStream, AudioFrame… │   (one per subscribed track)
+//!    │ ── ListenerMessage::Report ────────────▶ │   (about once a second)
+//!    │ ── ListenerMessage::RequestQuality ────▶ │   (whenever the user asks)
+//!    │ ── ListenerMessage::Unsubscribe ───────▶ │   (its uni-stream finishes)
+//!    │ ◀──────── SharerMessage::NowPlaying ───── │   (whenever it changes)
+//!    │ ── ListenerMessage::Subscribe ─────────▶ │   (a new uni-stream opens)
+//!    │ ═══ uni-stream: AudioFrame… ═══════════▶ │   (while talking back)
+//!    │ ◀─── SharerMessage::TalkbackMuted ─────── │   (the sharer muted us)
+//!    │ ◀──────── SharerMessage::Ending ───────── │   (the share is over)
+//! ```
+//!
+//! The listener speaks first so the sharer can refuse a listener it does not
+//! understand or admit. After the handshake both halves of the bi-stream
+//! carry control messages, while audio flows on uni-streams, one per track.
+
+use crate::capture::{CHANNELS, FRAME_SIZE, SAMPLE_RATE};
+use crate::codec::EncoderConfig;
+use crate::ticket::Grant;
+use anyhow::{Context, Result, bail};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Custom ALPN for hearme audio streams.
+pub const ALPN: &[u8] = b"/hearme/audio/1";
+
+/// Version of the handshake and framing spoken on [`ALPN`].
+/// Bump whenever the bytes on the wire change incompatibly.
+pub const PROTOCOL_VERSION: u16 = 1;
+
+/// Longest display name a listener may go by, in bytes.
+pub const MAX_DISPLAY_NAME_LEN: usize = 64;
+
+/// Most tracks a stream may carry.
+pub const MAX_TRACKS: usize = 8;
+
+/// Application close code: the sharer removed this listener on purpose.
+pub const CLOSE_REMOVED: u32 = 1;
+
+/// Audio codecs a peer can produce or consume.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Codec {
+    Opus,
+}
+
+/// First message on the stream, sent by the listener.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Hello {
+    /// Codecs the listener can decode.
+    pub codecs: Vec<Codec>,
+    /// Highest channel count the listener can play.
+    pub max_channels: u16,
+    /// Highest bitrate the listener wants; `None` for the best its link
+    /// sustains.
+    pub max_bitrate: Option<u32>,
+    /// The grant from the ticket the listener connects with.
+    pub grant: Option<Grant>,
+    /// The name the listener goes by, for the sharer's roster; at most
+    /// [`MAX_DISPLAY_NAME_LEN`] bytes.
+    pub display_name: Option<String>,
+    /// Tracks the listener doesn't want, by index; it is sent all others.
+    pub unsubscribed: Vec<u8>,
+}
+
+impl Hello {
+    /// The hello this build of hearme sends.
+    pub fn current() -> Self {
+        Self {
+            codecs: vec![Codec::Opus],
+            max_channels: CHANNELS,
+            max_bitrate: None,
+            grant: None,
+            display_name: None,
+            unsubscribed: Vec::new(),
+        }
+    }
+}
+
+/// Describes the audio that follows the handshake.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StreamHeader {
+    pub codec: Codec,
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// Samples per channel in each frame.
+    pub frame_size: u32,
+    /// Sharer's wall clock at sample-clock zero, in µs since the Unix epoch.
+    pub clock_epoch_us: u64,
+    /// Encoder profile the sharer chose for the share (bitrate, mode,
+    /// bandwidth...).
+    pub encoder: EncoderConfig,
+    /// Bitrates of the tiers the sharer encodes, lowest first; the last is
+    /// the profile's own bitrate. Every track encodes every tier, capped at
+    /// its own bitrate.
+    pub tiers: Vec<u32>,
+    /// The tracks the share carries (at least one, at most [`MAX_TRACKS`]),
+    /// by the index frames give.
+    pub tracks: Vec<TrackInfo>,
+}
+
+/// One track in a [`StreamHeader`]'s catalog.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrackInfo {
+    pub name: String,
+    /// How the track is encoded; its bitrate caps every tier's.
+    pub encoder: EncoderConfig,
+}
+
+impl TrackInfo {
+    /// The profile of this track's rendition on the share's tier at
+    /// `bitrate`.
+    pub fn for_tier(&self, bitrate: u32) -> EncoderConfig {
+        self.encoder.for_tier(bitrate.min(self.encoder.bitrate))
+    }
+}
+
+impl StreamHeader {
+    /// The stream this build of hearme produces: 48kHz stereo Opus, 20ms frames.
+    pub fn current() -> Self {
+        Self {
+            codec: Codec::Opus,
+            sample_rate: SAMPLE_RATE,
+            channels: CHANNELS,
+            frame_size: FRAME_SIZE as u32,
+            clock_epoch_us: 0,
+            encoder: EncoderConfig::default(),
+            tiers: EncoderConfig::default().tiers(),
+            tracks: vec![TrackInfo {
+                name: "Audio".into(),
+                encoder: EncoderConfig::default(),
+            }],
+        }
+    }
+
+    /// Whether both headers describe the same audio format (ignoring the clock
+    /// and encoder tuning, which any Opus decoder copes with).
+    pub fn same_format(&self, other: &StreamHeader) -> bool {
+        self.codec == other.codec
+            && self.sample_rate == other.sample_rate
+            && self.channels == other.channels
+            && self.frame_size == other.frame_size
+    }
+
+    /// Duration of a single frame.
+    pub fn frame_duration(&self) -> Duration {
+        Duration::from_micros(self.frame_size as u64 * 1_000_000 / self.sample_rate as u64)
+    }
+
+    /// Wall-clock time at which the frame with this timestamp was captured.
+    pub fn capture_time(&self, timestamp: u64) -> SystemTime {
+        let offset_us = timestamp * 1_000_000 / self.sample_rate as u64;
+        UNIX_EPOCH + Duration::from_micros(self.clock_epoch_us + offset_us)
+    }
+
+    /// End-to-end latency of a frame received at `now`.
+    ///
+    /// Only meaningful when both machines' clocks are synchronised (NTP);
+    /// returns `None` if the frame appears to come from the future.
+    pub fn latency(&self, timestamp: u64, now: SystemTime) -> Option<Duration> {
+        now.duration_since(self.capture_time(timestamp)).ok()
+    }
+}
+
+/// The first message on a track's uni-stream; the track's frames follow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrackStream {
+    /// Index of the track in the header.
+    pub track: u8,
+}
+
+/// One encoded audio frame as sent on the wire.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AudioFrame {
+    /// Index of the track (in the header) the frame belongs to; the same as
+    /// its stream's.
+    pub track: u8,
+    /// Increases by one for every frame the sharer encodes on the track.
+    pub seq: u64,
+    /// Sample clock (samples per channel since the header's epoch) at the
+    /// first sample of this frame.
+    pub timestamp: u64,
+    /// Opus packet.
+    pub data: Vec<u8>,
+}
+
+/// How playout is going on the listener, as cumulative counters since the
+/// stream started (so a lost report loses nothing).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReceptionReport {
+    /// Frames that arrived in time to be played.
+    pub received: u64,
+    /// Frames that never arrived.
+    pub lost: u64,
+    /// Frames that arrived after their playout time.
+    pub late: u64,
+    /// Interarrival jitter estimate, in µs.
+    pub jitter_us: u32,
+    /// Capture-to-arrival latency of the latest frame, in µs; `None` when
+    /// the clocks disagree too much to tell.
+    pub latency_us: Option<u32>,
+}
+
+/// Messages the listener sends after the handshake.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ListenerMessage {
+    Report(ReceptionReport),
+    /// Cap the tier this listener is served at `max_bitrate`, or lift the cap.
+    RequestQuality {
+        max_bitrate: Option<u32>,
+    },
+    /// From now on the listener goes by this name (at most
+    /// [`MAX_DISPLAY_NAME_LEN`] bytes), or by none.
+    DisplayName(Option<String>),
+    /// Send this track (by index), on a new uni-stream.
+    Subscribe(u8),
+    /// Stop sending this track (by index), finishing its uni-stream.
+    Unsubscribe(u8),
+}
+
+/// What the sharer's application is playing.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NowPlaying {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    /// Cover art, if it is at a URL listeners can fetch.
+    pub art_url: Option<String>,
+    /// Length of the track.
+    pub length_ms: Option<u64>,
+    /// Position in the track when this was sent.
+    pub position_ms: Option<u64>,
+    /// Whether the position moves on (the player isn't paused).
+    pub playing: bool,
+}
+
+impl NowPlaying {
+    /// The same, `elapsed` later: a playing track's position moves on (up
+    /// to its length).
+    pub fn advanced(&self, elapsed: Duration) -> Self {
+        let mut now_playing = self.clone();
+        if self.playing
+            && let Some(position) = &mut now_playing.position_ms
+        {
+            *position += elapsed.as_millis() as u64;
+            if let Some(length) = self.length_ms {
+                *position = (*position).min(length);
+            }
+        }
+        now_playing
+    }
+}
+
+/// Messages the sharer sends after the handshake.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SharerMessage {
+    /// What is playing from now on; `None` when the sharer can't tell.
+    NowPlaying(Option<NowPlaying>),
+    /// The sharer paused (`true`) or resumed (`false`) its audio; no frames
+    /// flow while paused.
+    Paused(bool),
+    /// The sharer is ending the share; nothing follows and reconnecting is
+    /// pointless.
+    Ending { reason: String },
+    /// The sharer muted (`true`) or unmuted (`false`) our talking back.
+    TalkbackMuted(bool),
+}
+
+/// The sharer's reply to a [`Hello`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Welcome {
+    /// The sharer will stream audio described by the header.
+    Accept { header: StreamHeader },
+    /// The sharer refuses this listener; the stream is closed afterwards.
+    Reject { reason: String },
+}
+
+/// The sharer turned the listener away; retrying won't change its mind.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rejected(pub String);
+
+impl std::fmt::Display for Rejected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Sharer rejected connection: {}", self.0)
+    }
+}
+
+impl std::error::Error for Rejected {}
+
+/// Sharer side: decide whether a listener's hello can be served.
+/// Returns the reason for rejecting it, if any.
+pub fn check_hello(hello: &Hello, header: &StreamHeader) -> Option<String> {
+    if !hello.codecs.contains(&header.codec) {
+        return Some(format!("listener cannot decode {:?}", header.codec));
+    }
+    if hello.max_channels < header.channels {
+        return Some(format!(
+            "listener plays at most {} channels, stream has {}",
+            hello.max_channels, header.channels
+        ));
+    }
+    None
+}
+
+/// Listener side: turn the sharer's welcome into the header of a stream we can
+/// play, or a descriptive error.
+pub fn check_welcome(welcome: Welcome) -> Result<StreamHeader> {
+    let header = match welcome {
+        Welcome::Accept { header } => header,
+        Welcome::Reject { reason } => return Err(Rejected(reason).into()),
+    };
+    if !header.same_format(&StreamHeader::current()) {
+        bail!(
+            "Incompatible sharer: streams {:?} at {}Hz, {} channels, {} samples/frame",
+            header.codec,
+            header.sample_rate,
+            header.channels,
+            header.frame_size
+        );
+    }
+    if header.tracks.is_empty() || header.tracks.len() > MAX_TRACKS {
+        bail!(
+            "Incompatible sharer: streams {} tracks, expected 1 to {MAX_TRACKS}",
+            header.tracks.len()
+        );
+    }
+    Ok(header)
+}
+
+/// Write one length-prefixed packet.
+pub async fn write_packet<W: AsyncWrite + Unpin>(send: &mut W, payload: &[u8]) -> Result<()> {
+    let len = u16::try_from(payload.len()).context("Packet too large for u16 length prefix")?;
+    send.write_all(&len.to_le_bytes()).await?;
+    send.write_all(payload).await?;
+    Ok(())
+}
+
+/// Read one length-prefixed packet.
+pub async fn read_packet<R: AsyncRead + Unpin>(recv: &mut R) -> Result<Vec<u8>> {
+    let mut len_buf = [0u8; 2];
+    recv.read_exact(&mut len_buf).await?;
+    let len = u16::from_le_bytes(len_buf) as usize;
+    let mut packet = vec![0u8; len];
+    recv.read_exact(&mut packet).await?;
+    Ok(packet)
+}
+
+/// Write one postcard-encoded message.
+pub async fn write_msg<W: AsyncWrite + Unpin, T: Serialize>(send: &mut W, msg: &T) -> Result<()> {
+    let bytes = postcard::to_allocvec(msg)?;
+    write_packet(send, &bytes).await
+}
+
+/// Read one postcard-encoded message.
+pub async fn read_msg<R: AsyncRead + Unpin, T: DeserializeOwned>(recv: &mut R) -> Result<T> {
+    let bytes = read_packet(recv).await?;
+    postcard::from_bytes(&bytes).context("Malformed protocol message")
+}
+
+/// Write a [`Hello`] or [`Welcome`], preceded by our [`PROTOCOL_VERSION`].
+pub async fn write_handshake<W: AsyncWrite + Unpin, T: Serialize>(
+    send: &mut W,
+    msg: &T,
+) -> Result<()> {
+    send.write_all(&PROTOCOL_VERSION.to_le_bytes()).await?;
+    write_msg(send, msg).await
+}
+
+/// Read the version preceding a peer's [`Hello`] or [`Welcome`]. Only if it
+/// is our [`PROTOCOL_VERSION`] can the message be decoded.
+pub async fn read_version<R: AsyncRead + Unpin>(recv: &mut R) -> Result<u16> {
+    let mut version = [0u8; 2];
+    recv.read_exact(&mut version).await?;
+    Ok(u16::from_le_bytes(version))
+}
+
+/// A fresh endpoint id, for tests that need a peer.
+#[cfg(test)]
+pub(crate) fn test_endpoint_id() -> iroh::EndpointId {
+    iroh::SecretKey::generate(&mut rand::rng()).public()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alpn_is_correct() {
+        assert_eq!(ALPN, b"/hearme/audio/1");
+    }
+
+    #[test]
+    fn current_hello_is_accepted() {
+        assert_eq!(
+            check_hello(&Hello::current(), &StreamHeader::current()),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn version_is_read_whatever_follows() {
+        // A peer of another version, whose hello we can't decode
+        let (mut a, mut b) = tokio::io::duplex(1024);
+        a.write_all(&(PROTOCOL_VERSION + 1).to_le_bytes())
+            .await
+            .unwrap();
+        write_packet(&mut a, b"\xff\xff\xff").await.unwrap();
+        assert_eq!(read_version(&mut b).await.unwrap(), PROTOCOL_VERSION + 1);
+    }
+
+    #[test]
+    fn hello_without_opus_is_rejected() {
+        let hello = Hello {
+            codecs: vec![],
+            ..Hello::current()
+        };
+        assert!(check_hello(&hello, &StreamHeader::current()).is_some());
+    }
+
+    #[test]
+    fn mono_listener_is_rejected_for_stereo_stream() {
+        let hello = Hello {
+            max_channels: 1,
+            ..Hello::current()
+        };
+        assert!(check_hello(&hello, &StreamHeader::current()).is_some());
+    }
+
+    #[test]
+    fn welcome_reject_surfaces_reason() {
+        let err = check_welcome(Welcome::Reject {
+            reason: "go away".into(),
+        })
+        .unwrap_err();
+        assert!(err.to_string().contains("go away"));
+        assert!(err.downcast_ref::<Rejected>().is_some());
+    }
+
+    #[test]
+    fn welcome_with_incompatible_header_fails() {
+        let header = StreamHeader {
+            sample_rate: 44_100,
+            ..StreamHeader::current()
+        };
+        let result = check_welcome(Welcome::Accept { header });
+        assert!(result.is_err());
+
+        let too_many = vec![StreamHeader::current().tracks[0].clone(); MAX_TRACKS + 1];
+        for tracks in [vec![], too_many] {
+            let header = StreamHeader {
+                tracks,
+                ..StreamHeader::current()
+            };
+            let result = check_welcome(Welcome::Accept { header });
+            assert!(result.is_err());
+        }
+    }
+
+    #[test]
+    fn frame_duration_is_20ms() {
+        assert_eq!(
+            StreamHeader::current().frame_duration(),
+            Duration::from_millis(20)
+        );
+    }
+
+    #[tokio::test]
+    async fn handshake_round_trip_over_stream() {
+        let (mut a, mut b) = tokio::io::duplex(1024);
+
+        let key = iroh::SecretKey::generate(&mut rand::rng());
+        let sent = Hello {
+            grant: Some(Grant::issue(&key, &Default::default(), SystemTime::now())),
+            display_name: Some("Alice".into()),
+            unsubscribed: vec![1],
+            ..Hello::current()
+        };
+        write_handshake(&mut a, &sent).await.unwrap();
+        assert_eq!(read_version(&mut b).await.unwrap(), PROTOCOL_VERSION);
+        let hello: Hello = read_msg(&mut b).await.unwrap();
+        assert_eq!(hello, sent);
+
+        let welcome = Welcome::Accept {
+            header: StreamHeader::current(),
+        };
+        write_handshake(&mut b, &welcome).await.unwrap();
+        assert_eq!(read_version(&mut a).await.unwrap(), PROTOCOL_VERSION);
+        let received: Welcome = read_msg(&mut a).await.unwrap();
+        assert_eq!(check_welcome(received).unwrap(), StreamHeader::current());
+
+        // A track's frames flow on a stream of their own
+        let (mut track_send, mut track_recv) = tokio::io::duplex(1024);
+        let frame = AudioFrame {
+            track: 0,
+            seq: 7,
+            timestamp: 7 * FRAME_SIZE as u64,
+            data: b"opus".to_vec(),
+        };
+        write_msg(&mut track_send, &TrackStream { track: 0 })
+            .await
+            .unwrap();
+        write_msg(&mut track_send, &frame).await.unwrap();
+        let opened: TrackStream = read_msg(&mut track_recv).await.unwrap();
+        assert_eq!(opened.track, 0);
+        let received: AudioFrame = read_msg(&mut track_recv).await.unwrap();
+        assert_eq!(received, frame);
+
+        let report = ListenerMessage::Report(ReceptionReport {
+            received: 50,
+            lost: 1,
+            late: 2,
+            jitter_us: 3_500,
+            latency_us: Some(80_000),
+        });
+        write_msg(&mut a, &report).await.unwrap();
+        let received: ListenerMessage = read_msg(&mut b).await.unwrap();
+        assert_eq!(received, report);
+
+        for request in [
+            ListenerMessage::RequestQuality {
+                max_bitrate: Some(32_000),
+            },
+            ListenerMessage::Subscribe(1),
+            ListenerMessage::Unsubscribe(0),
+        ] {
+            write_msg(&mut a, &request).await.unwrap();
+            let received: ListenerMessage = read_msg(&mut b).await.unwrap();
+            assert_eq!(received, request);
+        }
+    }
+
+    #[tokio::test]
+    async fn sharer_announcements_round_trip() {
+        let (mut a, mut b) = tokio::io::duplex(1024);
+        let announcements = [
+            SharerMessage::NowPlaying(Some(NowPlaying {
+                title: Some("Blue in Green".into()),
+                artist: Some("Miles Davis".into()),
+                position_ms: Some(12_000),
+                playing: true,
+                ..NowPlaying::default()
+            })),
+            SharerMessage::Paused(true),
+            SharerMessage::Paused(false),
+            SharerMessage::TalkbackMuted(true),
+            SharerMessage::Ending {
+                reason: "the sharer stopped sharing".into(),
+            },
+        ];
+        for msg in &announcements {
+            write_msg(&mut a, msg).await.unwrap();
+        }
+        for msg in announcements {
+            let received: SharerMessage = read_msg(&mut b).await.unwrap();
+            assert_eq!(received, msg);
+        }
+    }
+
+    #[test]
+    fn welcome_with_other_clock_epoch_is_accepted() {
+        let header = StreamHeader {
+            clock_epoch_us: 1_700_000_000_000_000,
+            ..StreamHeader::current()
+        };
+        let result = check_welcome(Welcome::Accept {
+            header: header.clone(),
+        });
+        assert_eq!(result.unwrap(), header);
+    }
+
+    #[test]
+    fn welcome_carries_encoder_profile() {
+        let header = StreamHeader {
+            encoder: EncoderConfig::voice(),
+            ..StreamHeader::current()
+        };
+        let result = check_welcome(Welcome::Accept { header });
+        assert_eq!(result.unwrap().encoder, EncoderConfig::voice());
+    }
+
+    #[test]
+    fn latency_from_sample_clock() {
+        let header = StreamHeader {
+            clock_epoch_us: 1_000_000,
+            ..StreamHeader::current()
+        };
+        // One second of samples after the epoch, received 50ms later.
+        let timestamp = SAMPLE_RATE as u64;
+        let now = UNIX_EPOCH + Duration::from_millis(2_050);
+        assert_eq!(
+            header.latency(timestamp, now),
+            Some(Duration::from_millis(50))
+        );
+        // Receiver clock behind the sharer's: no meaningful latency.
+        assert_eq!(header.latency(timestamp, UNIX_EPOCH), None);
+    }
+
+    #[tokio::test]
+    async fn read_msg_rejects_garbage() {
+        let (mut a, mut b) = tokio::io::duplex(64);
+        write_packet(&mut a, &[0xff, 0xff, 0xff]).await.unwrap();
+        let result: Result<Welcome> = read_msg(&mut b).await;
+        assert!(result.is_err());
+    }
+}
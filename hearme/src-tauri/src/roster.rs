@@ -0,0 +1,215 @@
+//! Who is listening to a share.
+//!
+//! The sharer keeps a [`Roster`] of its connected listeners: who they are
+//! (endpoint id and the display name from their hello), since when, and how
+//! their link is doing ([`ListenerStats`]: path, lag, tier), and whether
+//! they are talking back ([`Talkback`]). Listeners joining and leaving are
+//! announced as [`RosterEvent`]s.
+
+use crate::protocol::MAX_DISPLAY_NAME_LEN;
+use crate::stats::ListenerStats;
+use iroh::EndpointId;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+
+/// A listener connected to the share.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RosterListener {
+    pub endpoint_id: EndpointId,
+    /// The name the listener goes by, if it gave one.
+    pub display_name: Option<String>,
+    /// When the listener connected, in ms since the Unix epoch.
+    pub connected_since_ms: u64,
+    pub talkback: Talkback,
+    #[serde(flatten)]
+    pub stats: ListenerStats,
+}
+
+/// A listener's talking back to the sharer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Talkback {
+    /// The listener is sending its microphone.
+    pub talking: bool,
+    /// The sharer doesn't want to hear it.
+    pub muted: bool,
+}
+
+impl RosterListener {
+    /// A listener connecting at `now`, under the name its hello gave (which
+    /// is cleaned up, as any listener can send anything).
+    pub fn new(endpoint_id: EndpointId, display_name: Option<&str>, now: SystemTime) -> Self {
+        Self {
+            endpoint_id,
+            display_name: display_name.and_then(clean_display_name),
+            connected_since_ms: now
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            talkback: Talkback::default(),
+            stats: ListenerStats::default(),
+        }
+    }
+}
+
+/// A change to the roster.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RosterEvent {
+    Joined(RosterListener),
+    /// The listener as it was last seen.
+    Left(RosterListener),
+}
+
+/// The listeners connected to one share session, by connection.
+#[derive(Debug)]
+pub struct Roster {
+    listeners: Mutex<BTreeMap<usize, RosterListener>>,
+    events: mpsc::UnboundedSender<RosterEvent>,
+}
+
+impl Roster {
+    /// An empty roster. The receiver yields every listener joining and
+    /// leaving.
+    pub fn new() -> (Self, mpsc::UnboundedReceiver<RosterEvent>) {
+        let (events, events_rx) = mpsc::unbounded_channel();
+        let roster = Self {
+            listeners: Mutex::new(BTreeMap::new()),
+            events,
+        };
+        (roster, events_rx)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<usize, RosterListener>> {
+        self.listeners.lock().expect("roster poisoned")
+    }
+
+    /// Add `listener`, connected as `link_id`.
+    pub fn join(&self, link_id: usize, listener: RosterListener) {
+        self.lock().insert(link_id, listener.clone());
+        let _ = self.events.send(RosterEvent::Joined(listener));
+    }
+
+    /// Record the latest stats of connection `link_id`.
+    pub fn update(&self, link_id: usize, stats: ListenerStats) {
+        if let Some(listener) = self.lock().get_mut(&link_id) {
+            listener.stats = stats;
+        }
+    }
+
+    /// Connection `link_id` goes by `display_name` from now on (cleaned up
+    /// like a hello's), or by none.
+    pub fn rename(&self, link_id: usize, display_name: Option<&str>) {
+        if let Some(listener) = self.lock().get_mut(&link_id) {
+            listener.display_name = display_name.and_then(clean_display_name);
+        }
+    }
+
+    /// Connection `link_id` started (`true`) or stopped talking back.
+    pub fn set_talking(&self, link_id: usize, talking: bool) {
+        if let Some(listener) = self.lock().get_mut(&link_id) {
+            listener.talkback.talking = talking;
+        }
+    }
+
+    /// The sharer muted (`true`) or unmuted connection `link_id`.
+    pub fn set_talkback_muted(&self, link_id: usize, muted: bool) {
+        if let Some(listener) = self.lock().get_mut(&link_id) {
+            listener.talkback.muted = muted;
+        }
+    }
+
+    /// Remove connection `link_id`, which has ended.
+    pub fn leave(&self, link_id: usize) {
+        let listener = self.lock().remove(&link_id);
+        if let Some(listener) = listener {
+            let _ = self.events.send(RosterEvent::Left(listener));
+        }
+    }
+
+    /// Every connected listener, longest connected first.
+    pub fn listeners(&self) -> Vec<RosterListener> {
+        let mut listeners: Vec<_> = self.lock().values().cloned().collect();
+        listeners.sort_by_key(|l| l.connected_since_ms);
+        listeners
+    }
+}
+
+/// `name` fit for display: trimmed, without control characters and at most
+/// [`MAX_DISPLAY_NAME_LEN`] bytes. `None` if nothing is left.
+pub fn clean_display_name(name: &str) -> Option<String> {
+    let mut clean = String::new();
+    for c in name.trim().chars().filter(|c| !c.is_control()) {
+        if clean.len() + c.len_utf8() > MAX_DISPLAY_NAME_LEN {
+            break;
+        }
+        clean.push(c);
+    }
+    let clean = clean.trim_end();
+    (!clean.is_empty()).then(|| clean.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::test_endpoint_id;
+    use std::time::Duration;
+
+    #[test]
+    fn listeners_join_update_and_leave() {
+        let (roster, mut events) = Roster::new();
+        let start = SystemTime::now();
+        let alice = RosterListener::new(test_endpoint_id(), Some("Alice"), start);
+        let bob = RosterListener::new(test_endpoint_id(), None, start + Duration::from_secs(1));
+
+        roster.join(2, bob.clone());
+        roster.join(1, alice.clone());
+        assert_eq!(events.try_recv().unwrap(), RosterEvent::Joined(bob.clone()));
+        assert_eq!(
+            events.try_recv().unwrap(),
+            RosterEvent::Joined(alice.clone())
+        );
+        assert_eq!(roster.listeners(), vec![alice.clone(), bob.clone()]);
+
+        let stats = ListenerStats {
+            tier_bitrate: 64_000,
+            frames_skipped: 3,
+            ..ListenerStats::default()
+        };
+        roster.update(2, stats.clone());
+        roster.rename(2, Some(" Bob "));
+        roster.set_talking(2, true);
+        roster.set_talkback_muted(2, true);
+        roster.leave(2);
+        let left = RosterListener {
+            display_name: Some("Bob".into()),
+            talkback: Talkback {
+                talking: true,
+                muted: true,
+            },
+            stats,
+            ..bob
+        };
+        assert_eq!(events.try_recv().unwrap(), RosterEvent::Left(left));
+        assert_eq!(roster.listeners(), vec![alice]);
+
+        // Leaving twice, or updating a gone listener, changes nothing
+        roster.leave(2);
+        roster.update(2, ListenerStats::default());
+        assert!(events.try_recv().is_err());
+        assert_eq!(roster.listeners().len(), 1);
+    }
+
+    #[test]
+    fn display_names_are_cleaned() {
+        assert_eq!(clean_display_name("  Alice \n").as_deref(), Some("Alice"));
+        assert_eq!(clean_display_name("Al\u{7}ice").as_deref(), Some("Alice"));
+        assert_eq!(clean_display_name(" \t "), None);
+
+        let long = "é".repeat(MAX_DISPLAY_NAME_LEN);
+        let clean = clean_display_name(&long).unwrap();
+        assert!(clean.len() <= MAX_DISPLAY_NAME_LEN);
+        assert!(long.starts_with(&clean));
+    }
+}
@@ -0,0 +1,164 @@
+//! Persistent app settings.
+//!
+//! Stored as JSON in the app data directory, next to the identity. Fields
+//! missing from the file take their defaults, so settings written by an older
+//! build still load.
+
+use crate::network::NetworkConfig;
+use crate::protocol::MAX_DISPLAY_NAME_LEN;
+use crate::storage;
+use anyhow::{Context, Result, bail};
+use iroh::EndpointId;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// File holding the settings, inside the app data directory.
+const SETTINGS_FILE: &str = "settings.json";
+
+/// Longest contact alias accepted.
+const MAX_ALIAS_LEN: usize = 64;
+
+/// Everything hearme remembers between runs, apart from the identity.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Sharers to listen to by name: alias -> endpoint id.
+    pub contacts: BTreeMap<String, EndpointId>,
+    /// How sharing and listening reach the network.
+    pub network: NetworkConfig,
+    /// The name sharers list us under while we listen.
+    pub display_name: Option<String>,
+}
+
+impl Settings {
+    /// Load the settings stored in `dir`, or the defaults on first run.
+    pub fn load(dir: &Path) -> Result<Self> {
+        let path = settings_path(dir);
+        match fs::read(&path) {
+            Ok(json) => serde_json::from_slice(&json)
+                .with_context(|| format!("Corrupt settings in {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        }
+    }
+
+    /// Store the settings in `dir`.
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        storage::write_atomic(&settings_path(dir), &serde_json::to_vec_pretty(self)?)
+    }
+
+    /// Go by `name` when listening; a blank name goes by none.
+    pub fn set_display_name(&mut self, name: &str) -> Result<()> {
+        let name = name.trim();
+        if name.len() > MAX_DISPLAY_NAME_LEN {
+            bail!("A display name must be at most {MAX_DISPLAY_NAME_LEN} characters");
+        }
+        self.display_name = (!name.is_empty()).then(|| name.to_string());
+        Ok(())
+    }
+
+    /// Remember `id` as `alias`, replacing any contact of the same name.
+    pub fn add_contact(&mut self, alias: &str, id: EndpointId) -> Result<()> {
+        let alias = alias.trim();
+        if alias.is_empty() || alias.len() > MAX_ALIAS_LEN {
+            bail!("A contact name must be 1 to {MAX_ALIAS_LEN} characters");
+        }
+        self.remove_contact(alias);
+        self.contacts.insert(alias.to_string(), id);
+        Ok(())
+    }
+
+    /// Forget the contact called `alias`. Returns whether there was one.
+    pub fn remove_contact(&mut self, alias: &str) -> bool {
+        match self.contact_key(alias) {
+            Some(key) => self.contacts.remove(&key).is_some(),
+            None => false,
+        }
+    }
+
+    /// The endpoint id saved as `alias`, ignoring case.
+    pub fn contact(&self, alias: &str) -> Option<EndpointId> {
+        self.contact_key(alias).map(|key| self.contacts[&key])
+    }
+
+    fn contact_key(&self, alias: &str) -> Option<String> {
+        let alias = alias.trim();
+        self.contacts
+            .keys()
+            .find(|key| key.eq_ignore_ascii_case(alias))
+            .cloned()
+    }
+}
+
+fn settings_path(dir: &Path) -> PathBuf {
+    dir.join(SETTINGS_FILE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::test_endpoint_id;
+
+    #[test]
+    fn missing_settings_are_defaults() {
+        let dir = storage::temp_dir("settings");
+        assert_eq!(Settings::load(&dir).unwrap(), Settings::default());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn settings_survive_reload() {
+        let dir = storage::temp_dir("settings");
+        let mut settings = Settings::default();
+        settings.add_contact("Alice", test_endpoint_id()).unwrap();
+        settings.network = NetworkConfig::lan();
+        settings.set_display_name("Alice's laptop").unwrap();
+        settings.save(&dir).unwrap();
+        assert_eq!(Settings::load(&dir).unwrap(), settings);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn corrupt_settings_are_an_error() {
+        let dir = storage::temp_dir("settings");
+        fs::write(dir.join(SETTINGS_FILE), "{ not json").unwrap();
+        assert!(Settings::load(&dir).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn contacts_match_ignoring_case() {
+        let alice = test_endpoint_id();
+        let mut settings = Settings::default();
+        settings.add_contact(" Alice ", alice).unwrap();
+        assert_eq!(settings.contact("alice"), Some(alice));
+        assert_eq!(settings.contact("bob"), None);
+
+        // Saving under another case replaces the contact
+        let new_alice = test_endpoint_id();
+        settings.add_contact("ALICE", new_alice).unwrap();
+        assert_eq!(settings.contacts.len(), 1);
+        assert_eq!(settings.contact("Alice"), Some(new_alice));
+
+        assert!(settings.remove_contact("alice"));
+        assert!(settings.contacts.is_empty());
+    }
+
+    #[test]
+    fn blank_display_name_is_none() {
+        let mut settings = Settings::default();
+        settings.set_display_name(" Alice ").unwrap();
+        assert_eq!(settings.display_name.as_deref(), Some("Alice"));
+        settings.set_display_name("  ").unwrap();
+        assert_eq!(settings.display_name, None);
+        assert!(settings.set_display_name(&"a".repeat(100)).is_err());
+    }
+
+    #[test]
+    fn blank_alias_is_rejected() {
+        let mut settings = Settings::default();
+        assert!(settings.add_contact("  ", test_endpoint_id()).is_err());
+    }
+}
@@ -0,0 +1,290 @@
+//! Live statistics about the links between a sharer and its listeners.
+//!
+//! Both sides sample the QUIC connection's selected path about once a second
+//! with a [`LinkMeter`]: whether it runs direct or through a relay, to which
+//! address, its RTT, and its loss and throughput since the previous sample.
+//! The listener adds how playout is going ([`ListenStats`]); the sharer keeps
+//! a [`ListenerStats`] for every connected listener in its
+//! [`crate::roster`].
+
+use crate::jitter::JitterBuffer;
+use crate::protocol::ReceptionReport;
+use iroh::endpoint::Connection;
+use iroh::{TransportAddr, Watcher};
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+/// How a connection reaches the peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PathKind {
+    /// Straight to the peer's IP address (hole-punched or on the LAN).
+    Direct,
+    /// Through a relay server.
+    Relay,
+}
+
+/// The state of a connection, as of the latest sample.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct LinkStats {
+    /// How the connection runs; `None` while no path is selected.
+    pub path: Option<PathKind>,
+    /// The peer's end of the path: an IP address and port, or a relay URL.
+    pub remote_addr: Option<String>,
+    pub rtt_ms: f64,
+    /// Share of the packets sent since the previous sample that were lost,
+    /// from 0 to 1.
+    pub loss: f64,
+    pub send_bytes_per_sec: u64,
+    pub recv_bytes_per_sec: u64,
+}
+
+/// Counters read off a connection's selected path.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathReading {
+    pub remote: TransportAddr,
+    pub rtt_ms: f64,
+    pub sent_bytes: u64,
+    pub recv_bytes: u64,
+    pub sent_packets: u64,
+    pub lost_packets: u64,
+}
+
+impl PathReading {
+    /// Read the path `connection` currently sends on, if it has one.
+    pub fn selected(connection: &Connection) -> Option<Self> {
+        let paths = connection.paths().get();
+        let path = paths.iter().find(|p| p.is_selected())?;
+        let stats = path.stats();
+        Some(Self {
+            remote: path.remote_addr().clone(),
+            rtt_ms: stats.rtt.as_secs_f64() * 1000.0,
+            sent_bytes: stats.udp_tx.bytes,
+            recv_bytes: stats.udp_rx.bytes,
+            sent_packets: stats.udp_tx.datagrams,
+            lost_packets: stats.lost_packets,
+        })
+    }
+}
+
+/// Turns successive [`PathReading`]s of one connection into [`LinkStats`].
+#[derive(Debug, Default)]
+pub struct LinkMeter {
+    last: Option<(Instant, PathReading)>,
+}
+
+impl LinkMeter {
+    /// The link's stats as of `reading`, taken at `now`. Rates cover the time
+    /// since the previous reading of the same path; the first reading of a
+    /// path has none yet.
+    pub fn sample(&mut self, reading: Option<PathReading>, now: Instant) -> LinkStats {
+        let Some(reading) = reading else {
+            self.last = None;
+            return LinkStats::default();
+        };
+        let mut stats = LinkStats {
+            path: Some(match reading.remote {
+                TransportAddr::Relay(_) => PathKind::Relay,
+                _ => PathKind::Direct,
+            }),
+            remote_addr: Some(match &reading.remote {
+                TransportAddr::Relay(url) => url.to_string(),
+                TransportAddr::Ip(addr) => addr.to_string(),
+                other => format!("{other:?}"),
+            }),
+            rtt_ms: reading.rtt_ms,
+            ..LinkStats::default()
+        };
+        // Counters are per path, so only compare readings of the same one
+        if let Some((then, last)) = self
+            .last
+            .as_ref()
+            .filter(|(_, l)| l.remote == reading.remote)
+        {
+            let secs = now.saturating_duration_since(*then).as_secs_f64();
+            if secs > 0.0 {
+                let rate = |now: u64, then: u64| (now.saturating_sub(then) as f64 / secs) as u64;
+                stats.send_bytes_per_sec = rate(reading.sent_bytes, last.sent_bytes);
+                stats.recv_bytes_per_sec = rate(reading.recv_bytes, last.recv_bytes);
+            }
+            let sent = reading.sent_packets.saturating_sub(last.sent_packets);
+            let lost = reading.lost_packets.saturating_sub(last.lost_packets);
+            if sent > 0 {
+                stats.loss = (lost as f64 / sent as f64).min(1.0);
+            }
+        }
+        self.last = Some((now, reading));
+        stats
+    }
+}
+
+/// How listening is going: the link and playout.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ListenStats {
+    /// The link to the sharer; `None` while reconnecting.
+    pub link: Option<LinkStats>,
+    /// Frames that arrived in time to be played.
+    pub frames_received: u64,
+    /// Frames that never arrived.
+    pub frames_lost: u64,
+    /// Frames that arrived after their playout slot.
+    pub frames_late: u64,
+    /// Audio waiting in the jitter buffer.
+    pub buffer_ms: u64,
+    /// How much audio the jitter buffer aims to hold.
+    pub target_buffer_ms: u64,
+    /// Interarrival jitter estimate.
+    pub jitter_ms: f64,
+    /// Capture-to-arrival latency of the latest frame, if the clocks allow
+    /// telling.
+    pub latency_ms: Option<f64>,
+}
+
+impl ListenStats {
+    /// Stats of a listen whose link is `link`, whose latest frame took
+    /// `latency` to arrive and whose tracks play out through `jitters`.
+    /// Frames are counted over all tracks; buffering and jitter are the
+    /// worst track's.
+    pub fn new(
+        link: Option<LinkStats>,
+        latency: Option<Duration>,
+        jitters: &[&JitterBuffer],
+    ) -> Self {
+        let mut stats = Self {
+            link,
+            latency_ms: latency.map(|l| l.as_secs_f64() * 1000.0),
+            ..Self::default()
+        };
+        for jitter in jitters {
+            let counts = jitter.stats();
+            stats.frames_received += counts.received;
+            stats.frames_lost += counts.lost;
+            stats.frames_late += counts.late;
+            let buffer_ms = jitter.depth_duration().as_millis() as u64;
+            stats.buffer_ms = stats.buffer_ms.max(buffer_ms);
+            let target_buffer_ms = jitter.target_delay().as_millis() as u64;
+            stats.target_buffer_ms = stats.target_buffer_ms.max(target_buffer_ms);
+            stats.jitter_ms = stats.jitter_ms.max(jitter.jitter().as_secs_f64() * 1000.0);
+        }
+        stats
+    }
+
+    /// The report on these stats the sharer gets.
+    pub fn report(&self) -> ReceptionReport {
+        ReceptionReport {
+            received: self.frames_received,
+            lost: self.frames_lost,
+            late: self.frames_late,
+            jitter_us: (self.jitter_ms * 1000.0) as u32,
+            latency_us: self
+                .latency_ms
+                .map(|l| (l * 1000.0).min(u32::MAX as f64) as u32),
+        }
+    }
+}
+
+/// How one listener of a share is doing.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ListenerStats {
+    /// Bitrate of the tier the listener is served.
+    pub tier_bitrate: u32,
+    pub link: LinkStats,
+    /// Frames skipped because the listener's link fell behind.
+    pub frames_skipped: u64,
+    /// The listener's latest report on its playout, if it has sent one.
+    pub report: Option<ReceptionReport>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{AudioFrame, StreamHeader};
+
+    fn reading(remote: &str, sent_bytes: u64, sent_packets: u64, lost: u64) -> PathReading {
+        PathReading {
+            remote: TransportAddr::Ip(remote.parse().unwrap()),
+            rtt_ms: 12.5,
+            sent_bytes,
+            recv_bytes: sent_bytes / 10,
+            sent_packets,
+            lost_packets: lost,
+        }
+    }
+
+    #[test]
+    fn rates_cover_the_time_since_the_last_sample() {
+        let mut meter = LinkMeter::default();
+        let start = Instant::now();
+
+        let first = meter.sample(Some(reading("192.0.2.7:4433", 1000, 10, 0)), start);
+        assert_eq!(first.path, Some(PathKind::Direct));
+        assert_eq!(first.remote_addr.as_deref(), Some("192.0.2.7:4433"));
+        assert_eq!(first.rtt_ms, 12.5);
+        assert_eq!(first.send_bytes_per_sec, 0);
+
+        let later = start + Duration::from_secs(2);
+        let second = meter.sample(Some(reading("192.0.2.7:4433", 17_000, 110, 5)), later);
+        assert_eq!(second.send_bytes_per_sec, 8000);
+        assert_eq!(second.recv_bytes_per_sec, 800);
+        assert_eq!(second.loss, 0.05);
+    }
+
+    #[test]
+    fn listen_stats_cover_every_track() {
+        let header = StreamHeader::current();
+        let (mut game, mut chat) = (JitterBuffer::new(&header), JitterBuffer::new(&header));
+        let start = Instant::now();
+        for seq in 0..3 {
+            let frame = AudioFrame {
+                track: 0,
+                seq,
+                timestamp: seq * header.frame_size as u64,
+                data: vec![],
+            };
+            let arrival = start + header.frame_duration() * seq as u32;
+            if seq < 2 {
+                chat.push(
+                    AudioFrame {
+                        track: 1,
+                        ..frame.clone()
+                    },
+                    arrival,
+                );
+            }
+            game.push(frame, arrival);
+        }
+
+        let latency = Some(Duration::from_millis(80));
+        let stats = ListenStats::new(None, latency, &[&game, &chat]);
+        assert_eq!(stats.frames_received, 5);
+        assert_eq!(stats.buffer_ms, 60);
+        let report = stats.report();
+        assert_eq!(report.received, 5);
+        assert_eq!(report.latency_us, Some(80_000));
+    }
+
+    #[test]
+    fn switching_path_starts_rates_afresh() {
+        let mut meter = LinkMeter::default();
+        let start = Instant::now();
+        meter.sample(Some(reading("192.0.2.7:4433", 50_000, 500, 0)), start);
+
+        let relay = PathReading {
+            remote: TransportAddr::Relay("https://relay.example.com./".parse().unwrap()),
+            ..reading("192.0.2.7:4433", 100, 1, 1)
+        };
+        let stats = meter.sample(Some(relay), start + Duration::from_secs(1));
+        assert_eq!(stats.path, Some(PathKind::Relay));
+        assert_eq!(
+            stats.remote_addr.as_deref(),
+            Some("https://relay.example.com./")
+        );
+        assert_eq!(stats.send_bytes_per_sec, 0);
+        assert_eq!(stats.loss, 0.0);
+
+        assert_eq!(
+            meter.sample(None, start + Duration::from_secs(2)),
+            LinkStats::default()
+        );
+    }
+}
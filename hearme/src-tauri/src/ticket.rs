@@ -0,0 +1,683 @@
+//! Share tickets: where a sharer is, and what it lets the holder do.
+//!
+//! A ticket carries the sharer's address and a [`Grant`]: claims (expiry,
+//! listener limit, one-time use) signed with the sharer's endpoint key. The
+//! listener presents the grant in its hello and the sharer enforces the
+//! claims with a [`TicketLedger`], so a leaked ticket stops working when it
+//! expires or has been used up, and a forged or edited one never works. The
+//! ledger keeps who used which ticket in the app data directory, next to the
+//! identity, so a used-up ticket stays used up when the sharer restarts.
+//!
+//! Tickets are written as `hearme1` followed by the base32 of their postcard
+//! bytes, short enough to paste into a chat. The sharer can leave relay or
+//! direct addresses out to shorten them further; the listener then looks the
+//! sharer up by its endpoint id. A ticket can also travel as a
+//! `hearme://listen/<ticket>` link, which opens the app, or as a QR code of
+//! that link.
+
+use crate::storage;
+use anyhow::{Context, Result};
+use iroh::{EndpointAddr, EndpointId, SecretKey, Signature};
+use qrcode::QrCode;
+use qrcode::render::svg;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// Prefix of the signed bytes, so a ticket signature can't be mistaken for
+/// any other signature made with the endpoint key.
+const SIGNING_CONTEXT: &[u8] = b"hearme ticket grant v2";
+
+/// Prefix of an encoded ticket; the digit is the version of the format.
+const TICKET_PREFIX: &str = "hearme1";
+
+/// Prefix of a link that opens the app and listens on the ticket after it.
+pub const LISTEN_URI_PREFIX: &str = "hearme://listen/";
+
+/// File recording who used which ticket, inside the app data directory.
+const LEDGER_FILE: &str = "tickets.json";
+
+/// A ticket that a listener uses to connect to a sharer.
+/// Encoded as [`TICKET_PREFIX`] plus base32 postcard for easy copy/paste.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ticket {
+    pub addr: EndpointAddr,
+    /// What the sharer lets the holder do; presented during the handshake.
+    pub grant: Grant,
+}
+
+impl Ticket {
+    /// Encode ticket to a copy-pasteable string.
+    pub fn to_string_encoded(&self) -> Result<String> {
+        let bytes = postcard::to_stdvec(self)?;
+        let base32 = data_encoding::BASE32_NOPAD.encode(&bytes);
+        Ok(format!("{TICKET_PREFIX}{}", base32.to_ascii_lowercase()))
+    }
+
+    /// Decode ticket from the encoded string. Also accepts a
+    /// `hearme://listen/` link. A ticket in the older base64url-encoded JSON
+    /// form carries no grant, so it is recognised and refused with a hint to
+    /// ask for a new one.
+    pub fn from_string_encoded(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let s = s.strip_prefix(LISTEN_URI_PREFIX).unwrap_or(s);
+        match s.strip_prefix(TICKET_PREFIX) {
+            Some(base32) => {
+                let bytes = data_encoding::BASE32_NOPAD
+                    .decode(base32.to_ascii_uppercase().as_bytes())
+                    .context("Ticket is not valid base32")?;
+                Ok(postcard::from_bytes(&bytes).context("Ticket is malformed")?)
+            }
+            None => {
+                let json = data_encoding::BASE64URL_NOPAD.decode(s.as_bytes())?;
+                let legacy: LegacyTicket = serde_json::from_slice(&json)?;
+                anyhow::bail!(
+                    "Ticket from an older hearme (sharer {}); ask the sharer for a new one",
+                    legacy.addr.id
+                )
+            }
+        }
+    }
+
+    /// A `hearme://listen/` link to the ticket, which opens hearme and starts
+    /// listening.
+    pub fn to_uri(&self) -> Result<String> {
+        Ok(format!("{LISTEN_URI_PREFIX}{}", self.to_string_encoded()?))
+    }
+
+    /// An SVG QR code of the ticket's link, for a listener to scan.
+    pub fn to_qr_svg(&self) -> Result<String> {
+        let code =
+            QrCode::new(self.to_uri()?.as_bytes()).context("Ticket is too long for a QR code")?;
+        Ok(code
+            .render::<svg::Color>()
+            .min_dimensions(200, 200)
+            .dark_color(svg::Color("#000000"))
+            .light_color(svg::Color("#ffffff"))
+            .build())
+    }
+
+    /// Keep only the addresses `addrs` asks for.
+    pub fn with_addrs(mut self, addrs: TicketAddrs) -> Self {
+        self.addr.addrs.retain(|addr| match addrs {
+            TicketAddrs::RelayAndDirect => true,
+            TicketAddrs::Relay => addr.is_relay(),
+            TicketAddrs::Direct => addr.is_ip(),
+            TicketAddrs::IdOnly => false,
+        });
+        self
+    }
+}
+
+/// A ticket as hearme wrote it before grants: the sharer's address only,
+/// serialized to JSON and base64url-encoded.
+#[derive(Deserialize)]
+struct LegacyTicket {
+    addr: EndpointAddr,
+}
+
+/// Which of the sharer's addresses a ticket carries. Fewer make a shorter
+/// ticket; a listener finds the sharer by endpoint id all the same, only
+/// through address lookup first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TicketAddrs {
+    #[default]
+    RelayAndDirect,
+    Relay,
+    Direct,
+    IdOnly,
+}
+
+/// Limits the sharer puts on a ticket it hands out.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TicketOptions {
+    /// How long the ticket admits new listeners, in seconds; `None` for as
+    /// long as the share runs.
+    pub valid_for_secs: Option<u64>,
+    /// Most listeners connected on the ticket at once.
+    pub max_listeners: Option<u32>,
+    /// Only the first listener to use the ticket may ever use it.
+    pub one_time: bool,
+    /// Which of the sharer's addresses the ticket carries.
+    #[serde(default)]
+    pub addrs: TicketAddrs,
+}
+
+/// The claims a ticket makes, as signed by the sharer.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Claims {
+    /// The sharer that issued the ticket.
+    pub issuer: EndpointId,
+    /// Random, identifies this ticket among the sharer's tickets.
+    pub token: [u8; 16],
+    /// Seconds since the Unix epoch after which the ticket admits no new
+    /// listeners.
+    pub expires_at: Option<u64>,
+    /// Most listeners connected on the ticket at once.
+    pub max_listeners: Option<u32>,
+    /// Only the first listener to use the ticket may ever use it.
+    pub one_time: bool,
+}
+
+impl Claims {
+    fn signed_bytes(&self) -> Vec<u8> {
+        let mut bytes = SIGNING_CONTEXT.to_vec();
+        bytes.extend(postcard::to_stdvec(self).expect("claims always serialize"));
+        bytes
+    }
+}
+
+/// [`Claims`] and the issuer's signature over them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Grant {
+    pub claims: Claims,
+    pub signature: Signature,
+}
+
+impl Grant {
+    /// Issue a fresh grant limited by `options`, signed with `key`.
+    pub fn issue(key: &SecretKey, options: &TicketOptions, now: SystemTime) -> Self {
+        let claims = Claims {
+            issuer: key.public(),
+            token: rand::random(),
+            expires_at: options
+                .valid_for_secs
+                .map(|secs| unix_secs(now).saturating_add(secs)),
+            max_listeners: options.max_listeners,
+            one_time: options.one_time,
+        };
+        let signature = key.sign(&claims.signed_bytes());
+        Self { claims, signature }
+    }
+
+    /// Check that `issuer` signed these claims.
+    fn verify(&self, issuer: EndpointId) -> Result<(), String> {
+        if self.claims.issuer != issuer {
+            return Err("the ticket is for another sharer".into());
+        }
+        issuer
+            .verify(&self.claims.signed_bytes(), &self.signature)
+            .map_err(|_| "the ticket's signature is invalid".to_string())
+    }
+
+    fn is_expired(&self, now: SystemTime) -> bool {
+        self.claims
+            .expires_at
+            .is_some_and(|expires_at| unix_secs(now) >= expires_at)
+    }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs()
+}
+
+/// Enforces the claims of the tickets one sharer issued.
+///
+/// A listener the ticket has admitted once may come back on it (to
+/// reconnect) even after it expired or was used up; the listener limit
+/// always applies. Who holds each ticket outlives the share session when the
+/// ledger is [loaded](TicketLedger::load) from a directory; open connections
+/// don't.
+#[derive(Debug)]
+pub struct TicketLedger {
+    issuer: EndpointId,
+    tokens: Mutex<HashMap<[u8; 16], TokenUse>>,
+    /// Where holders are saved, if anywhere.
+    path: Option<PathBuf>,
+}
+
+/// What the ledger file holds: each ticket's holders, by hex token.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct LedgerFile {
+    /// The sharer the tokens belong to; a reset identity starts afresh.
+    issuer: Option<EndpointId>,
+    holders: BTreeMap<String, Vec<EndpointId>>,
+}
+
+#[derive(Debug, Default)]
+struct TokenUse {
+    /// Listeners ever admitted on the ticket.
+    holders: HashSet<EndpointId>,
+    /// Connections currently open on the ticket, by
+    /// [`iroh::endpoint::Connection::stable_id`].
+    links: HashMap<usize, EndpointId>,
+}
+
+impl TokenUse {
+    fn check(&self, grant: &Grant, id: EndpointId, now: SystemTime) -> Result<(), String> {
+        let claims = &grant.claims;
+        if !self.holders.contains(&id) {
+            if grant.is_expired(now) {
+                return Err("the ticket has expired".into());
+            }
+            if claims.one_time && !self.holders.is_empty() {
+                return Err("the ticket has already been used".into());
+            }
+        }
+        if let Some(max) = claims.max_listeners {
+            let others: HashSet<_> = self.links.values().filter(|&&l| l != id).collect();
+            if others.len() >= max as usize {
+                return Err("the ticket's listener limit is reached".into());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl TicketLedger {
+    /// A ledger for the tickets `issuer` signs, forgotten when dropped.
+    pub fn new(issuer: EndpointId) -> Self {
+        Self {
+            issuer,
+            tokens: Mutex::new(HashMap::new()),
+            path: None,
+        }
+    }
+
+    /// A ledger for the tickets `issuer` signs that remembers their holders
+    /// in `dir`, picking up those it remembered before.
+    pub fn load(issuer: EndpointId, dir: &Path) -> Result<Self> {
+        let path = dir.join(LEDGER_FILE);
+        let file: LedgerFile = match fs::read(&path) {
+            Ok(json) => serde_json::from_slice(&json)
+                .with_context(|| format!("Corrupt ticket ledger in {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => LedgerFile::default(),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", path.display()));
+            }
+        };
+
+        let mut tokens = HashMap::new();
+        if file.issuer == Some(issuer) {
+            for (hex, holders) in file.holders {
+                let token = data_encoding::HEXLOWER_PERMISSIVE
+                    .decode(hex.as_bytes())
+                    .ok()
+                    .and_then(|bytes| <[u8; 16]>::try_from(bytes).ok())
+                    .with_context(|| format!("Corrupt ticket token in {}", path.display()))?;
+                let token_use = TokenUse {
+                    holders: holders.into_iter().collect(),
+                    links: HashMap::new(),
+                };
+                tokens.insert(token, token_use);
+            }
+        }
+        Ok(Self {
+            issuer,
+            tokens: Mutex::new(tokens),
+            path: Some(path),
+        })
+    }
+
+    /// Write the holders of every ticket to the ledger's file, if it has one.
+    fn save(&self, tokens: &HashMap<[u8; 16], TokenUse>) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let file = LedgerFile {
+            issuer: Some(self.issuer),
+            holders: tokens
+                .iter()
+                .filter(|(_, token)| !token.holders.is_empty())
+                .map(|(token, token_use)| {
+                    let mut holders: Vec<_> = token_use.holders.iter().copied().collect();
+                    holders.sort_by_key(|id| id.to_string());
+                    (data_encoding::HEXLOWER.encode(token), holders)
+                })
+                .collect(),
+        };
+        storage::write_atomic(path, &serde_json::to_vec_pretty(&file)?)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<[u8; 16], TokenUse>> {
+        self.tokens.lock().expect("ticket ledger poisoned")
+    }
+
+    /// Whether `grant` would admit `id` now. Returns the reason if not.
+    pub fn check(&self, grant: &Grant, id: EndpointId, now: SystemTime) -> Result<(), String> {
+        let grant = self.verify(grant)?;
+        match self.lock().get(&grant.claims.token) {
+            Some(token) => token.check(grant, id, now),
+            None => TokenUse::default().check(grant, id, now),
+        }
+    }
+
+    /// Admit `id` on `grant` for the connection `link_id`, re-checking the
+    /// claims so concurrent listeners can't overrun them. Release the
+    /// connection with [`TicketLedger::release`] when it ends.
+    pub fn claim(
+        &self,
+        grant: &Grant,
+        id: EndpointId,
+        link_id: usize,
+        now: SystemTime,
+    ) -> Result<(), String> {
+        let grant = self.verify(grant)?;
+        let mut tokens = self.lock();
+        let token = tokens.entry(grant.claims.token).or_default();
+        token.check(grant, id, now)?;
+        token.links.insert(link_id, id);
+        if token.holders.insert(id) {
+            // Once admitted, a one-time ticket must stay used up even if the
+            // sharer restarts, so don't admit without a record of it.
+            if let Err(e) = self.save(&tokens) {
+                warn!("Failed to record ticket use: {e:#}");
+                let token = tokens
+                    .get_mut(&grant.claims.token)
+                    .expect("token was just claimed");
+                token.holders.remove(&id);
+                token.links.remove(&link_id);
+                return Err("the sharer could not record the ticket's use".into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Forget a connection that has ended.
+    pub fn release(&self, link_id: usize) {
+        for token in self.lock().values_mut() {
+            token.links.remove(&link_id);
+        }
+    }
+
+    fn verify<'a>(&self, grant: &'a Grant) -> Result<&'a Grant, String> {
+        grant.verify(self.issuer)?;
+        Ok(grant)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use iroh::{Endpoint, TransportAddr};
+
+    fn key() -> SecretKey {
+        SecretKey::generate(&mut rand::rng())
+    }
+
+    #[tokio::test]
+    async fn ticket_round_trip() {
+        // Create an endpoint just to get a real EndpointAddr
+        let endpoint = Endpoint::builder().bind().await.unwrap();
+        endpoint.online().await;
+        let addr = endpoint.addr();
+
+        let grant = Grant::issue(
+            endpoint.secret_key(),
+            &TicketOptions::default(),
+            SystemTime::now(),
+        );
+        let ticket = Ticket {
+            addr: addr.clone(),
+            grant,
+        };
+
+        // Encode to string
+        let encoded = ticket.to_string_encoded().unwrap();
+        assert!(!encoded.is_empty());
+
+        // Prefixed lowercase base32 (no padding)
+        let body = encoded.strip_prefix("hearme1").unwrap();
+        assert!(
+            body.chars()
+                .all(|c| c.is_ascii_lowercase() || ('2'..='7').contains(&c))
+        );
+
+        // Decode back
+        let decoded = Ticket::from_string_encoded(&encoded).unwrap();
+
+        // The node ID should match
+        assert_eq!(format!("{:?}", ticket.addr), format!("{:?}", decoded.addr),);
+        assert_eq!(ticket.grant, decoded.grant);
+
+        endpoint.close().await;
+    }
+
+    #[tokio::test]
+    async fn ticket_round_trip_with_whitespace() {
+        let endpoint = Endpoint::builder().bind().await.unwrap();
+        endpoint.online().await;
+
+        let ticket = Ticket {
+            addr: endpoint.addr(),
+            grant: Grant::issue(
+                endpoint.secret_key(),
+                &TicketOptions::default(),
+                SystemTime::now(),
+            ),
+        };
+        let encoded = ticket.to_string_encoded().unwrap();
+
+        // Should tolerate leading/trailing whitespace (e.g. from copy-paste)
+        let padded = format!("  {encoded}\n");
+        let decoded = Ticket::from_string_encoded(&padded);
+        assert!(decoded.is_ok());
+
+        endpoint.close().await;
+    }
+
+    fn test_ticket(key: &SecretKey) -> Ticket {
+        let addr = EndpointAddr::from_parts(
+            key.public(),
+            [
+                TransportAddr::Relay("https://relay.example.com./".parse().unwrap()),
+                TransportAddr::Ip("192.0.2.7:4433".parse().unwrap()),
+            ],
+        );
+        Ticket {
+            addr,
+            grant: Grant::issue(key, &TicketOptions::default(), SystemTime::now()),
+        }
+    }
+
+    #[test]
+    fn compact_ticket_is_shorter_than_legacy_json() {
+        let ticket = test_ticket(&key());
+        let compact = ticket.to_string_encoded().unwrap();
+        let legacy = data_encoding::BASE64URL_NOPAD.encode(&serde_json::to_vec(&ticket).unwrap());
+        assert!(compact.len() < legacy.len(), "{compact} vs {legacy}");
+        assert_eq!(Ticket::from_string_encoded(&compact).unwrap(), ticket);
+    }
+
+    #[test]
+    fn legacy_json_ticket_asks_for_a_new_one() {
+        // {"addr":{"id":"5866…66","addrs":[{"Relay":"https://relay.example.com./"},
+        // {"Ip":"192.0.2.7:4433"}]}}, as hearme wrote tickets before grants.
+        let legacy = "eyJhZGRyIjp7ImlkIjoiNTg2NjY2NjY2NjY2NjY2NjY2NjY2NjY2NjY2NjY2NjY2NjY2NjY2NjY2NjY2NjY2NjY2NjY2NjY2NjY2NjY2NiIsImFkZHJzIjpbeyJSZWxheSI6Imh0dHBzOi8vcmVsYXkuZXhhbXBsZS5jb20uLyJ9LHsiSXAiOiIxOTIuMC4yLjc6NDQzMyJ9XX19";
+        let err = Ticket::from_string_encoded(legacy).unwrap_err().to_string();
+        assert!(err.contains("older hearme"), "{err}");
+        assert!(err.contains("new one"), "{err}");
+    }
+
+    #[test]
+    fn listen_link_round_trip() {
+        let ticket = test_ticket(&key());
+        let uri = ticket.to_uri().unwrap();
+        assert!(uri.starts_with("hearme://listen/hearme1"), "{uri}");
+        assert_eq!(Ticket::from_string_encoded(&uri).unwrap(), ticket);
+    }
+
+    #[test]
+    fn qr_code_is_svg() {
+        let svg = test_ticket(&key()).to_qr_svg().unwrap();
+        assert!(svg.contains("<svg"), "{svg}");
+    }
+
+    #[test]
+    fn compact_ticket_tolerates_uppercase() {
+        let ticket = test_ticket(&key());
+        let encoded = ticket.to_string_encoded().unwrap();
+        let shouted = format!("hearme1{}", encoded[7..].to_ascii_uppercase());
+        assert_eq!(Ticket::from_string_encoded(&shouted).unwrap(), ticket);
+    }
+
+    #[test]
+    fn compact_ticket_with_bad_body_fails() {
+        assert!(Ticket::from_string_encoded("hearme1not-base32!").is_err());
+        assert!(Ticket::from_string_encoded("hearme1aaaa").is_err());
+    }
+
+    #[test]
+    fn ticket_addrs_can_be_left_out() {
+        let ticket = test_ticket(&key());
+        let relay = ticket.clone().with_addrs(TicketAddrs::Relay);
+        assert!(relay.addr.addrs.iter().all(|a| a.is_relay()));
+        assert_eq!(relay.addr.addrs.len(), 1);
+        let direct = ticket.clone().with_addrs(TicketAddrs::Direct);
+        assert!(direct.addr.addrs.iter().all(|a| a.is_ip()));
+        assert_eq!(direct.addr.addrs.len(), 1);
+        let id_only = ticket.clone().with_addrs(TicketAddrs::IdOnly);
+        assert!(id_only.addr.addrs.is_empty());
+        assert_eq!(id_only.addr.id, ticket.addr.id);
+
+        let full = ticket.to_string_encoded().unwrap();
+        let short = id_only.to_string_encoded().unwrap();
+        assert!(short.len() < full.len());
+    }
+
+    #[test]
+    fn ticket_from_invalid_base64_fails() {
+        let result = Ticket::from_string_encoded("not!valid!base64!!!");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn ticket_from_valid_base64_invalid_json_fails() {
+        // Valid base64url but not valid JSON
+        let encoded = data_encoding::BASE64URL_NOPAD.encode(b"not json");
+        let result = Ticket::from_string_encoded(&encoded);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn grant_is_checked_against_its_issuer() {
+        let sharer = key();
+        let now = SystemTime::now();
+        let ledger = TicketLedger::new(sharer.public());
+        let listener = key().public();
+
+        let grant = Grant::issue(&sharer, &TicketOptions::default(), now);
+        assert!(ledger.check(&grant, listener, now).is_ok());
+
+        // Someone else's ticket
+        let forged = Grant::issue(&key(), &TicketOptions::default(), now);
+        let err = ledger.check(&forged, listener, now).unwrap_err();
+        assert!(err.contains("another sharer"), "{err}");
+
+        // Edited claims no longer match the signature
+        let mut edited = Grant::issue(
+            &sharer,
+            &TicketOptions {
+                one_time: true,
+                ..TicketOptions::default()
+            },
+            now,
+        );
+        edited.claims.one_time = false;
+        let err = ledger.check(&edited, listener, now).unwrap_err();
+        assert!(err.contains("signature"), "{err}");
+    }
+
+    #[test]
+    fn expired_ticket_admits_only_earlier_listeners() {
+        let sharer = key();
+        let now = SystemTime::now();
+        let ledger = TicketLedger::new(sharer.public());
+        let options = TicketOptions {
+            valid_for_secs: Some(60),
+            ..TicketOptions::default()
+        };
+        let grant = Grant::issue(&sharer, &options, now);
+        let early = key().public();
+        ledger.claim(&grant, early, 1, now).unwrap();
+        ledger.release(1);
+
+        let later = now + Duration::from_secs(61);
+        let err = ledger.check(&grant, key().public(), later).unwrap_err();
+        assert!(err.contains("expired"), "{err}");
+        // Reconnecting after expiry is fine
+        assert!(ledger.claim(&grant, early, 2, later).is_ok());
+    }
+
+    #[test]
+    fn one_time_ticket_binds_to_first_listener() {
+        let sharer = key();
+        let now = SystemTime::now();
+        let ledger = TicketLedger::new(sharer.public());
+        let options = TicketOptions {
+            one_time: true,
+            ..TicketOptions::default()
+        };
+        let grant = Grant::issue(&sharer, &options, now);
+        let first = key().public();
+        ledger.claim(&grant, first, 1, now).unwrap();
+
+        let err = ledger.claim(&grant, key().public(), 2, now).unwrap_err();
+        assert!(err.contains("already been used"), "{err}");
+        assert!(ledger.claim(&grant, first, 3, now).is_ok());
+
+        // Other tickets are unaffected
+        let other = Grant::issue(&sharer, &options, now);
+        assert!(ledger.claim(&other, key().public(), 4, now).is_ok());
+    }
+
+    #[test]
+    fn listener_limit_counts_open_connections() {
+        let sharer = key();
+        let now = SystemTime::now();
+        let ledger = TicketLedger::new(sharer.public());
+        let options = TicketOptions {
+            max_listeners: Some(2),
+            ..TicketOptions::default()
+        };
+        let grant = Grant::issue(&sharer, &options, now);
+        let (a, b, c) = (key().public(), key().public(), key().public());
+        ledger.claim(&grant, a, 1, now).unwrap();
+        ledger.claim(&grant, b, 2, now).unwrap();
+
+        let err = ledger.claim(&grant, c, 3, now).unwrap_err();
+        assert!(err.contains("limit"), "{err}");
+        // A listener reconnecting before its old connection is noticed gone
+        // doesn't count twice.
+        assert!(ledger.claim(&grant, a, 4, now).is_ok());
+
+        ledger.release(2);
+        assert!(ledger.claim(&grant, c, 5, now).is_ok());
+    }
+
+    #[test]
+    fn used_up_ticket_stays_used_up_after_restart() {
+        let dir = storage::temp_dir("tickets");
+        let sharer = key();
+        let now = SystemTime::now();
+        let options = TicketOptions {
+            one_time: true,
+            ..TicketOptions::default()
+        };
+        let grant = Grant::issue(&sharer, &options, now);
+        let first = key().public();
+        let ledger = TicketLedger::load(sharer.public(), &dir).unwrap();
+        ledger.claim(&grant, first, 1, now).unwrap();
+        drop(ledger);
+
+        // The sharer restarts under the same identity
+        let ledger = TicketLedger::load(sharer.public(), &dir).unwrap();
+        let err = ledger.claim(&grant, key().public(), 1, now).unwrap_err();
+        assert!(err.contains("already been used"), "{err}");
+        assert!(ledger.claim(&grant, first, 2, now).is_ok());
+
+        // A reset identity starts with a clean ledger
+        let other = key();
+        let ledger = TicketLedger::load(other.public(), &dir).unwrap();
+        let grant = Grant::issue(&other, &options, now);
+        assert!(ledger.claim(&grant, key().public(), 1, now).is_ok());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
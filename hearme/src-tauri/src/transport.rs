@@ -4,124 +4,776 @@
 //! - **Share**: captures app audio, encodes Opus, serves to connecting listeners
 //! - **Listen**: connects to a sharer, receives Opus packets, decodes to PCM
 //!
-//! Wire protocol (per Opus frame on the QUIC stream):
-//!   [u16 LE length][opus packet bytes]
+//! The handshake and framing on the QUIC stream live in [`crate::protocol`].
 //!
 //! 1-to-many: each listener opens its own bi-stream. The sharer spawns a task
-//! per listener that reads from a broadcast channel of encoded frames.
+//! per listener, and one per track it subscribes to that reads from the
+//! broadcast channel of the track's encoded frames and writes them to a
+//! uni-stream of the track's own.
 
-use anyhow::{Context, Result};
-use iroh::endpoint::Connection;
+use crate::access::{AccessControl, AccessPolicy};
+use crate::bitrate::{self, BitrateController, LinkSample};
+use crate::capture::{FRAME_SIZE, SAMPLE_RATE};
+use crate::codec::EncoderConfig;
+use crate::network::NetworkConfig;
+use crate::protocol::{
+    self, ALPN, AudioFrame, CLOSE_REMOVED, Hello, ListenerMessage, NowPlaying, PROTOCOL_VERSION,
+    ReceptionReport, Rejected, SharerMessage, StreamHeader, TrackInfo, TrackStream, Welcome,
+};
+use crate::roster::{Roster, RosterEvent, RosterListener};
+use crate::stats::{LinkMeter, LinkStats, ListenerStats, PathReading};
+use crate::ticket::{Grant, Ticket, TicketLedger, TicketOptions};
+use anyhow::{Context, Result, bail};
+use iroh::endpoint::{Connection, ConnectionError, RecvStream, SendStream};
 use iroh::protocol::{AcceptError, ProtocolHandler, Router};
-use iroh::{Endpoint, EndpointAddr};
-use serde::{Deserialize, Serialize};
-use std::sync::Arc;
-use tokio::sync::{broadcast, mpsc};
+use iroh::{Endpoint, EndpointAddr, EndpointId, SecretKey, Watcher};
+use serde::Serialize;
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{broadcast, mpsc, watch};
+use tokio::task::{JoinHandle, JoinSet};
 use tracing::{info, warn};
 
-/// Custom ALPN for hearme audio streams.
-const ALPN: &[u8] = b"/hearme/audio/1";
-
-/// A ticket that a listener uses to connect to a sharer.
-/// Serialized as JSON then base64-encoded for easy copy/paste.
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Ticket {
-    pub addr: EndpointAddr,
-}
-
-impl Ticket {
-    /// Encode ticket to a copy-pasteable string.
-    pub fn to_string_encoded(&self) -> Result<String> {
-        let json = serde_json::to_vec(self)?;
-        Ok(data_encoding::BASE64URL_NOPAD.encode(&json))
-    }
-
-    /// Decode ticket from the encoded string.
-    pub fn from_string_encoded(s: &str) -> Result<Self> {
-        let json = data_encoding::BASE64URL_NOPAD.decode(s.trim().as_bytes())?;
-        Ok(serde_json::from_slice(&json)?)
-    }
-}
-
 // ─── Sharer (server) side ───────────────────────────────────────────
 
+/// How long listeners get to receive the news that the share is ending
+/// before it shuts down.
+const ENDING_GRACE: Duration = Duration::from_secs(1);
+
 /// Handle to an active sharing session. Drop to stop.
 pub struct ShareSession {
     router: Router,
-    /// Send encoded Opus frames here; all connected listeners receive them.
-    pub opus_tx: broadcast::Sender<Arc<Vec<u8>>>,
+    /// Send each track's encoded Opus frames to the channel of their tier:
+    /// by track, then tier (lowest bitrate first). Every listener receives
+    /// the frames of the tracks it subscribes to, on the tier it is on.
+    pub track_txs: Vec<Vec<broadcast::Sender<Arc<AudioFrame>>>>,
+    /// Sample-clock zero for this session's frame timestamps.
+    clock_epoch: Instant,
+    /// Bitrate each tier's encoder should currently use.
+    bitrate_rx: watch::Receiver<Vec<u32>>,
+    /// Who may listen.
+    access: Arc<AccessControl>,
+    /// Listeners waiting for approval, until taken by the UI.
+    approval_rx: Option<mpsc::Receiver<EndpointId>>,
+    /// Who is connected.
+    roster: Arc<Roster>,
+    /// Listeners joining and leaving, until taken by the UI.
+    roster_rx: Option<mpsc::UnboundedReceiver<RosterEvent>>,
+    /// What every listener is told besides audio.
+    announce_tx: watch::Sender<Announcements>,
+    /// Listeners talking back, until taken by the app.
+    talkback_rx: Option<mpsc::Receiver<Talkback>>,
+    /// Listeners whose talking back the sharer doesn't want to hear.
+    muted_tx: watch::Sender<BTreeSet<EndpointId>>,
+}
+
+/// News of a listener talking back to the sharer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Talkback {
+    /// A frame of the listener's voice.
+    Frame(EndpointId, AudioFrame),
+    /// The listener stopped talking back, was muted, or left.
+    Stopped(EndpointId),
 }
 
 impl ShareSession {
-    /// Start sharing. Returns the session and a ticket for listeners.
-    pub async fn start() -> Result<(Self, Ticket)> {
-        let endpoint = Endpoint::builder()
+    /// Start sharing as the endpoint identified by `secret_key`, on
+    /// `network`. Returns the session and a ticket for listeners, limited by
+    /// `ticket`. `encoder` (which decides the tiers) and the catalog of
+    /// `tracks` are announced to listeners in the stream header; `access`
+    /// decides who is served and `tickets` holds them to the claims of the
+    /// tickets they present. The track names may be advertised to listeners
+    /// browsing the local network.
+    pub async fn start(
+        encoder: &EncoderConfig,
+        tracks: Vec<TrackInfo>,
+        access: AccessPolicy,
+        ticket: &TicketOptions,
+        network: &NetworkConfig,
+        secret_key: SecretKey,
+        tickets: TicketLedger,
+    ) -> Result<(Self, Ticket)> {
+        if tracks.is_empty() || tracks.len() > protocol::MAX_TRACKS {
+            bail!("A share carries 1 to {} tracks", protocol::MAX_TRACKS);
+        }
+
+        // Publish our address via DNS/pkarr and on the LAN, as configured,
+        // so listeners who only know our endpoint id can find us.
+        let endpoint = network
+            .sharer_builder(secret_key, &track_names(&tracks))?
             .alpns(vec![ALPN.to_vec()])
             .bind()
             .await?;
 
-        endpoint.online().await;
-        let addr = endpoint.addr();
-        let ticket = Ticket { addr };
-
+        // Without relays there is nothing to wait for; our direct addresses
+        // are known once bound.
+        if let Err(e) = network.wait_online(&endpoint).await {
+            endpoint.close().await;
+            return Err(e);
+        }
         info!("Sharing on endpoint: {}", endpoint.id());
 
-        // Broadcast channel: sharer writes encoded frames, listeners read.
-        // Buffer 50 frames (~1 second of audio) before dropping oldest.
-        let (opus_tx, _) = broadcast::channel::<Arc<Vec<u8>>>(50);
+        // Broadcast channel per track and tier: sharer writes encoded frames,
+        // listeners read. Buffer 50 frames (~1 second of audio) before
+        // dropping oldest.
+        let tiers = encoder.tiers();
+        let track_txs: Vec<Vec<_>> = tracks
+            .iter()
+            .map(|_| {
+                tiers
+                    .iter()
+                    .map(|_| broadcast::channel::<Arc<AudioFrame>>(50).0)
+                    .collect()
+            })
+            .collect();
+
+        let clock_epoch = Instant::now();
+        let clock_epoch_us = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+
+        let (bitrate_tx, bitrate_rx) = watch::channel(tiers.clone());
+        let (access, approval_rx) = AccessControl::new(access);
+        let access = Arc::new(access);
+        let (roster, roster_rx) = Roster::new();
+        let roster = Arc::new(roster);
+        let announce_tx = watch::Sender::new(Announcements::default());
+        let (talkback_tx, talkback_rx) = mpsc::channel(64);
+        let muted_tx = watch::Sender::new(BTreeSet::new());
 
         let handler = AudioShareHandler {
-            opus_tx: opus_tx.clone(),
+            track_txs: track_txs.clone(),
+            bitrate: Arc::new(Mutex::new(BitrateController::new(tiers.clone()))),
+            bitrate_tx,
+            access: access.clone(),
+            tickets: Arc::new(tickets),
+            roster: roster.clone(),
+            announce_tx: announce_tx.clone(),
+            talkback_tx,
+            muted_tx: muted_tx.clone(),
+            header: StreamHeader {
+                clock_epoch_us,
+                encoder: encoder.clone(),
+                tiers,
+                tracks,
+                ..StreamHeader::current()
+            },
         };
 
         let router = Router::builder(endpoint).accept(ALPN, handler).spawn();
 
-        Ok((Self { router, opus_tx }, ticket))
+        let session = Self {
+            router,
+            track_txs,
+            clock_epoch,
+            bitrate_rx,
+            access,
+            approval_rx: Some(approval_rx),
+            roster,
+            roster_rx: Some(roster_rx),
+            announce_tx,
+            talkback_rx: Some(talkback_rx),
+            muted_tx,
+        };
+        let ticket = session.issue_ticket(ticket);
+        Ok((session, ticket))
+    }
+
+    /// Issue another ticket for this share, limited by `options` and carrying
+    /// the addresses it asks for. Every ticket is signed with the sharer's key
+    /// and enforced on its own.
+    pub fn issue_ticket(&self, options: &TicketOptions) -> Ticket {
+        let endpoint = self.router.endpoint();
+        Ticket {
+            addr: endpoint.addr(),
+            grant: Grant::issue(endpoint.secret_key(), options, SystemTime::now()),
+        }
+        .with_addrs(options.addrs)
+    }
+
+    /// A frame clock for stamping the frames of one track of this session.
+    pub fn frame_clock(&self) -> FrameClock {
+        FrameClock::new(self.clock_epoch)
+    }
+
+    /// The bitrate each tier's encoder should use, adapted to the links of
+    /// the listeners on it; never above the tier's own bitrate.
+    pub fn bitrate_targets(&self) -> watch::Receiver<Vec<u32>> {
+        self.bitrate_rx.clone()
+    }
+
+    /// Who may listen: change the policy, approve or remove listeners.
+    pub fn access(&self) -> &AccessControl {
+        &self.access
+    }
+
+    /// Listeners waiting for approval under [`AccessPolicy::ApproveOnConnect`];
+    /// answer each with [`AccessControl::decide`]. Only the first call gets
+    /// the receiver.
+    pub fn take_approval_requests(&mut self) -> Option<mpsc::Receiver<EndpointId>> {
+        self.approval_rx.take()
+    }
+
+    /// The connected listeners, with their stats refreshed about once a
+    /// second.
+    pub fn roster(&self) -> Arc<Roster> {
+        self.roster.clone()
+    }
+
+    /// Listeners joining and leaving the [`Self::roster`]. Only the first
+    /// call gets the receiver.
+    pub fn take_roster_events(&mut self) -> Option<mpsc::UnboundedReceiver<RosterEvent>> {
+        self.roster_rx.take()
     }
 
-    /// Shut down the sharing session.
+    /// Listeners talking back. Only the first call gets the receiver.
+    pub fn take_talkback(&mut self) -> Option<mpsc::Receiver<Talkback>> {
+        self.talkback_rx.take()
+    }
+
+    /// Stop hearing `listener` talk back (`muted`), or hear it again. The
+    /// listener is told.
+    pub fn set_talkback_muted(&self, listener: EndpointId, muted: bool) {
+        self.muted_tx.send_if_modified(|muted_ids| match muted {
+            true => muted_ids.insert(listener),
+            false => muted_ids.remove(&listener),
+        });
+    }
+
+    /// Tell listeners what is playing from now on (`None`: can't tell).
+    pub fn set_now_playing(&self, now_playing: Option<NowPlaying>) {
+        set_now_playing(&self.announce_tx, now_playing);
+    }
+
+    /// Tell listeners whatever `now_playing` says is playing, until its
+    /// sender goes.
+    pub fn follow_now_playing(&self, mut now_playing: watch::Receiver<Option<NowPlaying>>) {
+        let announce_tx = self.announce_tx.clone();
+        tokio::spawn(async move {
+            loop {
+                let latest = now_playing.borrow_and_update().clone();
+                set_now_playing(&announce_tx, latest);
+                if now_playing.changed().await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    /// Pause or resume the audio. Listeners stay connected and are told.
+    pub fn set_paused(&self, paused: bool) {
+        self.announce_tx.send_if_modified(|a| {
+            let changed = a.paused != paused;
+            a.paused = paused;
+            changed
+        });
+    }
+
+    /// Shut down the sharing session, telling listeners it is over so they
+    /// don't try to reconnect.
     pub async fn stop(self) -> Result<()> {
+        self.announce_tx
+            .send_modify(|a| a.ending = Some("the sharer stopped sharing".into()));
+        // Every listener drops its subscription once it has the news
+        let _ = tokio::time::timeout(ENDING_GRACE, self.announce_tx.closed()).await;
         self.router.shutdown().await?;
         Ok(())
     }
 }
 
+fn set_now_playing(announce_tx: &watch::Sender<Announcements>, now_playing: Option<NowPlaying>) {
+    announce_tx.send_if_modified(|a| {
+        if a.now_playing == now_playing {
+            return false;
+        }
+        a.now_playing = now_playing;
+        a.now_playing_at = Some(Instant::now());
+        true
+    });
+}
+
+/// What the sharer tells its listeners besides audio.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Announcements {
+    now_playing: Option<NowPlaying>,
+    /// When `now_playing` was told, so listeners joining later get its
+    /// position as of when they join.
+    now_playing_at: Option<Instant>,
+    paused: bool,
+    /// Why the share is ending, once it is.
+    ending: Option<String>,
+}
+
+impl Announcements {
+    /// The messages that bring a listener last told `previous` up to date.
+    fn changes_since(&self, previous: &Self) -> Vec<SharerMessage> {
+        let mut messages = Vec::new();
+        if self.now_playing != previous.now_playing {
+            let elapsed = self.now_playing_at.map(|at| at.elapsed());
+            messages.push(SharerMessage::NowPlaying(
+                self.now_playing
+                    .as_ref()
+                    .map(|n| n.advanced(elapsed.unwrap_or_default())),
+            ));
+        }
+        if self.paused != previous.paused {
+            messages.push(SharerMessage::Paused(self.paused));
+        }
+        if let Some(reason) = self.ending.as_ref().filter(|_| previous.ending.is_none()) {
+            messages.push(SharerMessage::Ending {
+                reason: reason.clone(),
+            });
+        }
+        messages
+    }
+}
+
+/// Assigns sequence numbers and sample-clock timestamps to captured frames.
+///
+/// Timestamps advance by exactly one frame per frame while capture keeps up
+/// with real time, and snap back to the wall clock after a stall (e.g. the
+/// captured app went quiet and its audio node stopped producing buffers).
+#[derive(Debug)]
+pub struct FrameClock {
+    epoch: Instant,
+    next_seq: u64,
+    next_timestamp: Option<u64>,
+}
+
+/// How far the running timestamp may drift from the wall clock before it is
+/// resynchronised, in samples (100ms).
+const MAX_CLOCK_DRIFT: u64 = SAMPLE_RATE as u64 / 10;
+
+impl FrameClock {
+    pub fn new(epoch: Instant) -> Self {
+        Self {
+            epoch,
+            next_seq: 0,
+            next_timestamp: None,
+        }
+    }
+
+    /// Stamp a frame whose last sample was captured at `captured_at`.
+    /// Returns `(seq, timestamp)`.
+    pub fn stamp(&mut self, captured_at: Instant) -> (u64, u64) {
+        let elapsed = captured_at.saturating_duration_since(self.epoch);
+        let end = elapsed.as_micros() as u64 * SAMPLE_RATE as u64 / 1_000_000;
+        let wall = end.saturating_sub(FRAME_SIZE as u64);
+
+        let timestamp = match self.next_timestamp {
+            Some(ts) if ts.abs_diff(wall) <= MAX_CLOCK_DRIFT => ts,
+            _ => wall,
+        };
+        let seq = self.next_seq;
+
+        self.next_seq += 1;
+        self.next_timestamp = Some(timestamp + FRAME_SIZE as u64);
+        (seq, timestamp)
+    }
+}
+
 /// Protocol handler: accepts connections from listeners and streams audio.
 #[derive(Debug, Clone)]
 struct AudioShareHandler {
-    opus_tx: broadcast::Sender<Arc<Vec<u8>>>,
+    track_txs: Vec<Vec<broadcast::Sender<Arc<AudioFrame>>>>,
+    /// Describes the stream; sent to every listener after its hello.
+    header: StreamHeader,
+    /// Tracks every listener's link; its targets are published on `bitrate_tx`.
+    bitrate: Arc<Mutex<BitrateController>>,
+    bitrate_tx: watch::Sender<Vec<u32>>,
+    access: Arc<AccessControl>,
+    /// Enforces the claims of the tickets listeners present.
+    tickets: Arc<TicketLedger>,
+    roster: Arc<Roster>,
+    announce_tx: watch::Sender<Announcements>,
+    talkback_tx: mpsc::Sender<Talkback>,
+    muted_tx: watch::Sender<BTreeSet<EndpointId>>,
+}
+
+impl AudioShareHandler {
+    /// Apply `f` to the bitrate controller and publish its new targets.
+    fn update_bitrate(&self, f: impl FnOnce(&mut BitrateController)) {
+        let targets = {
+            let mut controller = self.bitrate.lock().expect("bitrate controller poisoned");
+            f(&mut controller);
+            controller.targets()
+        };
+        self.bitrate_tx.send_if_modified(|current| {
+            if *current == targets {
+                return false;
+            }
+            info!("Adapting tier bitrates: {current:?} -> {targets:?} bps");
+            *current = targets;
+            true
+        });
+    }
+
+    /// Admit `remote` if its ticket is good and the access policy lets it
+    /// in. Returns the reason for refusing, if refused.
+    ///
+    /// A listener without a ticket knows no more than our endpoint id, so
+    /// only a policy that checks who is listening can admit it.
+    async fn admit(
+        &self,
+        remote: EndpointId,
+        link_id: usize,
+        grant: Option<&Grant>,
+    ) -> Result<(), String> {
+        let Some(grant) = grant else {
+            if !self.access.checks_identity() {
+                return Err("the sharer only admits listeners with a ticket".into());
+            }
+            return self.access.admit(remote).await;
+        };
+        // Don't ask the sharer about a listener the ticket won't admit anyway
+        self.tickets.check(grant, remote, SystemTime::now())?;
+        self.access.admit(remote).await?;
+        self.tickets
+            .claim(grant, remote, link_id, SystemTime::now())
+    }
+
+    /// The tier a listener's link should currently be served.
+    fn tier(&self, link_id: usize) -> usize {
+        self.bitrate
+            .lock()
+            .expect("bitrate controller poisoned")
+            .tier(link_id)
+    }
+
+    /// Spawn a task sending `track` to the listener on `connection`, until
+    /// aborted. Frames the listener's link falls behind on are counted in
+    /// `lagged_frames`.
+    fn serve_track(
+        &self,
+        connection: &Connection,
+        link_id: usize,
+        track: u8,
+        lagged_frames: Arc<AtomicU64>,
+    ) -> JoinHandle<()> {
+        let handler = self.clone();
+        let connection = connection.clone();
+        tokio::spawn(async move {
+            let result = handler
+                .send_track(&connection, link_id, track, &lagged_frames)
+                .await;
+            if let Err(e) = result {
+                info!(
+                    "Stopped sending track {track} to {}: {e:#}",
+                    connection.remote_id()
+                );
+            }
+        })
+    }
+
+    /// Send `track` on a uni-stream of its own, on whichever tier the
+    /// listener's link should be served.
+    async fn send_track(
+        &self,
+        connection: &Connection,
+        link_id: usize,
+        track: u8,
+        lagged_frames: &AtomicU64,
+    ) -> Result<()> {
+        let mut send = connection.open_uni().await?;
+        protocol::write_msg(&mut send, &TrackStream { track }).await?;
+        let tier_txs = &self.track_txs[usize::from(track)];
+        let mut tier = self.tier(link_id);
+        let mut opus_rx = tier_txs[tier].subscribe();
+        let mut last_seq = None;
+        loop {
+            match opus_rx.recv().await {
+                Ok(frame) => {
+                    // Every tier stamps the same seq, so after a switch skip
+                    // frames this listener already has.
+                    if last_seq.is_some_and(|seq| frame.seq <= seq)
+                        || self.announce_tx.borrow().paused
+                    {
+                        continue;
+                    }
+                    protocol::write_msg(&mut send, &*frame).await?;
+                    last_seq = Some(frame.seq);
+
+                    let next_tier = self.tier(link_id);
+                    if next_tier != tier {
+                        tier = next_tier;
+                        opus_rx = tier_txs[tier].subscribe();
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    warn!(
+                        "Listener {} lagged by {n} frames of track {track}, skipping",
+                        connection.remote_id()
+                    );
+                    lagged_frames.fetch_add(n, Ordering::Relaxed);
+                }
+                Err(broadcast::error::RecvError::Closed) => return Ok(()),
+            }
+        }
+    }
+
+    /// Hand the voice of `listener` (connected as `link_id`), talking back
+    /// on `recv`, to the app, except while muted, until it stops.
+    async fn receive_talkback(self, mut recv: RecvStream, listener: EndpointId, link_id: usize) {
+        self.roster.set_talking(link_id, true);
+        while let Ok(frame) = protocol::read_msg::<_, AudioFrame>(&mut recv).await {
+            if self.muted_tx.borrow().contains(&listener) {
+                continue;
+            }
+            if self
+                .talkback_tx
+                .send(Talkback::Frame(listener, frame))
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+        self.roster.set_talking(link_id, false);
+        let _ = self.talkback_tx.send(Talkback::Stopped(listener)).await;
+    }
+}
+
+/// The names of `tracks`, as one.
+fn track_names(tracks: &[TrackInfo]) -> String {
+    let names: Vec<_> = tracks.iter().map(|t| t.name.as_str()).collect();
+    names.join(" + ")
+}
+
+/// Sample the link to a listener from the connection's selected path.
+fn link_sample(
+    connection: &Connection,
+    lagged_frames: u64,
+    report: Option<ReceptionReport>,
+) -> LinkSample {
+    let mut sample = LinkSample {
+        lagged_frames,
+        report,
+        ..LinkSample::default()
+    };
+    if let Some(path) = connection.paths().get().iter().find(|p| p.is_selected()) {
+        let stats = path.stats();
+        sample.rtt = stats.rtt;
+        sample.cwnd = stats.cwnd;
+        sample.sent_packets = stats.udp_tx.datagrams;
+        sample.lost_packets = stats.lost_packets;
+    }
+    sample
 }
 
 impl ProtocolHandler for AudioShareHandler {
     async fn accept(&self, connection: Connection) -> Result<(), AcceptError> {
-        let mut opus_rx = self.opus_tx.subscribe();
         let remote = connection.remote_id();
         info!("Listener connected: {remote}");
 
-        // Accept a bi-stream from the listener (they open it to signal readiness)
-        let (mut send, _recv) = connection.accept_bi().await?;
+        // Accept a bi-stream from the listener; it opens with a hello, which
+        // we can only decode if it speaks our version
+        let (mut send, mut recv) = connection.accept_bi().await?;
+        let version = protocol::read_version(&mut recv)
+            .await
+            .map_err(|e| AcceptError::from_boxed(e.into()))?;
+        let hello: Option<Hello> = if version == PROTOCOL_VERSION {
+            let hello = protocol::read_msg(&mut recv)
+                .await
+                .map_err(|e| AcceptError::from_boxed(e.into()))?;
+            Some(hello)
+        } else {
+            None
+        };
+
+        let link_id = connection.stable_id();
+        let refusal = match &hello {
+            None => Some(format!(
+                "unsupported protocol version {version} (sharer speaks {PROTOCOL_VERSION})"
+            )),
+            Some(hello) => match protocol::check_hello(hello, &self.header) {
+                Some(reason) => Some(reason),
+                None => self
+                    .admit(remote, link_id, hello.grant.as_ref())
+                    .await
+                    .err(),
+            },
+        };
+        let welcome = match refusal {
+            Some(reason) => {
+                warn!("Rejecting listener {remote}: {reason}");
+                Welcome::Reject { reason }
+            }
+            None => Welcome::Accept {
+                header: self.header.clone(),
+            },
+        };
+        let accepted = matches!(welcome, Welcome::Accept { .. });
+        if let Err(e) = protocol::write_handshake(&mut send, &welcome).await {
+            self.tickets.release(link_id);
+            return Err(AcceptError::from_boxed(e.into()));
+        }
+        let Some(hello) = hello.filter(|_| accepted) else {
+            send.finish()?;
+            connection.closed().await;
+            return Ok(());
+        };
 
-        // Stream Opus frames to this listener
+        self.access.register(&connection);
+        self.update_bitrate(|c| c.add_link(link_id, hello.max_bitrate));
+
+        // The listener's half of the stream now carries its feedback
+        let (report_tx, report_rx) = watch::channel(None);
+        let (subscribe_tx, mut subscribe_rx) = mpsc::unbounded_channel();
+        let handler = self.clone();
+        let feedback_task = tokio::spawn(async move {
+            while let Ok(msg) = protocol::read_msg::<_, ListenerMessage>(&mut recv).await {
+                match msg {
+                    ListenerMessage::Report(report) => {
+                        let _ = report_tx.send(Some(report));
+                    }
+                    ListenerMessage::RequestQuality { max_bitrate } => {
+                        info!("Listener {remote} requested max bitrate {max_bitrate:?}");
+                        handler.update_bitrate(|c| c.set_requested(link_id, max_bitrate));
+                    }
+                    ListenerMessage::DisplayName(name) => {
+                        handler.roster.rename(link_id, name.as_deref());
+                    }
+                    ListenerMessage::Subscribe(track) => {
+                        let _ = subscribe_tx.send((track, true));
+                    }
+                    ListenerMessage::Unsubscribe(track) => {
+                        let _ = subscribe_tx.send((track, false));
+                    }
+                }
+            }
+        });
+
+        let mut tier = self.tier(link_id);
+        let mut listener =
+            RosterListener::new(remote, hello.display_name.as_deref(), SystemTime::now());
+        listener.stats.tier_bitrate = self.bitrate_tx.borrow()[tier];
+        info!("Listener {remote} joined as {:?}", listener.display_name);
+        self.roster.join(link_id, listener);
+        let lagged_frames = Arc::new(AtomicU64::new(0));
+        let mut track_tasks: BTreeMap<u8, JoinHandle<()>> = (0..self.header.tracks.len() as u8)
+            .filter(|track| !hello.unsubscribed.contains(track))
+            .map(|track| {
+                let task = self.serve_track(&connection, link_id, track, lagged_frames.clone());
+                (track, task)
+            })
+            .collect();
+        let mut sample_tick = tokio::time::interval(bitrate::SAMPLE_INTERVAL);
+        let mut meter = LinkMeter::default();
+        // Bring the listener up to date with the announcements straight away
+        let mut announce_rx = self.announce_tx.subscribe();
+        announce_rx.mark_changed();
+        let mut announced = Announcements::default();
+        // Likewise with whether we hear it talk back
+        let mut muted_rx = self.muted_tx.subscribe();
+        muted_rx.mark_changed();
+        let mut muted = false;
+        let mut talkback_task: Option<JoinHandle<()>> = None;
+
+        // Serve the listener's subscriptions, hear it talk back and tell it
+        // the news
         loop {
-            match opus_rx.recv().await {
-                Ok(packet) => {
-                    let len = packet.len() as u16;
-                    // Write length-prefixed packet
-                    if send.write_all(&len.to_le_bytes()).await.is_err() {
+            tokio::select! {
+                _ = connection.closed() => break,
+                incoming = connection.accept_uni() => {
+                    let Ok(talkback_recv) = incoming else { break };
+                    // One voice per listener: a new stream replaces the old
+                    if let Some(task) = talkback_task.take() {
+                        task.abort();
+                        let _ = self.talkback_tx.send(Talkback::Stopped(remote)).await;
+                    }
+                    let handler = self.clone();
+                    talkback_task = Some(tokio::spawn(
+                        handler.receive_talkback(talkback_recv, remote, link_id),
+                    ));
+                }
+                Ok(()) = muted_rx.changed() => {
+                    let now_muted = muted_rx.borrow_and_update().contains(&remote);
+                    if now_muted != muted {
+                        muted = now_muted;
+                        self.roster.set_talkback_muted(link_id, muted);
+                        // Its voice starts afresh once unmuted
+                        if muted {
+                            let _ = self.talkback_tx.send(Talkback::Stopped(remote)).await;
+                        }
+                        let msg = SharerMessage::TalkbackMuted(muted);
+                        if protocol::write_msg(&mut send, &msg).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+                Some((track, subscribed)) = subscribe_rx.recv() => {
+                    if usize::from(track) >= self.header.tracks.len() {
+                        warn!("Listener {remote} asked for unknown track {track}");
+                    } else if !subscribed {
+                        // Dropping the stream finishes it
+                        if let Some(task) = track_tasks.remove(&track) {
+                            task.abort();
+                        }
+                    } else if let Entry::Vacant(entry) = track_tasks.entry(track) {
+                        entry.insert(self.serve_track(&connection, link_id, track, lagged_frames.clone()));
+                    }
+                }
+                Ok(()) = announce_rx.changed() => {
+                    let current = announce_rx.borrow_and_update().clone();
+                    let messages = current.changes_since(&announced);
+                    announced = current;
+                    let sent: Result<()> = async {
+                        for msg in &messages {
+                            protocol::write_msg(&mut send, msg).await?;
+                        }
+                        Ok(())
+                    }
+                    .await;
+                    if sent.is_err() {
                         break;
                     }
-                    if send.write_all(&packet).await.is_err() {
+                    if announced.ending.is_some() {
+                        // Hang up once the listener has the news
+                        let _ = send.finish();
+                        let _ = tokio::time::timeout(ENDING_GRACE, send.stopped()).await;
                         break;
                     }
                 }
-                Err(broadcast::error::RecvError::Lagged(n)) => {
-                    warn!("Listener {remote} lagged by {n} frames, skipping");
-                    continue;
+                _ = sample_tick.tick() => {
+                    let report = *report_rx.borrow();
+                    let skipped = lagged_frames.load(Ordering::Relaxed);
+                    let sample = link_sample(&connection, skipped, report);
+                    self.update_bitrate(|c| c.update(link_id, sample, Instant::now()));
+                    let next_tier = self.tier(link_id);
+                    if next_tier != tier {
+                        info!(
+                            "Moving listener {remote} to the {} bps tier",
+                            self.header.tiers[next_tier]
+                        );
+                        tier = next_tier;
+                    }
+                    let link = meter.sample(PathReading::selected(&connection), Instant::now());
+                    self.roster.update(link_id, ListenerStats {
+                        tier_bitrate: self.bitrate_tx.borrow()[tier],
+                        link,
+                        frames_skipped: skipped,
+                        report,
+                    });
                 }
-                Err(broadcast::error::RecvError::Closed) => break,
             }
         }
 
+        for task in track_tasks.into_values() {
+            task.abort();
+        }
+        if let Some(task) = talkback_task {
+            task.abort();
+            let _ = self.talkback_tx.send(Talkback::Stopped(remote)).await;
+        }
+        feedback_task.abort();
+        self.access.unregister(&connection);
+        self.tickets.release(link_id);
+        self.roster.leave(link_id);
+        self.update_bitrate(|c| c.remove_link(link_id));
         info!("Listener disconnected: {remote}");
         Ok(())
     }
@@ -129,135 +781,678 @@ impl ProtocolHandler for AudioShareHandler {
 
 // ─── Listener (client) side ─────────────────────────────────────────
 
+/// First delay before reconnecting to a sharer; doubles on every attempt.
+const RECONNECT_INITIAL_DELAY: Duration = Duration::from_millis(500);
+/// Longest delay between two reconnection attempts.
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(10);
+/// Attempts before giving up on a sharer (about a minute and a half).
+const MAX_RECONNECT_ATTEMPTS: u32 = 12;
+/// How often the listener samples its link to the sharer.
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Why a listening session ended on its own.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ListenEnd {
+    /// The sharer ended the share.
+    Finished { reason: String },
+    /// The sharer turned us away or removed us.
+    Refused { reason: String },
+    /// The network failed: the sharer stayed unreachable.
+    Unreachable,
+}
+
+/// What a listening session delivers to its consumer.
+#[derive(Debug)]
+pub enum ListenEvent {
+    /// An audio frame from the sharer.
+    Frame(AudioFrame),
+    /// What the sharer is playing from now on, if it can tell.
+    NowPlaying(Option<NowPlaying>),
+    /// The sharer paused (`true`) or resumed (`false`) its audio.
+    Paused(bool),
+    /// The sharer muted (`true`) or unmuted us talking back.
+    TalkbackMuted(bool),
+    /// The latest stats of the link to the sharer, about once a second.
+    Link(LinkStats),
+    /// The stream broke; reconnection attempt `attempt` is about to start.
+    Reconnecting { attempt: u32 },
+    /// The stream is back. The sharer may have restarted, so sequence
+    /// numbers and timestamps start afresh under this header.
+    Resumed(StreamHeader),
+    /// The stream is gone for good and won't be retried.
+    Ended(ListenEnd),
+}
+
 /// Handle to a listening session. Drop to stop.
 pub struct ListenSession {
     endpoint: Endpoint,
     stop_tx: tokio::sync::oneshot::Sender<()>,
+    header: StreamHeader,
+    report_tx: watch::Sender<Option<ReceptionReport>>,
+    quality_tx: watch::Sender<Option<u32>>,
+    name_tx: watch::Sender<Option<String>>,
+    /// Tracks we don't want, by index.
+    unsubscribed_tx: watch::Sender<BTreeSet<u8>>,
+    uplink_rx: watch::Receiver<Uplink>,
 }
 
-impl ListenSession {
-    /// Connect to a sharer and start receiving audio.
-    /// Returns decoded PCM frames via the mpsc channel.
-    pub async fn connect(ticket: &Ticket) -> Result<(Self, mpsc::Receiver<Vec<u8>>)> {
-        let endpoint = Endpoint::bind().await?;
-        endpoint.online().await;
-
-        let conn = endpoint
-            .connect(ticket.addr.clone(), ALPN)
+/// Where to talk back to the sharer.
+#[derive(Debug, Clone, Default)]
+struct Uplink {
+    /// The link we're on, if any.
+    conn: Option<Connection>,
+    /// Whether the sharer muted us on it.
+    muted: bool,
+}
+
+/// The sharer a listening session connects to.
+#[derive(Debug, Clone)]
+pub struct ListenTarget {
+    /// Where the sharer was last known to be; may hold no addresses at all,
+    /// in which case they are looked up by endpoint id.
+    pub addr: EndpointAddr,
+    /// The grant of the ticket we were given, if any.
+    pub grant: Option<Grant>,
+}
+
+impl From<&Ticket> for ListenTarget {
+    fn from(ticket: &Ticket) -> Self {
+        Self {
+            addr: ticket.addr.clone(),
+            grant: Some(ticket.grant.clone()),
+        }
+    }
+}
+
+impl From<EndpointId> for ListenTarget {
+    /// A sharer known only by its endpoint id. It admits us only if its
+    /// access policy lets us in by our own endpoint id.
+    fn from(id: EndpointId) -> Self {
+        Self {
+            addr: EndpointAddr::new(id),
+            grant: None,
+        }
+    }
+}
+
+/// A handshaken stream to the sharer.
+struct SharerLink {
+    conn: Connection,
+    send: SendStream,
+    recv: RecvStream,
+    header: StreamHeader,
+    /// The tracks our hello left out.
+    unsubscribed: BTreeSet<u8>,
+}
+
+/// Opens [`SharerLink`]s to one sharer, as often as the stream breaks.
+struct Dialer {
+    endpoint: Endpoint,
+    target: ListenTarget,
+    /// The name we go by in the sharer's roster.
+    display_name: watch::Receiver<Option<String>>,
+    /// The tracks we don't want.
+    unsubscribed: watch::Receiver<BTreeSet<u8>>,
+}
+
+impl Dialer {
+    /// Connect to the sharer and handshake before any audio flows.
+    async fn open(&self, max_bitrate: Option<u32>) -> Result<SharerLink> {
+        let conn = self
+            .endpoint
+            .connect(self.target.addr.clone(), ALPN)
             .await
             .context("Failed to connect to sharer")?;
 
         info!("Connected to sharer: {}", conn.remote_id());
 
-        // Open bi-stream to signal we're ready
-        let (send, mut recv) = conn.open_bi().await.context("Failed to open bi-stream")?;
+        let (mut send, mut recv) = conn.open_bi().await.context("Failed to open bi-stream")?;
+        let unsubscribed = self.unsubscribed.borrow().clone();
+        let hello = Hello {
+            max_bitrate,
+            grant: self.target.grant.clone(),
+            display_name: self.display_name.borrow().clone(),
+            unsubscribed: unsubscribed.iter().copied().collect(),
+            ..Hello::current()
+        };
+        protocol::write_handshake(&mut send, &hello)
+            .await
+            .context("Failed to send hello")?;
+        let version = protocol::read_version(&mut recv)
+            .await
+            .context("Sharer did not answer the handshake")?;
+        if version != PROTOCOL_VERSION {
+            bail!("Incompatible sharer: protocol version {version}, expected {PROTOCOL_VERSION}");
+        }
+        let welcome: Welcome = protocol::read_msg(&mut recv)
+            .await
+            .context("Sharer did not answer the handshake")?;
+        let header = protocol::check_welcome(welcome)?;
+        info!("Stream header: {header:?}");
+
+        Ok(SharerLink {
+            conn,
+            send,
+            recv,
+            header,
+            unsubscribed,
+        })
+    }
+}
 
-        let (opus_tx, opus_rx) = mpsc::channel::<Vec<u8>>(64);
-        let (stop_tx, mut stop_rx) = tokio::sync::oneshot::channel::<()>();
+impl ListenSession {
+    /// Connect to `target` as the endpoint identified by `secret_key`, over
+    /// `network`, and start receiving audio, at most `max_bitrate` if given.
+    /// `display_name` is how the sharer lists us, if given.
+    /// Returns frames and reconnection news via the mpsc channel, which closes
+    /// when the session is stopped or the sharer is gone for good.
+    ///
+    /// The first connection must succeed; after that a broken stream is
+    /// retried with exponential backoff, unless the sharer refused or removed
+    /// us, or ended the share ([`ListenEvent::Ended`]).
+    pub async fn connect(
+        target: &ListenTarget,
+        max_bitrate: Option<u32>,
+        display_name: Option<String>,
+        network: &NetworkConfig,
+        secret_key: SecretKey,
+    ) -> Result<(Self, mpsc::Receiver<ListenEvent>)> {
+        let endpoint = network.listener_builder(secret_key)?.bind().await?;
+        if let Err(e) = network.wait_online(&endpoint).await {
+            endpoint.close().await;
+            return Err(e);
+        }
 
-        // Spawn receive loop
-        tokio::spawn(async move {
-            let mut len_buf = [0u8; 2];
-            loop {
-                tokio::select! {
-                    _ = &mut stop_rx => break,
-                    result = recv.read_exact(&mut len_buf) => {
-                        match result {
-                            Ok(()) => {},
-                            Err(_) => break,
+        let (name_tx, name_rx) = watch::channel(display_name);
+        let (unsubscribed_tx, unsubscribed_rx) = watch::channel(BTreeSet::new());
+        let dialer = Dialer {
+            endpoint: endpoint.clone(),
+            target: target.clone(),
+            display_name: name_rx,
+            unsubscribed: unsubscribed_rx,
+        };
+        let link = dialer.open(max_bitrate).await?;
+        let header = link.header.clone();
+
+        let (event_tx, event_rx) = mpsc::channel::<ListenEvent>(64);
+        let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+        let (report_tx, report_rx) = watch::channel(None);
+        let (quality_tx, quality_rx) = watch::channel(max_bitrate);
+        let (uplink_tx, uplink_rx) = watch::channel(Uplink::default());
+
+        tokio::spawn(run_listener(
+            dialer, link, event_tx, stop_rx, report_rx, quality_rx, uplink_tx,
+        ));
+
+        Ok((
+            Self {
+                endpoint,
+                stop_tx,
+                header,
+                report_tx,
+                quality_tx,
+                name_tx,
+                unsubscribed_tx,
+                uplink_rx,
+            },
+            event_rx,
+        ))
+    }
+
+    /// The header the sharer sent during the first handshake.
+    pub fn header(&self) -> &StreamHeader {
+        &self.header
+    }
+
+    /// A handle for reporting playout statistics back to the sharer, which
+    /// uses them to adapt its bitrate. Only the latest report is sent.
+    pub fn reporter(&self) -> watch::Sender<Option<ReceptionReport>> {
+        self.report_tx.clone()
+    }
+
+    /// Ask the sharer to serve at most `max_bitrate`, or the best tier our
+    /// link sustains if `None`.
+    pub fn request_quality(&self, max_bitrate: Option<u32>) {
+        self.quality_tx.send_replace(max_bitrate);
+    }
+
+    /// Go by `name` in the sharer's roster from now on, or by none.
+    pub fn set_display_name(&self, name: Option<String>) {
+        self.name_tx.send_replace(name);
+    }
+
+    /// Have the sharer send `track` (by index), or stop sending it. Every
+    /// track is sent until unsubscribed, across reconnections too.
+    pub fn set_subscribed(&self, track: u8, subscribed: bool) {
+        self.unsubscribed_tx
+            .send_if_modified(|unsubscribed| match subscribed {
+                true => unsubscribed.remove(&track),
+                false => unsubscribed.insert(track),
+            });
+    }
+
+    /// Talk back to the sharer with the frames sent on the returned channel
+    /// (on track 0, stamped by a [`FrameClock`] of our own), until it is
+    /// dropped. Nothing is sent while the sharer has us muted.
+    pub fn start_talkback(&self) -> mpsc::Sender<AudioFrame> {
+        let (frame_tx, frame_rx) = mpsc::channel(64);
+        tokio::spawn(send_talkback(self.uplink_rx.clone(), frame_rx));
+        frame_tx
+    }
+
+    /// Disconnect from the sharer.
+    pub async fn stop(self) {
+        let _ = self.stop_tx.send(());
+        self.endpoint.close().await;
+    }
+}
+
+/// Receive from the sharer until stopped, reconnecting whenever the stream
+/// breaks.
+async fn run_listener(
+    dialer: Dialer,
+    mut link: SharerLink,
+    event_tx: mpsc::Sender<ListenEvent>,
+    mut stop_rx: tokio::sync::oneshot::Receiver<()>,
+    report_rx: watch::Receiver<Option<ReceptionReport>>,
+    quality_rx: watch::Receiver<Option<u32>>,
+    uplink_tx: watch::Sender<Uplink>,
+) {
+    'session: loop {
+        // Talking back starts unmuted on every link
+        uplink_tx.send_replace(Uplink {
+            conn: Some(link.conn.clone()),
+            muted: false,
+        });
+        // Reports, quality requests and subscriptions go back on our send
+        // half, from their own task so a message is never abandoned
+        // half-read.
+        let feedback_task = tokio::spawn(send_feedback(
+            link.send,
+            report_rx.clone(),
+            quality_rx.clone(),
+            dialer.display_name.clone(),
+            dialer.unsubscribed.clone(),
+            link.unsubscribed,
+        ));
+        // Likewise the control stream is read from a task of its own, so
+        // waking for anything else never cuts a message in half.
+        let (control_tx, mut control_rx) = mpsc::channel(8);
+        let control_task = tokio::spawn(read_control(link.recv, control_tx));
+        let mut stats_tick = tokio::time::interval(STATS_INTERVAL);
+        let mut meter = LinkMeter::default();
+        // One reader per track the sharer sends; gone with the link
+        let mut track_readers = JoinSet::new();
+        let result = loop {
+            tokio::select! {
+                _ = &mut stop_rx => break Ok(()),
+                incoming = link.conn.accept_uni() => match incoming {
+                    Ok(track_recv) => {
+                        let tracks = link.header.tracks.len();
+                        track_readers.spawn(read_track(track_recv, tracks, event_tx.clone()));
+                    }
+                    Err(e) => break Err(e.into()),
+                },
+                Some(_) = track_readers.join_next() => {}
+                _ = stats_tick.tick() => {
+                    let stats = meter.sample(PathReading::selected(&link.conn), Instant::now());
+                    if event_tx.send(ListenEvent::Link(stats)).await.is_err() {
+                        break Ok(()); // receiver dropped
+                    }
+                }
+                Some(result) = control_rx.recv() => {
+                    let event = match result {
+                        Ok(SharerMessage::NowPlaying(now_playing)) => {
+                            ListenEvent::NowPlaying(now_playing)
+                        }
+                        Ok(SharerMessage::Paused(paused)) => ListenEvent::Paused(paused),
+                        Ok(SharerMessage::TalkbackMuted(muted)) => {
+                            uplink_tx.send_modify(|uplink| uplink.muted = muted);
+                            ListenEvent::TalkbackMuted(muted)
                         }
-                        let len = u16::from_le_bytes(len_buf) as usize;
-                        let mut packet = vec![0u8; len];
-                        match recv.read_exact(&mut packet).await {
-                            Ok(()) => {
-                                if opus_tx.send(packet).await.is_err() {
-                                    break; // receiver dropped
-                                }
-                            }
-                            Err(_) => break,
+                        Ok(SharerMessage::Ending { reason }) => {
+                            info!("Sharer ended the share: {reason}");
+                            let _ = event_tx.send(ListenEvent::Ended(ListenEnd::Finished { reason })).await;
+                            break Ok(());
                         }
+                        Err(e) => break Err(e),
+                    };
+                    if event_tx.send(event).await.is_err() {
+                        break Ok(()); // receiver dropped
                     }
                 }
             }
-            drop(send); // close our end
-            info!("Listen session ended");
-        });
+        };
+        feedback_task.abort();
+        control_task.abort();
+        uplink_tx.send_replace(Uplink::default());
+        let Err(e) = result else {
+            break 'session;
+        };
+        if let Some(reason) = removal_reason(&link.conn) {
+            warn!("Sharer removed us: {reason}");
+            let _ = event_tx
+                .send(ListenEvent::Ended(ListenEnd::Refused { reason }))
+                .await;
+            break 'session;
+        }
+        warn!("Stream from sharer broke: {e:#}");
 
-        Ok((Self { endpoint, stop_tx }, opus_rx))
+        let mut attempt = 0;
+        link = loop {
+            attempt += 1;
+            if attempt > MAX_RECONNECT_ATTEMPTS {
+                warn!("Giving up on sharer after {MAX_RECONNECT_ATTEMPTS} attempts");
+                let _ = event_tx
+                    .send(ListenEvent::Ended(ListenEnd::Unreachable))
+                    .await;
+                break 'session;
+            }
+            if event_tx
+                .send(ListenEvent::Reconnecting { attempt })
+                .await
+                .is_err()
+            {
+                break 'session;
+            }
+
+            let max_bitrate = *quality_rx.borrow();
+            tokio::select! {
+                _ = &mut stop_rx => break 'session,
+                result = async {
+                    tokio::time::sleep(reconnect_delay(attempt)).await;
+                    dialer.open(max_bitrate).await
+                } => match result {
+                    Ok(link) => break link,
+                    Err(e) => match e.downcast::<Rejected>() {
+                        // Asking again won't change the sharer's mind
+                        Ok(Rejected(reason)) => {
+                            warn!("Sharer rejected reconnection: {reason}");
+                            let _ = event_tx.send(ListenEvent::Ended(ListenEnd::Refused { reason })).await;
+                            break 'session;
+                        }
+                        Err(e) => warn!("Reconnection attempt {attempt} failed: {e:#}"),
+                    },
+                },
+            }
+        };
+
+        info!("Resumed stream after {attempt} attempt(s)");
+        if event_tx
+            .send(ListenEvent::Resumed(link.header.clone()))
+            .await
+            .is_err()
+        {
+            break;
+        }
     }
+    info!("Listen session ended");
+}
 
-    /// Disconnect from the sharer.
-    pub async fn stop(self) {
-        let _ = self.stop_tx.send(());
-        self.endpoint.close().await;
+/// Forward the latest reception report, quality request, display name and
+/// subscriptions to the sharer. `unsubscribed` is what the sharer was last
+/// told about the tracks we don't want.
+async fn send_feedback(
+    mut send: SendStream,
+    mut report_rx: watch::Receiver<Option<ReceptionReport>>,
+    mut quality_rx: watch::Receiver<Option<u32>>,
+    mut name_rx: watch::Receiver<Option<String>>,
+    mut unsubscribed_rx: watch::Receiver<BTreeSet<u8>>,
+    mut unsubscribed: BTreeSet<u8>,
+) {
+    // The hello may have missed a change made while connecting
+    unsubscribed_rx.mark_changed();
+    loop {
+        let messages = tokio::select! {
+            Ok(()) = report_rx.changed() => match *report_rx.borrow_and_update() {
+                Some(report) => vec![ListenerMessage::Report(report)],
+                None => continue,
+            },
+            Ok(()) = quality_rx.changed() => vec![ListenerMessage::RequestQuality {
+                max_bitrate: *quality_rx.borrow_and_update(),
+            }],
+            Ok(()) = name_rx.changed() => {
+                vec![ListenerMessage::DisplayName(name_rx.borrow_and_update().clone())]
+            }
+            Ok(()) = unsubscribed_rx.changed() => {
+                let wanted = unsubscribed_rx.borrow_and_update().clone();
+                let messages = subscription_changes(&unsubscribed, &wanted);
+                unsubscribed = wanted;
+                messages
+            }
+            else => break,
+        };
+        for msg in &messages {
+            if protocol::write_msg(&mut send, msg).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Talk back to the sharer with the frames from `frame_rx` until they stop,
+/// on a uni-stream of the link we're on. A new link gets a new stream;
+/// frames are dropped while there is no link or the sharer muted us. The
+/// frames are in the stream's format, as track 0, numbered and stamped by
+/// our own clock.
+async fn send_talkback(
+    uplink_rx: watch::Receiver<Uplink>,
+    mut frame_rx: mpsc::Receiver<AudioFrame>,
+) {
+    // The stream we talk on, by the id of its connection. Dropping it
+    // finishes it, which tells the sharer we stopped.
+    let mut stream: Option<(usize, SendStream)> = None;
+    while let Some(frame) = frame_rx.recv().await {
+        let uplink = uplink_rx.borrow().clone();
+        let Some(conn) = uplink.conn.filter(|_| !uplink.muted) else {
+            continue;
+        };
+        if stream
+            .as_ref()
+            .is_none_or(|(id, _)| *id != conn.stable_id())
+        {
+            stream = conn
+                .open_uni()
+                .await
+                .ok()
+                .map(|send| (conn.stable_id(), send));
+        }
+        let Some((_, send)) = stream.as_mut() else {
+            continue;
+        };
+        if protocol::write_msg(send, &frame).await.is_err() {
+            stream = None;
+        }
     }
 }
 
+/// The messages that take a sharer from leaving out the `previous` tracks
+/// to leaving out the `current` ones.
+fn subscription_changes(previous: &BTreeSet<u8>, current: &BTreeSet<u8>) -> Vec<ListenerMessage> {
+    let subscribe = previous.difference(current).copied();
+    let unsubscribe = current.difference(previous).copied();
+    subscribe
+        .map(ListenerMessage::Subscribe)
+        .chain(unsubscribe.map(ListenerMessage::Unsubscribe))
+        .collect()
+}
+
+/// Forward the messages the sharer sends on the control stream, up to and
+/// including the error that ends it.
+async fn read_control(mut recv: RecvStream, control_tx: mpsc::Sender<Result<SharerMessage>>) {
+    loop {
+        let result = protocol::read_msg(&mut recv).await;
+        let failed = result.is_err();
+        if control_tx.send(result).await.is_err() || failed {
+            return;
+        }
+    }
+}
+
+/// Forward the frames of the track the sharer sends on `recv` (one of
+/// `tracks`), until it finishes the stream.
+async fn read_track(
+    mut recv: RecvStream,
+    tracks: usize,
+    event_tx: mpsc::Sender<ListenEvent>,
+) -> Result<()> {
+    let TrackStream { track } = protocol::read_msg(&mut recv).await?;
+    if usize::from(track) >= tracks {
+        bail!("Sharer sent unknown track {track}");
+    }
+    loop {
+        let frame: AudioFrame = protocol::read_msg(&mut recv).await?;
+        if frame.track != track {
+            bail!(
+                "Sharer sent a frame of track {} on track {track}",
+                frame.track
+            );
+        }
+        if event_tx.send(ListenEvent::Frame(frame)).await.is_err() {
+            return Ok(()); // receiver dropped
+        }
+    }
+}
+
+/// Why the sharer closed `conn`, if it removed us on purpose.
+fn removal_reason(conn: &Connection) -> Option<String> {
+    match conn.close_reason()? {
+        ConnectionError::ApplicationClosed(close)
+            if close.error_code.into_inner() == u64::from(CLOSE_REMOVED) =>
+        {
+            Some(String::from_utf8_lossy(&close.reason).into_owned())
+        }
+        _ => None,
+    }
+}
+
+/// How long to wait before reconnection attempt `attempt` (1-based).
+fn reconnect_delay(attempt: u32) -> Duration {
+    RECONNECT_INITIAL_DELAY
+        .saturating_mul(1 << attempt.saturating_sub(1).min(16))
+        .min(RECONNECT_MAX_DELAY)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    #[tokio::test]
-    async fn ticket_round_trip() {
-        // Create an endpoint just to get a real EndpointAddr
-        let endpoint = Endpoint::builder().bind().await.unwrap();
-        endpoint.online().await;
-        let addr = endpoint.addr();
-
-        let ticket = Ticket { addr: addr.clone() };
-
-        // Encode to string
-        let encoded = ticket.to_string_encoded().unwrap();
-        assert!(!encoded.is_empty());
+    #[test]
+    fn frame_clock_advances_one_frame_per_frame() {
+        let epoch = Instant::now();
+        let mut clock = FrameClock::new(epoch);
+        let frame = Duration::from_millis(20);
 
-        // Should be valid base64url (no padding, no +/)
-        assert!(!encoded.contains('+'));
-        assert!(!encoded.contains('/'));
-        assert!(!encoded.contains('='));
+        let (seq0, ts0) = clock.stamp(epoch + frame);
+        assert_eq!((seq0, ts0), (0, 0));
 
-        // Decode back
-        let decoded = Ticket::from_string_encoded(&encoded).unwrap();
+        // Capture jitter of a few ms doesn't move the timestamps off the grid.
+        let (seq1, ts1) = clock.stamp(epoch + frame * 2 + Duration::from_millis(3));
+        assert_eq!((seq1, ts1), (1, FRAME_SIZE as u64));
+    }
 
-        // The node ID should match
-        assert_eq!(format!("{:?}", ticket.addr), format!("{:?}", decoded.addr),);
+    #[test]
+    fn frame_clock_resyncs_after_stall() {
+        let epoch = Instant::now();
+        let mut clock = FrameClock::new(epoch);
+        clock.stamp(epoch + Duration::from_millis(20));
 
-        endpoint.close().await;
+        // Capture goes quiet for a second; the next frame is stamped at wall time.
+        let (seq, ts) = clock.stamp(epoch + Duration::from_millis(1_020));
+        assert_eq!(seq, 1);
+        assert_eq!(ts, SAMPLE_RATE as u64);
     }
 
-    #[tokio::test]
-    async fn ticket_round_trip_with_whitespace() {
-        let endpoint = Endpoint::builder().bind().await.unwrap();
-        endpoint.online().await;
+    #[test]
+    fn announcements_send_only_what_changed() {
+        let quiet = Announcements::default();
+        assert!(quiet.changes_since(&quiet).is_empty());
 
-        let ticket = Ticket {
-            addr: endpoint.addr(),
+        let now_playing = NowPlaying {
+            title: Some("Blue in Green".into()),
+            ..NowPlaying::default()
         };
-        let encoded = ticket.to_string_encoded().unwrap();
-
-        // Should tolerate leading/trailing whitespace (e.g. from copy-paste)
-        let padded = format!("  {encoded}\n");
-        let decoded = Ticket::from_string_encoded(&padded);
-        assert!(decoded.is_ok());
+        let playing = Announcements {
+            now_playing: Some(now_playing.clone()),
+            ..Announcements::default()
+        };
+        assert_eq!(
+            playing.changes_since(&quiet),
+            vec![SharerMessage::NowPlaying(Some(now_playing))]
+        );
 
-        endpoint.close().await;
+        // The goodbye comes last, after any other news
+        let ending = Announcements {
+            paused: true,
+            ending: Some("bye".into()),
+            ..playing.clone()
+        };
+        assert_eq!(
+            ending.changes_since(&playing),
+            vec![
+                SharerMessage::Paused(true),
+                SharerMessage::Ending {
+                    reason: "bye".into()
+                }
+            ]
+        );
+        assert!(ending.changes_since(&ending).is_empty());
     }
 
     #[test]
-    fn ticket_from_invalid_base64_fails() {
-        let result = Ticket::from_string_encoded("not!valid!base64!!!");
-        assert!(result.is_err());
+    fn late_listeners_hear_where_the_track_is_now() {
+        let (announce_tx, announce_rx) = watch::channel(Announcements::default());
+        let now_playing = NowPlaying {
+            position_ms: Some(10_000),
+            length_ms: Some(60_000),
+            playing: true,
+            ..NowPlaying::default()
+        };
+        set_now_playing(&announce_tx, Some(now_playing.clone()));
+        std::thread::sleep(Duration::from_millis(20));
+
+        let messages = announce_rx
+            .borrow()
+            .changes_since(&Announcements::default());
+        let [SharerMessage::NowPlaying(Some(told))] = messages.as_slice() else {
+            panic!("expected what is playing, got {messages:?}");
+        };
+        assert!(told.position_ms.unwrap() >= 10_020);
+
+        // Paused tracks stay put; playing ones stop at their end
+        let paused = NowPlaying {
+            playing: false,
+            ..now_playing.clone()
+        };
+        assert_eq!(paused.advanced(Duration::from_secs(5)), paused);
+        assert_eq!(
+            now_playing.advanced(Duration::from_secs(90)).position_ms,
+            Some(60_000)
+        );
     }
 
     #[test]
-    fn ticket_from_valid_base64_invalid_json_fails() {
-        // Valid base64url but not valid JSON
-        let encoded = data_encoding::BASE64URL_NOPAD.encode(b"not json");
-        let result = Ticket::from_string_encoded(&encoded);
-        assert!(result.is_err());
+    fn subscription_changes_are_diffed() {
+        let previous = BTreeSet::from([1, 2]);
+        let current = BTreeSet::from([2, 3]);
+        let messages = subscription_changes(&previous, &current);
+        assert!(matches!(
+            messages.as_slice(),
+            [
+                ListenerMessage::Subscribe(1),
+                ListenerMessage::Unsubscribe(3)
+            ]
+        ));
+        assert!(subscription_changes(&current, &current).is_empty());
     }
 
     #[test]
-    fn alpn_is_correct() {
-        assert_eq!(ALPN, b"/hearme/audio/1");
+    fn reconnect_backoff_doubles_up_to_cap() {
+        assert_eq!(reconnect_delay(1), Duration::from_millis(500));
+        assert_eq!(reconnect_delay(2), Duration::from_secs(1));
+        assert_eq!(reconnect_delay(4), Duration::from_secs(4));
+        assert_eq!(reconnect_delay(6), RECONNECT_MAX_DELAY);
+        assert_eq!(reconnect_delay(u32::MAX), RECONNECT_MAX_DELAY);
     }
 }